@@ -0,0 +1,166 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec
+};
+use core::{fmt, str::FromStr};
+
+#[cfg(test)]
+mod tests;
+
+/// Boot options, as `key=value` pairs. A key given on its own, like `quiet`,
+/// is a flag with an empty value.
+///
+/// Options come from the boot configuration file, then the settings saved in
+/// firmware variables, and then the command line, each overriding the last.
+#[derive(Debug, Clone, Default)]
+pub struct CommandLine {
+    options: Vec<(String, String)>
+}
+
+impl CommandLine {
+    pub fn new() -> Self {
+        CommandLine { options: Vec::new() }
+    }
+
+    /// Parses space-separated options. Values can be quoted to include spaces,
+    /// as in `init="EFI\Binaries\my init.efi"`.
+    ///
+    /// The UEFI shell puts the path it started us from before the options,
+    /// so a first word that looks like an image path, like
+    /// `fs0:\EFI\BOOT\BOOTX64.EFI`, is skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut command_line = CommandLine::new();
+        let mut chars = text.chars().peekable();
+        let mut first = true;
+
+        loop {
+            while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+                chars.next();
+            }
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let mut option = String::new();
+            let mut quoted = false;
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => quoted = !quoted,
+                    c if c.is_whitespace() && !quoted => break,
+                    c => option.push(c)
+                }
+            }
+
+            if !(first && is_image_path(&option)) {
+                command_line.push(&option);
+            }
+            first = false;
+        }
+
+        command_line
+    }
+
+    /// Parses a configuration file, with one option per line. Blank lines
+    /// and lines starting with `#` are ignored, and spaces around the `=`
+    /// are allowed.
+    pub fn parse_config(text: &str) -> Self {
+        let mut command_line = CommandLine::new();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            command_line.push(line);
+        }
+
+        command_line
+    }
+
+    fn push(&mut self, option: &str) {
+        let (key, value) = match option.find('=') {
+            Some(index) => (&option[..index], &option[index + 1..]),
+            None => (option, "")
+        };
+
+        self.set(key.trim(), value.trim());
+    }
+
+    /// Sets an option, replacing any earlier value.
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.options.retain(|(existing, _)| existing != key);
+        self.options.push((key.to_string(), value.to_string()));
+    }
+
+    /// Adds the options in `other`, which win over ours.
+    pub fn merge(&mut self, other: CommandLine) {
+        for (key, value) in other.options {
+            self.set(&key, &value);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.options.iter()
+            .find(|(existing, _)| existing == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Parses an option's value. A value that doesn't parse is logged and
+    /// treated as missing.
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Option<T> {
+        let value = self.get(key)?;
+
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                log::warn!("Ignoring boot option {}={}: can't parse the value", key, value);
+                None
+            }
+        }
+    }
+
+    /// Whether a flag was given. `flag=0`, `flag=false` and `flag=no` turn it
+    /// back off.
+    pub fn flag(&self, key: &str) -> bool {
+        match self.get(key) {
+            Some("0") | Some("false") | Some("no") | None => false,
+            Some(_) => true
+        }
+    }
+
+    /// The comma-separated items of a list option, like `drivers.disable`.
+    pub fn list(&self, key: &str) -> Vec<&str> {
+        match self.get(key) {
+            Some(value) => value.split(',').map(str::trim).filter(|item| !item.is_empty()).collect(),
+            None => Vec::new()
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.options.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+impl fmt::Display for CommandLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, (key, value)) in self.iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+
+            match (value.is_empty(), value.contains(' ')) {
+                (true, _) => write!(f, "{}", key)?,
+                (false, true) => write!(f, "{}=\"{}\"", key, value)?,
+                (false, false) => write!(f, "{}={}", key, value)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a word is the path to a UEFI image rather than an option. Options
+/// with values have an `=`, and flags have neither a path nor an extension.
+fn is_image_path(word: &str) -> bool {
+    !word.contains('=') && (word.contains('\\') || word.to_ascii_lowercase().ends_with(".efi"))
+}
@@ -0,0 +1,107 @@
+use super::*;
+
+fn options(command_line: &CommandLine) -> Vec<(&str, &str)> {
+    command_line.iter().collect()
+}
+
+#[test]
+fn options_and_flags() {
+    let command_line = CommandLine::parse("log=debug quiet  init=shell.efi");
+
+    assert_eq!(options(&command_line), vec![("log", "debug"), ("quiet", ""), ("init", "shell.efi")]);
+    assert_eq!(command_line.get("log"), Some("debug"));
+    assert_eq!(command_line.get("quiet"), Some(""));
+    assert_eq!(command_line.get("missing"), None);
+}
+
+#[test]
+fn quoted_values_keep_their_spaces() {
+    let command_line = CommandLine::parse(r#"init="EFI\Binaries\my init.efi" quiet"#);
+
+    assert_eq!(command_line.get("init"), Some(r"EFI\Binaries\my init.efi"));
+    assert!(command_line.flag("quiet"));
+}
+
+#[test]
+fn later_options_win() {
+    let command_line = CommandLine::parse("log=info log=trace");
+
+    assert_eq!(options(&command_line), vec![("log", "trace")]);
+}
+
+#[test]
+fn image_path_is_skipped() {
+    for text in &[r"fs0:\EFI\BOOT\BOOTX64.EFI quiet", "kernel.efi quiet", r"\kernel quiet"] {
+        assert_eq!(options(&CommandLine::parse(text)), vec![("quiet", "")], "{}", text);
+    }
+}
+
+#[test]
+fn only_the_first_word_can_be_an_image_path() {
+    let command_line = CommandLine::parse(r"quiet shell.efi init=EFI\init.efi");
+
+    assert_eq!(options(&command_line), vec![("quiet", ""), ("shell.efi", ""), ("init", r"EFI\init.efi")]);
+}
+
+#[test]
+fn options_that_look_like_paths_are_kept() {
+    let command_line = CommandLine::parse(r"init=EFI\init.efi");
+
+    assert_eq!(command_line.get("init"), Some(r"EFI\init.efi"));
+}
+
+#[test]
+fn config_file() {
+    let text = "# Boot options\n\nlog = debug\n  quiet\ndrivers.disable = i8042, cirrus\n";
+    let command_line = CommandLine::parse_config(text);
+
+    assert_eq!(options(&command_line), vec![("log", "debug"), ("quiet", ""), ("drivers.disable", "i8042, cirrus")]);
+}
+
+#[test]
+fn flags() {
+    let command_line = CommandLine::parse("on a=1 b=yes c=0 d=false e=no");
+
+    assert!(command_line.flag("on"));
+    assert!(command_line.flag("a"));
+    assert!(command_line.flag("b"));
+    assert!(!command_line.flag("c"));
+    assert!(!command_line.flag("d"));
+    assert!(!command_line.flag("e"));
+    assert!(!command_line.flag("missing"));
+}
+
+#[test]
+fn lists() {
+    let command_line = CommandLine::parse("drivers.disable=i8042,,cirrus, empty=");
+
+    assert_eq!(command_line.list("drivers.disable"), vec!["i8042", "cirrus"]);
+    assert!(command_line.list("empty").is_empty());
+    assert!(command_line.list("missing").is_empty());
+}
+
+#[test]
+fn merged_options_win() {
+    let mut command_line = CommandLine::parse_config("log=info\nquiet\n");
+    command_line.merge(CommandLine::parse("log=trace gdb"));
+
+    assert_eq!(command_line.get("log"), Some("trace"));
+    assert!(command_line.flag("quiet"));
+    assert!(command_line.flag("gdb"));
+}
+
+#[test]
+fn parsed_values() {
+    let command_line = CommandLine::parse("panic=30 log=loud");
+
+    assert_eq!(command_line.get_parsed::<u64>("panic"), Some(30));
+    assert_eq!(command_line.get_parsed::<u64>("log"), None);
+}
+
+#[test]
+fn display_quotes_values_with_spaces() {
+    let command_line = CommandLine::parse(r#"quiet init="my init.efi""#);
+
+    assert_eq!(command_line.to_string(), r#"quiet init="my init.efi""#);
+    assert_eq!(options(&CommandLine::parse(&command_line.to_string())), options(&command_line));
+}
@@ -1,54 +1,209 @@
 use alloc::{
+    boxed::Box,
     string::String,
+    sync::Arc,
     vec::Vec
 };
 use hashbrown::HashMap;
-use crate::Platform;
+use crate::{
+    Platform,
+    device_tree::{DeviceDescription, DeviceTree}
+};
+
+/// A driver instance. Devices are shared between the platform, the registry
+/// and whatever subsystems use them, so every method takes `&self` and drivers
+/// keep mutable state behind their own locks.
+pub trait Device<P: Platform>: Send + Sync {
+    fn poll(&self);
+
+    /// Where the device sits in the device tree.
+    fn description(&self) -> DeviceDescription<P>;
+
+    /// Called before the system sleeps, after every device below this one
+    /// has been suspended.
+    fn suspend(&self) {}
+
+    /// Called after the system wakes, before any device below this one is
+    /// resumed.
+    fn resume(&self) {}
 
-pub trait Device<P: Platform>: Clone {
-    fn poll(&mut self);
+    /// Called when the device is removed from the registry, whether it was
+    /// unplugged or the kernel is done with it. The hardware may already be
+    /// gone, so this mustn't wait on it.
+    fn shutdown(&self) {}
 
-    fn as_filesystem(&mut self) -> Option<&mut dyn Filesystem<P>> { None }
-    fn as_graphics_device(&mut self) -> Option<&mut dyn GraphicsDevice<P>> { None }
+    fn as_filesystem(&self) -> Option<&dyn Filesystem<P>> { None }
+    fn as_graphics_device(&self) -> Option<&dyn GraphicsDevice<P>> { None }
+    fn as_block_device(&self) -> Option<&dyn BlockDevice<P>> { None }
+    fn as_char_device(&self) -> Option<&dyn CharDevice<P>> { None }
+    fn as_input_device(&self) -> Option<&dyn InputDevice<P>> { None }
+    fn as_network_device(&self) -> Option<&dyn NetworkDevice<P>> { None }
+    fn as_clock_device(&self) -> Option<&dyn ClockDevice<P>> { None }
 }
 
+/// A reference to a device that can be held onto and shared.
+pub type DeviceHandle<P> = Arc<<P as Platform>::Device>;
+
+/// What subscribers to the device registry hear about.
+#[derive(Debug)]
+pub enum DeviceEvent<'a, P: Platform> {
+    Connected(P::DeviceID),
+    Disconnected(P::DeviceID),
+    Error(P::DeviceID, &'a P::Error)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Subscriber<P> = Box<dyn FnMut(&DeviceEvent<P>)>;
+
 pub struct DeviceRegistry<P: Platform> {
-    devices: HashMap<P::DeviceID, P::Device>
+    devices: HashMap<P::DeviceID, DeviceHandle<P>>,
+    tree: DeviceTree<P>,
+    subscribers: Vec<(SubscriptionId, Subscriber<P>)>,
+    next_subscription: u64
 }
 
 impl <P: Platform> DeviceRegistry<P> {
     pub fn new() -> Self {
         DeviceRegistry {
-            devices: HashMap::new()
+            devices: HashMap::new(),
+            tree: DeviceTree::new(),
+            subscribers: Vec::new(),
+            next_subscription: 0
         }
     }
 
-    pub fn insert(&mut self, id: P::DeviceID, device: P::Device) {
+    /// Adds a device, replacing (and shutting down) any existing device with
+    /// the same ID.
+    pub fn insert(&mut self, id: P::DeviceID, device: DeviceHandle<P>) {
+        if self.devices.contains_key(&id) {
+            log::warn!("Device {:?} connected twice, replacing it", id);
+            self.remove(&id);
+        }
+
+        self.tree.insert(id, device.description());
         self.devices.insert(id, device);
+
+        self.notify(&DeviceEvent::Connected(id));
     }
 
-    pub fn device(&mut self, id: &P::DeviceID) -> Option<&mut P::Device> {
-        self.devices.get_mut(id)
+    /// Shuts the device down and removes it, along with every device attached
+    /// below it. Returns `None` if there was no such device.
+    pub fn remove(&mut self, id: &P::DeviceID) -> Option<DeviceHandle<P>> {
+        let mut removed = None;
+
+        for child in self.tree.remove(id) {
+            if let Some(device) = self.devices.remove(&child) {
+                device.shutdown();
+                self.notify(&DeviceEvent::Disconnected(child));
+
+                if child == *id {
+                    removed = Some(device);
+                }
+            }
+        }
+
+        removed
     }
 
-    pub fn filesystem_devices(&mut self) -> Vec<P::DeviceID> {
-        let mut devices = Vec::new();
+    pub fn tree(&self) -> &DeviceTree<P> {
+        &self.tree
+    }
 
-        for (id, device) in self.devices.iter_mut() {
-            if let Some(_) = device.as_filesystem() {
-                devices.push(*id);
+    /// Suspends every device, children before their parents.
+    pub fn suspend(&self) {
+        for id in self.tree.bottom_up() {
+            if let Some(device) = self.devices.get(&id) {
+                device.suspend();
             }
         }
+    }
 
-        devices
+    /// Resumes every device, parents before their children.
+    pub fn resume(&self) {
+        for id in self.tree.top_down() {
+            if let Some(device) = self.devices.get(&id) {
+                device.resume();
+            }
+        }
+    }
+
+    /// Shuts down and removes every device, children before their parents.
+    pub fn shutdown(&mut self) {
+        for root in self.tree.roots().to_vec() {
+            self.remove(&root);
+        }
     }
 
-    pub fn graphics_devices(&mut self) -> Vec<P::DeviceID> {
+    /// Tells subscribers that a device reported an error. The device stays
+    /// registered; it's up to subscribers whether to remove it.
+    pub fn report_error(&mut self, id: P::DeviceID, error: &P::Error) {
+        self.notify(&DeviceEvent::Error(id, error));
+    }
+
+    /// Calls `callback` whenever a device is connected, disconnected or
+    /// reports an error, until `unsubscribe` is called.
+    pub fn subscribe<F: FnMut(&DeviceEvent<P>) + 'static>(&mut self, callback: F) -> SubscriptionId {
+        let id = SubscriptionId(self.next_subscription);
+        self.next_subscription += 1;
+
+        self.subscribers.push((id, Box::new(callback)));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) {
+        self.subscribers.retain(|(subscription, _)| *subscription != id);
+    }
+
+    fn notify(&mut self, event: &DeviceEvent<P>) {
+        for (_, subscriber) in self.subscribers.iter_mut() {
+            subscriber(event);
+        }
+    }
+
+    pub fn device(&self, id: &P::DeviceID) -> Option<DeviceHandle<P>> {
+        self.devices.get(id).cloned()
+    }
+
+    pub fn filesystem_devices(&self) -> Vec<P::DeviceID> {
+        self.devices_with(|device| device.as_filesystem().is_some())
+    }
+
+    pub fn graphics_devices(&self) -> Vec<P::DeviceID> {
+        self.devices_with(|device| device.as_graphics_device().is_some())
+    }
+
+    pub fn block_devices(&self) -> Vec<P::DeviceID> {
+        self.devices_with(|device| device.as_block_device().is_some())
+    }
+
+    pub fn char_devices(&self) -> Vec<P::DeviceID> {
+        self.devices_with(|device| device.as_char_device().is_some())
+    }
+
+    pub fn input_devices(&self) -> Vec<P::DeviceID> {
+        self.devices_with(|device| device.as_input_device().is_some())
+    }
+
+    pub fn network_devices(&self) -> Vec<P::DeviceID> {
+        self.devices_with(|device| device.as_network_device().is_some())
+    }
+
+    pub fn clock_devices(&self) -> Vec<P::DeviceID> {
+        self.devices_with(|device| device.as_clock_device().is_some())
+    }
+
+    /// The IDs of every device `has_capability` returns true for, in device
+    /// tree order so that the result doesn't change from call to call.
+    fn devices_with<F: FnMut(&P::Device) -> bool>(&self, mut has_capability: F) -> Vec<P::DeviceID> {
         let mut devices = Vec::new();
 
-        for (id, device) in self.devices.iter_mut() {
-            if let Some(_) = device.as_graphics_device() {
-                devices.push(*id);
+        for id in self.tree.top_down() {
+            if let Some(device) = self.devices.get(&id) {
+                if has_capability(device) {
+                    devices.push(id);
+                }
             }
         }
 
@@ -57,10 +212,63 @@ impl <P: Platform> DeviceRegistry<P> {
 }
 
 pub trait Filesystem<P: Platform> {
-    fn list(&mut self) -> Result<Vec<String>, P::Error>;
-    fn read(&mut self, path: &str) -> Result<Vec<u8>, P::Error>;
+    fn list(&self) -> Result<Vec<String>, P::Error>;
+    fn read(&self, path: &str) -> Result<Vec<u8>, P::Error>;
 }
 
 pub trait GraphicsDevice<P: Platform> {
-    fn clear(&mut self) -> Result<(), P::Error>;
+    fn clear(&self) -> Result<(), P::Error>;
+}
+
+/// Storage addressed in fixed-size blocks, like a disk.
+pub trait BlockDevice<P: Platform> {
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> u64;
+
+    /// Reads whole blocks starting at `block` to fill `buffer`, whose length
+    /// must be a multiple of the block size.
+    fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> Result<(), P::Error>;
+    fn write_blocks(&self, block: u64, buffer: &[u8]) -> Result<(), P::Error>;
+}
+
+/// A stream of bytes, like a serial port.
+pub trait CharDevice<P: Platform> {
+    /// Reads whatever is available without waiting, returning how many bytes
+    /// were read.
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, P::Error>;
+    fn write(&self, buffer: &[u8]) -> Result<usize, P::Error>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    /// A key went down or up. `code` is the device's own key code.
+    Key { code: u16, pressed: bool },
+    Button { button: u8, pressed: bool },
+    Motion { dx: i32, dy: i32 }
+}
+
+/// Keyboards, mice and the like.
+pub trait InputDevice<P: Platform> {
+    /// The oldest event the device has seen that hasn't been taken yet.
+    fn next_event(&self) -> Option<InputEvent>;
+}
+
+pub trait NetworkDevice<P: Platform> {
+    fn mac_address(&self) -> [u8; 6];
+    fn link_up(&self) -> bool;
+
+    fn send(&self, frame: &[u8]) -> Result<(), P::Error>;
+
+    /// Copies the next received frame into `buffer`, returning its length,
+    /// or `None` if nothing has arrived.
+    fn receive(&self, buffer: &mut [u8]) -> Result<Option<usize>, P::Error>;
+}
+
+/// A source of regular ticks.
+pub trait ClockDevice<P: Platform> {
+    /// Ticks since the clock started.
+    fn ticks(&self) -> u64;
+
+    /// Ticks per second.
+    fn frequency(&self) -> u64;
 }
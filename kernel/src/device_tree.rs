@@ -0,0 +1,218 @@
+use alloc::{
+    format,
+    string::String,
+    vec::Vec
+};
+use core::fmt;
+use hashbrown::HashMap;
+
+use crate::Platform;
+
+/// Broadly what a device does.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DeviceClass {
+    Bus,
+    Display,
+    Input,
+    Storage,
+    Network,
+    Clock,
+    Other
+}
+
+/// Hardware resources a device decodes or raises.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Resource {
+    Memory { base: u64, size: u64, prefetchable: bool },
+    IoPort { base: u16, size: u16 },
+    Irq(u32)
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Resource::Memory { base, size, prefetchable } => {
+                write!(f, "mem {:#x}-{:#x}", base, base + size.saturating_sub(1))?;
+                if *prefetchable {
+                    write!(f, " pref")?;
+                }
+                Ok(())
+            },
+            Resource::IoPort { base, size } => write!(f, "io {:#x}-{:#x}", base, base + size.saturating_sub(1)),
+            Resource::Irq(irq) => write!(f, "irq {}", irq)
+        }
+    }
+}
+
+/// Where a device sits in the tree, as the platform describes it.
+#[derive(Debug, Clone)]
+pub struct DeviceDescription<P: Platform> {
+    /// The bus the device is attached to, or `None` for a root device.
+    pub parent: Option<P::DeviceID>,
+
+    /// The device's own path component, like `pci0` or `00:02.0`.
+    pub name: String,
+    pub class: DeviceClass,
+
+    /// The driver bound to the device, or `None` if nothing claimed it.
+    pub driver: Option<&'static str>,
+    pub resources: Vec<Resource>
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceNode<P: Platform> {
+    pub id: P::DeviceID,
+    pub parent: Option<P::DeviceID>,
+
+    /// The names of the device and all its ancestors, like `pci0/00:02.0`.
+    pub path: String,
+    pub class: DeviceClass,
+    pub driver: Option<&'static str>,
+    pub resources: Vec<Resource>,
+    pub children: Vec<P::DeviceID>
+}
+
+/// How devices are connected to each other. A device's parent is always
+/// connected before it and disconnected after it.
+pub struct DeviceTree<P: Platform> {
+    nodes: HashMap<P::DeviceID, DeviceNode<P>>,
+    roots: Vec<P::DeviceID>
+}
+
+impl <P: Platform> DeviceTree<P> {
+    pub fn new() -> Self {
+        DeviceTree {
+            nodes: HashMap::new(),
+            roots: Vec::new()
+        }
+    }
+
+    /// Adds a device under its parent. A device whose parent isn't in the
+    /// tree becomes a root.
+    pub fn insert(&mut self, id: P::DeviceID, description: DeviceDescription<P>) {
+        let parent = match description.parent {
+            Some(parent) if self.nodes.contains_key(&parent) => Some(parent),
+            Some(parent) => {
+                log::warn!("Device {:?} connected before its parent {:?}", id, parent);
+                None
+            },
+            None => None
+        };
+
+        let path = match parent {
+            Some(parent) => format!("{}/{}", self.nodes[&parent].path, description.name),
+            None => description.name
+        };
+
+        match parent {
+            Some(parent) => self.nodes.get_mut(&parent).unwrap().children.push(id),
+            None => self.roots.push(id)
+        }
+
+        self.nodes.insert(id, DeviceNode {
+            id,
+            parent,
+            path,
+            class: description.class,
+            driver: description.driver,
+            resources: description.resources,
+            children: Vec::new()
+        });
+    }
+
+    /// Removes a device and everything below it, returning the removed IDs
+    /// children first.
+    pub fn remove(&mut self, id: &P::DeviceID) -> Vec<P::DeviceID> {
+        let removed = self.subtree_bottom_up(id);
+
+        if let Some(node) = self.nodes.get(id) {
+            match node.parent {
+                Some(parent) => self.nodes.get_mut(&parent).unwrap().children.retain(|child| child != id),
+                None => self.roots.retain(|root| root != id)
+            }
+        }
+
+        for id in removed.iter() {
+            self.nodes.remove(id);
+        }
+
+        removed
+    }
+
+    pub fn node(&self, id: &P::DeviceID) -> Option<&DeviceNode<P>> {
+        self.nodes.get(id)
+    }
+
+    pub fn find(&self, path: &str) -> Option<P::DeviceID> {
+        self.nodes.values()
+            .find(|node| node.path == path)
+            .map(|node| node.id)
+    }
+
+    pub fn roots(&self) -> &[P::DeviceID] {
+        &self.roots
+    }
+
+    /// Every device, each one after its parent. Resume and start devices in
+    /// this order; suspend and shut them down in reverse.
+    pub fn top_down(&self) -> Vec<P::DeviceID> {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack: Vec<P::DeviceID> = self.roots.iter().rev().cloned().collect();
+
+        while let Some(id) = stack.pop() {
+            order.push(id);
+            stack.extend(self.nodes[&id].children.iter().rev().cloned());
+        }
+
+        order
+    }
+
+    /// Every device, each one before its parent.
+    pub fn bottom_up(&self) -> Vec<P::DeviceID> {
+        let mut order = self.top_down();
+        order.reverse();
+        order
+    }
+
+    fn subtree_bottom_up(&self, id: &P::DeviceID) -> Vec<P::DeviceID> {
+        let mut order = Vec::new();
+        if !self.nodes.contains_key(id) {
+            return order;
+        }
+
+        let mut stack = alloc::vec![*id];
+        while let Some(id) = stack.pop() {
+            order.push(id);
+            stack.extend(self.nodes[&id].children.iter().cloned());
+        }
+
+        order.reverse();
+        order
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+impl <P: Platform> fmt::Display for DeviceTree<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for id in self.top_down() {
+            let node = &self.nodes[&id];
+            let depth = node.path.matches('/').count();
+
+            write!(f, "{:indent$}{} ({:?}, {:?}, {})", "", node.path, id, node.class,
+                node.driver.unwrap_or("unbound"), indent = depth * 2)?;
+            for resource in node.resources.iter() {
+                write!(f, ", {}", resource)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,474 @@
+//! The kernel log buffer, and which records get logged at all.
+//!
+//! Records the filters let through are handed to the sinks in `log_sink`.
+//! One of them, `RING_SINK`, keeps each record in a ring of bytes along with
+//! when it was logged, its level, the module it came from and the CPU it was
+//! logged on, so it can be read back later with `snapshot`. Once the ring is
+//! full the oldest records are dropped to make room.
+//!
+//! Which records get through is decided per module. A filter covers a module
+//! and everything under it, the longest matching filter wins, and modules
+//! without one get the default level. The `log` boot option sets the
+//! default, and `log.filter` adds filters, as in
+//! `log.filter=platform_x86_64::memory=warn,kernel::driver=trace`.
+//!
+//! The allocator logs too, so nothing here allocates while holding a lock.
+//! Records logged where the sinks can't safely be called, like interrupt
+//! handlers, are staged by `log_staging` and passed on later.
+
+use alloc::{
+    string::String,
+    vec::Vec
+};
+use core::{
+    fmt::{self, Write},
+    str,
+    sync::atomic::{AtomicU64, Ordering}
+};
+use log::{Level, LevelFilter, Metadata};
+
+use crate::{
+    CommandLine,
+    interrupts,
+    log_sink::{self, LogSink},
+    log_staging,
+    sync::{IrqSpinLock, LockClass}
+};
+
+const RING_SIZE: usize = 64 * 1024;
+
+/// Longer messages are cut off.
+const MAX_MESSAGE: usize = 512;
+
+/// Longer module paths are cut off, in the ring and in filters.
+const MAX_MODULE: usize = 64;
+
+const MAX_FILTERS: usize = 16;
+
+/// Sequence number, timestamp, CPU, level, module length and message length.
+const HEADER_SIZE: usize = 8 + 8 + 2 + 1 + 1 + 2;
+
+/// Neither lock is held while taking another, so their ranks only need to
+/// come after the allocator's, which logs while holding its lock.
+static RING_CLASS: LockClass = LockClass::new("log buffer", 95);
+static FILTERS_CLASS: LockClass = LockClass::new("log filters", 95);
+
+static RING: IrqSpinLock<Ring> = IrqSpinLock::ranked(Ring {
+    bytes: [0; RING_SIZE],
+    head: 0,
+    used: 0,
+    dropped: 0
+}, &RING_CLASS);
+
+/// The sequence number of the next record.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+pub use crate::log_staging::{DeferGuard, defer};
+
+static FILTERS: IrqSpinLock<Filters> = IrqSpinLock::ranked(Filters {
+    default: LevelFilter::Trace,
+    filters: [None; MAX_FILTERS]
+}, &FILTERS_CLASS);
+
+struct Ring {
+    bytes: [u8; RING_SIZE],
+
+    /// Where the oldest record starts.
+    head: usize,
+
+    /// How many bytes of records follow `head`, wrapping around.
+    used: usize,
+
+    /// How many records have been dropped to make room.
+    dropped: u64
+}
+
+impl Ring {
+    fn push(&mut self, record: &Record) {
+        let module = &record.module.as_bytes()[..floor_char_boundary(record.module, MAX_MODULE)];
+        let message = &record.message.as_bytes()[..floor_char_boundary(record.message, MAX_MESSAGE)];
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[..8].copy_from_slice(&record.sequence.to_le_bytes());
+        header[8..16].copy_from_slice(&record.timestamp.to_le_bytes());
+        header[16..18].copy_from_slice(&(record.cpu as u16).to_le_bytes());
+        header[18] = record.level as u8;
+        header[19] = module.len() as u8;
+        header[20..22].copy_from_slice(&(message.len() as u16).to_le_bytes());
+
+        let size = HEADER_SIZE + module.len() + message.len();
+        while RING_SIZE - self.used < size {
+            self.drop_oldest();
+        }
+
+        let mut offset = (self.head + self.used) % RING_SIZE;
+        for part in [&header[..], module, message].iter() {
+            self.copy_in(offset, part);
+            offset = (offset + part.len()) % RING_SIZE;
+        }
+
+        self.used += size;
+    }
+
+    fn drop_oldest(&mut self) {
+        let mut header = [0u8; HEADER_SIZE];
+        self.copy_out(self.head, &mut header);
+
+        let size = HEADER_SIZE + header[19] as usize + u16::from_le_bytes([header[20], header[21]]) as usize;
+        self.head = (self.head + size) % RING_SIZE;
+        self.used -= size;
+        self.dropped += 1;
+    }
+
+    fn copy_in(&mut self, offset: usize, data: &[u8]) {
+        let first = data.len().min(RING_SIZE - offset);
+        self.bytes[offset..offset + first].copy_from_slice(&data[..first]);
+        self.bytes[..data.len() - first].copy_from_slice(&data[first..]);
+    }
+
+    fn copy_out(&self, offset: usize, data: &mut [u8]) {
+        let first = data.len().min(RING_SIZE - offset);
+        let length = data.len();
+        data[..first].copy_from_slice(&self.bytes[offset..offset + first]);
+        data[first..].copy_from_slice(&self.bytes[..length - first]);
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Filter {
+    module: [u8; MAX_MODULE],
+    length: usize,
+    level: LevelFilter
+}
+
+impl Filter {
+    fn module(&self) -> &str {
+        str::from_utf8(&self.module[..self.length]).unwrap_or("")
+    }
+
+    /// Whether the filter covers `module`: the module itself, or one of
+    /// its submodules.
+    fn covers(&self, module: &str) -> bool {
+        let prefix = self.module();
+        module.starts_with(prefix) && (module.len() == prefix.len() || module[prefix.len()..].starts_with("::"))
+    }
+}
+
+struct Filters {
+    default: LevelFilter,
+    filters: [Option<Filter>; MAX_FILTERS]
+}
+
+impl Filters {
+    fn level(&self, module: &str) -> LevelFilter {
+        self.filters.iter()
+            .filter_map(|filter| filter.as_ref())
+            .filter(|filter| filter.covers(module))
+            .max_by_key(|filter| filter.length)
+            .map(|filter| filter.level)
+            .unwrap_or(self.default)
+    }
+
+    /// The `log` crate drops anything above its maximum level before it
+    /// reaches us, so that has to let through the most verbose filter.
+    fn update_max_level(&self) {
+        let max = self.filters.iter()
+            .filter_map(|filter| filter.as_ref())
+            .map(|filter| filter.level)
+            .fold(self.default, |max, level| max.max(level));
+
+        log::set_max_level(max);
+    }
+}
+
+/// Whether the filters let a record through. If they're being changed,
+/// which only happens if this interrupted the change, it lets it through.
+pub fn enabled(metadata: &Metadata) -> bool {
+    match FILTERS.try_lock() {
+        Some(filters) => metadata.level() <= filters.level(metadata.target()),
+        None => true
+    }
+}
+
+/// Hands a record the filters let through to the sinks, along with any
+/// staged before it, or stages it if the sinks can't be called right now.
+/// `timestamp` is in platform timestamp units.
+pub fn record(record: &log::Record, timestamp: u64) {
+    let sequence = next_sequence();
+
+    let sent = log_staging::with_sinks(|| {
+        log_staging::flush(log_sink::dispatch);
+
+        let mut buffer = [0; MAX_MESSAGE];
+        let mut message = Truncated::new(&mut buffer);
+        let _ = write!(message, "{}", record.args());
+
+        log_sink::dispatch(&Record {
+            sequence,
+            timestamp,
+            cpu: interrupts::current_cpu(),
+            level: record.level(),
+            module: record.module_path().unwrap_or(record.target()),
+            message: message.as_str()
+        });
+    });
+
+    if !sent {
+        log_staging::stage(record, sequence, timestamp);
+    }
+}
+
+/// Hands staged records to the sinks. The kernel's event loop calls this so
+/// they don't wait long for the next record.
+pub fn flush() {
+    log_staging::with_sinks(|| log_staging::flush(log_sink::dispatch));
+}
+
+pub(crate) fn next_sequence() -> u64 {
+    SEQUENCE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Keeps records in the log buffer.
+pub(crate) struct RingSink;
+
+pub(crate) static RING_SINK: RingSink = RingSink;
+
+impl LogSink for RingSink {
+    fn write(&self, record: &Record, _text: &str) {
+        RING.lock().push(record);
+    }
+}
+
+/// Sets the level for modules without a filter of their own.
+pub fn set_default_level(level: LevelFilter) {
+    let mut filters = FILTERS.lock();
+    filters.default = level;
+    filters.update_max_level();
+}
+
+/// Sets the level for `module` and everything under it. Returns false if
+/// there's no room for another filter.
+pub fn set_filter(module: &str, level: LevelFilter) -> bool {
+    let length = floor_char_boundary(module, MAX_MODULE);
+    let mut filter = Filter { module: [0; MAX_MODULE], length, level };
+    filter.module[..length].copy_from_slice(&module.as_bytes()[..length]);
+
+    let mut filters = FILTERS.lock();
+
+    let slot = filters.filters.iter()
+        .position(|existing| existing.map(|existing| existing.module() == filter.module()).unwrap_or(false))
+        .or_else(|| filters.filters.iter().position(Option::is_none));
+
+    match slot {
+        Some(slot) => {
+            filters.filters[slot] = Some(filter);
+            filters.update_max_level();
+            true
+        },
+        None => false
+    }
+}
+
+/// Removes the filter for `module`, so it gets the default level again.
+pub fn clear_filter(module: &str) {
+    let mut filters = FILTERS.lock();
+
+    for slot in filters.filters.iter_mut() {
+        if slot.map(|filter| filter.module() == module).unwrap_or(false) {
+            *slot = None;
+        }
+    }
+    filters.update_max_level();
+}
+
+/// The default level, and the level of each module with a filter.
+pub fn filters() -> (LevelFilter, Vec<(String, LevelFilter)>) {
+    let (default, copied) = {
+        let filters = FILTERS.lock();
+        (filters.default, filters.filters)
+    };
+
+    let filters = copied.iter()
+        .filter_map(|filter| filter.as_ref())
+        .map(|filter| (filter.module().into(), filter.level))
+        .collect();
+
+    (default, filters)
+}
+
+/// Applies the `log` and `log.filter` boot options, and the sinks' own.
+pub fn configure(command_line: &'static CommandLine) {
+    if let Some(level) = command_line.get_parsed::<LevelFilter>("log") {
+        set_default_level(level);
+    }
+
+    for item in command_line.list("log.filter") {
+        let mut parts = item.splitn(2, '=');
+        let module = parts.next().unwrap_or("").trim();
+
+        match parts.next().map(str::trim).and_then(|level| level.parse::<LevelFilter>().ok()) {
+            Some(level) if !module.is_empty() => {
+                if !set_filter(module, level) {
+                    log::warn!("Too many log filters, ignoring {}", item);
+                }
+            },
+            _ => log::warn!("Ignoring log filter {}: expected module=level", item)
+        }
+    }
+
+    log_sink::configure(command_line);
+}
+
+/// A copy of the log buffer, taken by `snapshot`.
+pub struct Snapshot {
+    bytes: Vec<u8>,
+    dropped: u64
+}
+
+/// Copies the log buffer out, after flushing anything staged.
+pub fn snapshot() -> Snapshot {
+    flush();
+
+    let mut bytes = Vec::new();
+    bytes.resize(RING_SIZE, 0);
+
+    let ring = RING.lock();
+    let used = ring.used;
+    ring.copy_out(ring.head, &mut bytes[..used]);
+    let dropped = ring.dropped;
+    drop(ring);
+
+    bytes.truncate(used);
+    Snapshot { bytes, dropped }
+}
+
+impl Snapshot {
+    /// How many records were dropped to make room before this was taken.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The records, oldest first.
+    pub fn records(&self) -> Records {
+        Records { bytes: &self.bytes }
+    }
+}
+
+pub struct Records<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Record<'a>> {
+        if self.bytes.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let (header, rest) = self.bytes.split_at(HEADER_SIZE);
+        let module_length = header[19] as usize;
+        let message_length = u16::from_le_bytes([header[20], header[21]]) as usize;
+
+        let (module, rest) = rest.split_at(module_length);
+        let (message, rest) = rest.split_at(message_length);
+        self.bytes = rest;
+
+        let mut sequence = [0u8; 8];
+        sequence.copy_from_slice(&header[..8]);
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&header[8..16]);
+
+        Some(Record {
+            sequence: u64::from_le_bytes(sequence),
+            timestamp: u64::from_le_bytes(timestamp),
+            cpu: u16::from_le_bytes([header[16], header[17]]) as usize,
+            level: level_from_u8(header[18]),
+            module: str::from_utf8(module).unwrap_or("?"),
+            message: str::from_utf8(message).unwrap_or("?")
+        })
+    }
+}
+
+/// A record, as handed to sinks and read back from a `Snapshot`.
+#[derive(Debug, Clone)]
+pub struct Record<'a> {
+    /// Counts up from zero at boot. Gaps in the log buffer are records it
+    /// dropped, or that were above its sink's level.
+    pub sequence: u64,
+
+    /// In platform timestamp units.
+    pub timestamp: u64,
+    pub cpu: usize,
+    pub level: Level,
+    pub module: &'a str,
+    pub message: &'a str
+}
+
+impl<'a> Record<'a> {
+    /// The timestamp in microseconds, given how many timestamp units make a
+    /// second. Records logged before the platform knew that read as zero.
+    pub fn micros(&self, frequency: u64) -> u64 {
+        if frequency == 0 {
+            0
+        } else {
+            (self.timestamp as u128 * 1_000_000 / frequency as u128) as u64
+        }
+    }
+}
+
+fn level_from_u8(level: u8) -> Level {
+    match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace
+    }
+}
+
+/// The longest prefix of `text` no longer than `max` bytes that doesn't
+/// split a character.
+pub(crate) fn floor_char_boundary(text: &str, max: usize) -> usize {
+    let mut index = text.len().min(max);
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Formats into a borrowed buffer, cutting off whatever doesn't fit without
+/// splitting a character. The logger uses it wherever it mustn't allocate.
+pub(crate) struct Truncated<'a> {
+    bytes: &'a mut [u8],
+    length: usize
+}
+
+impl<'a> Truncated<'a> {
+    pub(crate) fn new(bytes: &'a mut [u8]) -> Self {
+        Truncated { bytes, length: 0 }
+    }
+
+    /// How many bytes have been written.
+    pub(crate) fn length(&self) -> usize {
+        self.length
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        str::from_utf8(&self.bytes[..self.length]).unwrap_or("")
+    }
+
+    /// Starts again from an empty buffer.
+    pub(crate) fn clear(&mut self) {
+        self.length = 0;
+    }
+}
+
+impl<'a> Write for Truncated<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = floor_char_boundary(s, self.bytes.len() - self.length);
+        self.bytes[self.length..self.length + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.length += count;
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! Portable drivers.
+//!
+//! These live in the kernel crate and reach the hardware only through a
+//! `Hal`, so the same driver works on every platform. The platform reads each
+//! PCI device's header, offers it to the registered drivers whose match
+//! tables accept it, and connects whatever `Device` the first successful
+//! probe returns.
+
+use alloc::{
+    boxed::Box,
+    vec::Vec
+};
+
+use crate::{
+    Device,
+    Platform,
+    command_line::CommandLine,
+    device_tree::{DeviceClass, Resource},
+    hal::{Hal, PciConfig, PciLocation}
+};
+
+const CONFIG_VENDOR_ID: u16 = 0x00;
+const CONFIG_DEVICE_ID: u16 = 0x02;
+const CONFIG_REVISION: u16 = 0x08;
+const CONFIG_PROG_IF: u16 = 0x09;
+const CONFIG_SUBCLASS: u16 = 0x0a;
+const CONFIG_CLASS: u16 = 0x0b;
+
+/// What a device says about itself in its configuration header.
+#[derive(Debug, Clone)]
+pub struct PciDeviceInfo {
+    pub location: PciLocation,
+    pub vendor: u16,
+    pub device: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8
+}
+
+impl PciDeviceInfo {
+    pub fn read(pci: &dyn PciConfig, location: PciLocation) -> Self {
+        PciDeviceInfo {
+            vendor: pci.read_u16(location, CONFIG_VENDOR_ID),
+            device: pci.read_u16(location, CONFIG_DEVICE_ID),
+            class: pci.read_u8(location, CONFIG_CLASS),
+            subclass: pci.read_u8(location, CONFIG_SUBCLASS),
+            prog_if: pci.read_u8(location, CONFIG_PROG_IF),
+            revision: pci.read_u8(location, CONFIG_REVISION),
+            location
+        }
+    }
+
+    /// Roughly what the class code means, for the device tree.
+    pub fn device_class(&self) -> DeviceClass {
+        match (self.class, self.subclass) {
+            (0x01, _) => DeviceClass::Storage,
+            (0x02, _) => DeviceClass::Network,
+            (0x03, _) => DeviceClass::Display,
+            (0x06, _) => DeviceClass::Bus,
+            (0x08, 0x02) => DeviceClass::Clock,
+            (0x09, _) => DeviceClass::Input,
+            (0x0c, 0x03) => DeviceClass::Bus,
+            _ => DeviceClass::Other
+        }
+    }
+}
+
+/// One entry in a driver's match table. `None` fields match anything.
+#[derive(Debug, Copy, Clone)]
+pub struct PciMatch {
+    pub vendor: Option<u16>,
+    pub device: Option<u16>,
+    pub class: Option<u8>,
+    pub subclass: Option<u8>,
+    pub prog_if: Option<u8>
+}
+
+impl PciMatch {
+    /// Matches one vendor and device ID.
+    pub const fn device(vendor: u16, device: u16) -> Self {
+        PciMatch { vendor: Some(vendor), device: Some(device), class: None, subclass: None, prog_if: None }
+    }
+
+    /// Matches any device with this class code, and programming interface if
+    /// given.
+    pub const fn class(class: u8, subclass: u8, prog_if: Option<u8>) -> Self {
+        PciMatch { vendor: None, device: None, class: Some(class), subclass: Some(subclass), prog_if }
+    }
+
+    pub fn matches(&self, info: &PciDeviceInfo) -> bool {
+        fn field<T: PartialEq>(expected: Option<T>, actual: T) -> bool {
+            expected.map(|expected| expected == actual).unwrap_or(true)
+        }
+
+        field(self.vendor, info.vendor)
+            && field(self.device, info.device)
+            && field(self.class, info.class)
+            && field(self.subclass, info.subclass)
+            && field(self.prog_if, info.prog_if)
+    }
+}
+
+/// What a driver is given to set a device up with.
+pub struct ProbeContext<P: Platform> {
+    /// The ID the device will be connected under, for routing its IRQ.
+    pub id: P::DeviceID,
+
+    /// The bus the device is on, to use as its parent in the device tree.
+    pub parent: Option<P::DeviceID>,
+
+    /// The device's BARs and interrupt line.
+    pub resources: Vec<Resource>,
+    pub hal: Hal<P>,
+
+    /// The boot options, for the driver's own settings.
+    pub command_line: &'static CommandLine
+}
+
+pub struct PciDriver<P: Platform> {
+    pub name: &'static str,
+    pub matches: &'static [PciMatch],
+
+    /// Sets the device up. An error leaves it unbound, and lets later drivers
+    /// try it.
+    pub probe: fn(&PciDeviceInfo, ProbeContext<P>) -> Result<Box<dyn Device<P>>, P::Error>
+}
+
+impl <P: Platform> PciDriver<P> {
+    pub fn matches(&self, info: &PciDeviceInfo) -> bool {
+        self.matches.iter().any(|entry| entry.matches(info))
+    }
+}
+
+impl <P: Platform> Clone for PciDriver<P> {
+    fn clone(&self) -> Self {
+        PciDriver { name: self.name, matches: self.matches, probe: self.probe }
+    }
+}
+
+impl <P: Platform> Copy for PciDriver<P> {}
@@ -0,0 +1,49 @@
+use core::fmt;
+
+/// What went wrong, in terms the kernel can act on without knowing the
+/// platform.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Talking to the hardware or firmware failed.
+    Io,
+
+    /// A device didn't respond in time. Trying again may work.
+    Timeout,
+    NotFound,
+    Unsupported,
+    OutOfMemory,
+
+    /// A binary couldn't be parsed or loaded.
+    InvalidExecutable,
+
+    /// A device reported a fault. It's still connected, and may recover.
+    DeviceFault,
+    Other
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Io => "I/O error",
+            ErrorKind::Timeout => "timed out",
+            ErrorKind::NotFound => "not found",
+            ErrorKind::Unsupported => "unsupported",
+            ErrorKind::OutOfMemory => "out of memory",
+            ErrorKind::InvalidExecutable => "invalid executable",
+            ErrorKind::DeviceFault => "device fault",
+            ErrorKind::Other => "other error"
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What `Platform::Error` has to support. The kernel sorts platform errors
+/// by `kind`, and raises its own errors by converting from an `ErrorKind`.
+pub trait PlatformError: fmt::Debug + fmt::Display + Clone + From<ErrorKind> {
+    fn kind(&self) -> ErrorKind;
+}
@@ -0,0 +1,169 @@
+//! A single-threaded executor for kernel tasks written as `async fn`s.
+//!
+//! Tasks wait on the `Reactor`, which the kernel's event loop feeds with
+//! platform events. An interrupt pushes an event, the event loop hands it to
+//! the reactor, the reactor wakes whichever tasks were waiting for it, and
+//! the executor polls them on its next run.
+
+mod reactor;
+mod waker;
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker}
+};
+use spin::Mutex;
+
+use self::waker::TaskWaker;
+
+pub use self::reactor::{DeviceReady, Reactor, Sleep};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+type ReadyQueue = Mutex<VecDeque<TaskId>>;
+type BoxedFuture = Pin<Box<dyn Future<Output = ()>>>;
+
+struct Task {
+    future: BoxedFuture,
+    waker: Arc<TaskWaker>
+}
+
+/// State shared between the executor and its spawners.
+struct Shared {
+    next_id: AtomicU64,
+    ready: Arc<ReadyQueue>,
+
+    /// Tasks spawned since the executor last ran. They can't go straight into
+    /// the task table because a running task may be the one spawning them.
+    spawned: Mutex<Vec<(TaskId, BoxedFuture)>>
+}
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    shared: Arc<Shared>
+}
+
+/// Spawns tasks onto an `Executor` from anywhere, including other tasks.
+#[derive(Clone)]
+pub struct Spawner {
+    shared: Arc<Shared>
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            shared: Arc::new(Shared {
+                next_id: AtomicU64::new(0),
+                ready: Arc::new(Mutex::new(VecDeque::new())),
+                spawned: Mutex::new(Vec::new())
+            })
+        }
+    }
+
+    pub fn spawner(&self) -> Spawner {
+        Spawner { shared: self.shared.clone() }
+    }
+
+    pub fn spawn<F: Future<Output = ()> + 'static>(&self, future: F) -> TaskId {
+        self.spawner().spawn(future)
+    }
+
+    /// True if any task is waiting to be polled.
+    pub fn has_ready(&self) -> bool {
+        !self.shared.ready.lock().is_empty() || !self.shared.spawned.lock().is_empty()
+    }
+
+    pub fn task_count(&self) -> usize {
+        self.tasks.len() + self.shared.spawned.lock().len()
+    }
+
+    /// Polls every task that's ready right now, once. Tasks woken while this
+    /// runs are left for the next call so that a task that keeps waking itself
+    /// can't starve the event loop. Returns the number of tasks polled.
+    pub fn run_ready(&mut self) -> usize {
+        self.admit_spawned();
+
+        let ready: Vec<TaskId> = self.shared.ready.lock().drain(..).collect();
+
+        for id in ready.iter() {
+            let finished = match self.tasks.get_mut(id) {
+                Some(task) => {
+                    task.waker.dequeued();
+
+                    let waker = waker::waker(task.waker.clone());
+                    let mut context = Context::from_waker(&waker);
+
+                    task.future.as_mut().poll(&mut context).is_ready()
+                },
+
+                // Woken after it finished.
+                None => false
+            };
+
+            if finished {
+                self.tasks.remove(id);
+            }
+        }
+
+        ready.len()
+    }
+
+    fn admit_spawned(&mut self) {
+        let spawned: Vec<(TaskId, BoxedFuture)> = self.shared.spawned.lock().drain(..).collect();
+
+        for (id, future) in spawned {
+            let waker = TaskWaker::new(id, self.shared.ready.clone());
+            waker.wake();
+
+            self.tasks.insert(id, Task { future, waker });
+        }
+    }
+}
+
+impl Spawner {
+    pub fn spawn<F: Future<Output = ()> + 'static>(&self, future: F) -> TaskId {
+        let id = TaskId(self.shared.next_id.fetch_add(1, Ordering::Relaxed));
+        self.shared.spawned.lock().push((id, Box::pin(future)));
+        id
+    }
+}
+
+/// Lets other tasks run before continuing.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+pub struct YieldNow {
+    yielded: bool
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            context.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Wakes every waker in `wakers`.
+fn wake_all<I: IntoIterator<Item = Waker>>(wakers: I) {
+    for waker in wakers {
+        waker.wake();
+    }
+}
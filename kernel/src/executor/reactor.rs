@@ -0,0 +1,198 @@
+use alloc::{
+    collections::{BTreeMap, btree_map::Entry},
+    sync::Arc
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker}
+};
+use hashbrown::HashMap;
+use spin::Mutex;
+
+use crate::Platform;
+use super::wake_all;
+
+#[cfg(test)]
+mod tests;
+
+/// Identifies one `Sleep` or `DeviceReady`, so that each keeps a single
+/// waker however often it's polled, and can take it back when dropped.
+type WaiterId = u64;
+/// Turns platform events into task wake-ups.
+///
+/// The kernel's event loop calls `clock_ticked` and `device_ready` as it
+/// dispatches events. Tasks wait on the futures returned by `sleep` and
+/// `device`, which resolve once the matching event has been seen.
+pub struct Reactor<P: Platform> {
+    inner: Arc<Mutex<ReactorState<P>>>
+}
+
+struct ReactorState<P: Platform> {
+    ticks: u64,
+    next_waiter: WaiterId,
+
+    /// Sleeping tasks keyed by the tick they should wake on.
+    timers: BTreeMap<(u64, WaiterId), Waker>,
+
+    /// How many times each device has signalled it's ready, so a future can
+    /// tell whether a signal arrived since it was created.
+    device_generations: HashMap<P::DeviceID, u64>,
+    device_waiters: HashMap<P::DeviceID, BTreeMap<WaiterId, Waker>>
+}
+
+impl <P: Platform> ReactorState<P> {
+    fn next_waiter(&mut self) -> WaiterId {
+        self.next_waiter += 1;
+        self.next_waiter
+    }
+}
+
+impl <P: Platform> Clone for Reactor<P> {
+    fn clone(&self) -> Self {
+        Reactor { inner: self.inner.clone() }
+    }
+}
+
+impl <P: Platform> Reactor<P> {
+    pub fn new() -> Self {
+        Reactor {
+            inner: Arc::new(Mutex::new(ReactorState {
+                ticks: 0,
+                next_waiter: 0,
+                timers: BTreeMap::new(),
+                device_generations: HashMap::new(),
+                device_waiters: HashMap::new()
+            }))
+        }
+    }
+
+    /// Clock ticks seen so far.
+    pub fn ticks(&self) -> u64 {
+        self.inner.lock().ticks
+    }
+
+    pub fn clock_ticked(&self) {
+        let mut state = self.inner.lock();
+        state.ticks += 1;
+
+        let now = state.ticks;
+        let later = state.timers.split_off(&(now + 1, 0));
+        let expired = core::mem::replace(&mut state.timers, later);
+
+        wake_all(expired.into_iter().map(|(_, waker)| waker));
+    }
+
+    /// Wakes everything waiting on the device. Futures that are still
+    /// pending when polled again wait afresh.
+    pub fn device_ready(&self, id: P::DeviceID) {
+        let mut state = self.inner.lock();
+        *state.device_generations.entry(id).or_insert(0) += 1;
+
+        if let Some(waiters) = state.device_waiters.remove(&id) {
+            wake_all(waiters.into_iter().map(|(_, waker)| waker));
+        }
+    }
+
+    /// Wakes anything waiting on a device that's gone away. They'll find it
+    /// missing from the registry when they go to use it.
+    pub fn device_removed(&self, id: P::DeviceID) {
+        self.device_ready(id);
+    }
+
+    /// Resolves after `ticks` more clock ticks.
+    pub fn sleep(&self, ticks: u64) -> Sleep<P> {
+        let mut state = self.inner.lock();
+        let deadline = state.ticks + ticks;
+        let waiter = state.next_waiter();
+
+        Sleep { reactor: self.clone(), deadline, waiter }
+    }
+
+    /// Resolves the next time the device signals it's ready, for example when
+    /// its interrupt fires.
+    pub fn device(&self, id: P::DeviceID) -> DeviceReady<P> {
+        let mut state = self.inner.lock();
+        let generation = state.device_generations.get(&id).cloned().unwrap_or(0);
+        let waiter = state.next_waiter();
+
+        DeviceReady { reactor: self.clone(), id, generation, waiter }
+    }
+}
+
+/// Keeps `waker` as the one to wake for `key`, unless the one already there
+/// would wake the same task.
+fn register<K: Ord>(wakers: &mut BTreeMap<K, Waker>, key: K, waker: &Waker) {
+    match wakers.entry(key) {
+        Entry::Occupied(mut entry) => if !entry.get().will_wake(waker) {
+            entry.insert(waker.clone());
+        },
+        Entry::Vacant(entry) => {
+            entry.insert(waker.clone());
+        }
+    }
+}
+
+pub struct Sleep<P: Platform> {
+    reactor: Reactor<P>,
+    deadline: u64,
+    waiter: WaiterId
+}
+
+impl <P: Platform> Future for Sleep<P> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let mut state = self.reactor.inner.lock();
+
+        if state.ticks >= self.deadline {
+            Poll::Ready(())
+        } else {
+            register(&mut state.timers, (self.deadline, self.waiter), context.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl <P: Platform> Drop for Sleep<P> {
+    fn drop(&mut self) {
+        self.reactor.inner.lock().timers.remove(&(self.deadline, self.waiter));
+    }
+}
+
+pub struct DeviceReady<P: Platform> {
+    reactor: Reactor<P>,
+    id: P::DeviceID,
+    generation: u64,
+    waiter: WaiterId
+}
+
+impl <P: Platform> Future for DeviceReady<P> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        let mut state = self.reactor.inner.lock();
+
+        let current = state.device_generations.get(&self.id).cloned().unwrap_or(0);
+        if current > self.generation {
+            Poll::Ready(())
+        } else {
+            let waiters = state.device_waiters.entry(self.id).or_default();
+            register(waiters, self.waiter, context.waker());
+            Poll::Pending
+        }
+    }
+}
+
+impl <P: Platform> Drop for DeviceReady<P> {
+    fn drop(&mut self) {
+        let mut state = self.reactor.inner.lock();
+
+        if let Some(waiters) = state.device_waiters.get_mut(&self.id) {
+            waiters.remove(&self.waiter);
+            if waiters.is_empty() {
+                state.device_waiters.remove(&self.id);
+            }
+        }
+    }
+}
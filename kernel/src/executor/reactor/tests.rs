@@ -0,0 +1,292 @@
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{RawWaker, RawWakerVTable}
+};
+
+use super::*;
+use crate::{
+    CommandLine,
+    Device,
+    DeviceDescription,
+    ErrorKind,
+    EventEnvelope,
+    InterruptStatistics,
+    MemoryRegion,
+    MemoryStatistics,
+    PciDriver,
+    PlatformError,
+    ThreadContext,
+    ThreadEntry,
+    firmware::Firmware,
+    hal::Hal
+};
+
+/// Just enough of a platform to name device IDs. The reactor never calls it.
+struct MockPlatform;
+
+struct MockDevice;
+
+#[derive(Debug, Clone)]
+struct MockError(ErrorKind);
+
+impl Device<MockPlatform> for MockDevice {
+    fn poll(&self) {}
+    fn description(&self) -> DeviceDescription<MockPlatform> { unimplemented!() }
+}
+
+impl fmt::Display for MockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<ErrorKind> for MockError {
+    fn from(kind: ErrorKind) -> Self {
+        MockError(kind)
+    }
+}
+
+impl PlatformError for MockError {
+    fn kind(&self) -> ErrorKind {
+        self.0
+    }
+}
+
+impl Platform for MockPlatform {
+    type DeviceID = u32;
+    type Device = MockDevice;
+    type Error = MockError;
+    type File = ();
+
+    fn init(&mut self) {}
+    fn poll_event(&self) -> Option<EventEnvelope<Self>> { None }
+    fn device(&self, _id: u32) -> Option<Arc<MockDevice>> { None }
+    fn sleep(&self) {}
+    fn command_line(&self) -> &CommandLine { unimplemented!() }
+    fn register_pci_driver(&self, _driver: PciDriver<Self>) {}
+    fn device_polled(&self, _id: u32) {}
+    fn hal() -> Hal<Self> { unimplemented!() }
+    fn firmware() -> Option<&'static dyn Firmware<Self>> { None }
+    fn timestamp(&self) -> u64 { 0 }
+    fn timestamp_frequency(&self) -> u64 { 1 }
+    fn event_overflows(&self) -> u64 { 0 }
+    fn interrupt_statistics(&self) -> InterruptStatistics { unimplemented!() }
+    fn memory_statistics(&self) -> MemoryStatistics { unimplemented!() }
+    fn memory_map(&self) -> Vec<MemoryRegion> { Vec::new() }
+    fn init_thread_context(_stack: &mut [u8], _entry: ThreadEntry, _argument: usize) -> ThreadContext { unimplemented!() }
+    unsafe fn switch_thread(_from: *mut ThreadContext, _to: *const ThreadContext) { unimplemented!() }
+    fn disable_interrupts() -> bool { false }
+    fn restore_interrupts(_enabled: bool) {}
+    fn current_cpu() -> usize { 0 }
+    fn cpu_count() -> usize { 1 }
+    fn stop_other_cpus() {}
+    fn panic_write(_text: &str) {}
+    fn backtrace(_frame: &mut dyn FnMut(usize)) {}
+    fn delay(_milliseconds: u64) {}
+    fn halt() -> ! { unimplemented!() }
+    fn reboot() -> ! { unimplemented!() }
+}
+
+/// A waker that counts how many times it's woken.
+fn counting_waker() -> (Waker, Arc<AtomicUsize>) {
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+
+    fn raw(count: Arc<AtomicUsize>) -> RawWaker {
+        RawWaker::new(Arc::into_raw(count) as *const (), &VTABLE)
+    }
+
+    unsafe fn clone(data: *const ()) -> RawWaker {
+        let count = Arc::from_raw(data as *const AtomicUsize);
+        let clone = count.clone();
+        core::mem::forget(count);
+        raw(clone)
+    }
+
+    unsafe fn wake(data: *const ()) {
+        Arc::from_raw(data as *const AtomicUsize).fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe fn wake_by_ref(data: *const ()) {
+        (*(data as *const AtomicUsize)).fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe fn drop(data: *const ()) {
+        core::mem::drop(Arc::from_raw(data as *const AtomicUsize));
+    }
+
+    let count = Arc::new(AtomicUsize::new(0));
+    (unsafe { Waker::from_raw(raw(count.clone())) }, count)
+}
+
+fn poll<F: Future + Unpin>(future: &mut F, waker: &Waker) -> Poll<F::Output> {
+    Pin::new(future).poll(&mut Context::from_waker(waker))
+}
+
+fn wakes(count: &Arc<AtomicUsize>) -> usize {
+    count.load(Ordering::Relaxed)
+}
+
+#[test]
+fn sleep_resolves_after_its_ticks() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (waker, count) = counting_waker();
+    let mut sleep = reactor.sleep(2);
+
+    assert_eq!(poll(&mut sleep, &waker), Poll::Pending);
+    reactor.clock_ticked();
+    assert_eq!(wakes(&count), 0);
+    assert_eq!(poll(&mut sleep, &waker), Poll::Pending);
+
+    reactor.clock_ticked();
+    assert_eq!(wakes(&count), 1);
+    assert_eq!(poll(&mut sleep, &waker), Poll::Ready(()));
+    assert!(reactor.inner.lock().timers.is_empty());
+}
+
+#[test]
+fn sleeps_wake_in_deadline_order() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (short_waker, short) = counting_waker();
+    let (long_waker, long) = counting_waker();
+    let mut short_sleep = reactor.sleep(1);
+    let mut long_sleep = reactor.sleep(3);
+
+    assert_eq!(poll(&mut long_sleep, &long_waker), Poll::Pending);
+    assert_eq!(poll(&mut short_sleep, &short_waker), Poll::Pending);
+
+    reactor.clock_ticked();
+    assert_eq!((wakes(&short), wakes(&long)), (1, 0));
+
+    reactor.clock_ticked();
+    reactor.clock_ticked();
+    assert_eq!((wakes(&short), wakes(&long)), (1, 1));
+}
+
+#[test]
+fn repolling_keeps_one_waker() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (waker, count) = counting_waker();
+    let mut sleep = reactor.sleep(1);
+
+    for _ in 0..3 {
+        assert_eq!(poll(&mut sleep, &waker), Poll::Pending);
+    }
+    assert_eq!(reactor.inner.lock().timers.len(), 1);
+
+    reactor.clock_ticked();
+    assert_eq!(wakes(&count), 1);
+}
+
+#[test]
+fn polling_with_another_waker_replaces_it() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (first_waker, first) = counting_waker();
+    let (second_waker, second) = counting_waker();
+    let mut sleep = reactor.sleep(1);
+
+    assert_eq!(poll(&mut sleep, &first_waker), Poll::Pending);
+    assert_eq!(poll(&mut sleep, &second_waker), Poll::Pending);
+
+    reactor.clock_ticked();
+    assert_eq!((wakes(&first), wakes(&second)), (0, 1));
+}
+
+#[test]
+fn dropped_sleep_forgets_its_waker() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (waker, count) = counting_waker();
+    let mut sleep = reactor.sleep(1);
+
+    assert_eq!(poll(&mut sleep, &waker), Poll::Pending);
+    core::mem::drop(sleep);
+    assert!(reactor.inner.lock().timers.is_empty());
+
+    reactor.clock_ticked();
+    assert_eq!(wakes(&count), 0);
+}
+
+#[test]
+fn device_ready_wakes_only_that_device() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (waker, count) = counting_waker();
+    let mut ready = reactor.device(1);
+
+    assert_eq!(poll(&mut ready, &waker), Poll::Pending);
+
+    reactor.device_ready(2);
+    assert_eq!(wakes(&count), 0);
+    assert_eq!(poll(&mut ready, &waker), Poll::Pending);
+
+    reactor.device_ready(1);
+    assert_eq!(wakes(&count), 1);
+    assert_eq!(poll(&mut ready, &waker), Poll::Ready(()));
+}
+
+#[test]
+fn device_future_waits_for_the_next_signal() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (waker, _) = counting_waker();
+
+    reactor.device_ready(1);
+    let mut ready = reactor.device(1);
+    assert_eq!(poll(&mut ready, &waker), Poll::Pending);
+
+    reactor.device_ready(1);
+    assert_eq!(poll(&mut ready, &waker), Poll::Ready(()));
+}
+
+#[test]
+fn signal_before_the_first_poll_is_seen() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (waker, count) = counting_waker();
+    let mut ready = reactor.device(1);
+
+    reactor.device_ready(1);
+    assert_eq!(poll(&mut ready, &waker), Poll::Ready(()));
+    assert_eq!(wakes(&count), 0);
+}
+
+#[test]
+fn waiters_are_cleared_once_woken() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (waker, count) = counting_waker();
+    let mut ready = reactor.device(1);
+
+    for _ in 0..3 {
+        assert_eq!(poll(&mut ready, &waker), Poll::Pending);
+    }
+
+    reactor.device_ready(1);
+    assert_eq!(wakes(&count), 1);
+    assert!(reactor.inner.lock().device_waiters.is_empty());
+}
+
+#[test]
+fn dropped_device_future_forgets_its_waker() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (waker, count) = counting_waker();
+    let mut ready = reactor.device(1);
+
+    assert_eq!(poll(&mut ready, &waker), Poll::Pending);
+    core::mem::drop(ready);
+    assert!(reactor.inner.lock().device_waiters.is_empty());
+
+    reactor.device_ready(1);
+    assert_eq!(wakes(&count), 0);
+}
+
+#[test]
+fn device_removed_wakes_and_resolves_waiters() {
+    let reactor = Reactor::<MockPlatform>::new();
+    let (waker, count) = counting_waker();
+    let mut ready = reactor.device(1);
+
+    assert_eq!(poll(&mut ready, &waker), Poll::Pending);
+
+    reactor.device_removed(1);
+    assert_eq!(wakes(&count), 1);
+    assert!(reactor.inner.lock().device_waiters.is_empty());
+    assert_eq!(poll(&mut ready, &waker), Poll::Ready(()));
+}
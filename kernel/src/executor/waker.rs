@@ -0,0 +1,67 @@
+use alloc::sync::Arc;
+use core::{
+    mem,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{RawWaker, RawWakerVTable, Waker}
+};
+
+use super::{ReadyQueue, TaskId};
+
+/// What a task's `Waker` points at. Waking puts the task back on the ready
+/// queue, unless it's already there.
+pub(super) struct TaskWaker {
+    id: TaskId,
+    queued: AtomicBool,
+    ready: Arc<ReadyQueue>
+}
+
+impl TaskWaker {
+    pub fn new(id: TaskId, ready: Arc<ReadyQueue>) -> Arc<Self> {
+        Arc::new(TaskWaker { id, queued: AtomicBool::new(false), ready })
+    }
+
+    pub fn wake(&self) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.ready.lock().push_back(self.id);
+        }
+    }
+
+    /// Called by the executor just before it polls the task, so that wakes
+    /// during the poll queue it again.
+    pub fn dequeued(&self) {
+        self.queued.store(false, Ordering::Release);
+    }
+}
+
+pub(super) fn waker(task: Arc<TaskWaker>) -> Waker {
+    unsafe { Waker::from_raw(raw_waker(task)) }
+}
+
+fn raw_waker(task: Arc<TaskWaker>) -> RawWaker {
+    RawWaker::new(Arc::into_raw(task) as *const (), &VTABLE)
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake_by_ref, drop);
+
+unsafe fn clone(data: *const ()) -> RawWaker {
+    let task = Arc::from_raw(data as *const TaskWaker);
+    let clone = task.clone();
+    mem::forget(task);
+
+    raw_waker(clone)
+}
+
+unsafe fn wake(data: *const ()) {
+    let task = Arc::from_raw(data as *const TaskWaker);
+    task.wake();
+}
+
+unsafe fn wake_by_ref(data: *const ()) {
+    let task = Arc::from_raw(data as *const TaskWaker);
+    task.wake();
+    mem::forget(task);
+}
+
+unsafe fn drop(data: *const ()) {
+    mem::drop(Arc::from_raw(data as *const TaskWaker));
+}
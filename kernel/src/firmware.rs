@@ -0,0 +1,109 @@
+//! What the firmware still does for us once the kernel has taken over:
+//! resetting the machine, keeping the real-time clock, and storing variables
+//! that survive a reboot.
+//!
+//! Platforms whose firmware doesn't stay around after boot have none of
+//! these, and `Platform::firmware` returns `None` there.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::Platform;
+
+pub trait Firmware<P: Platform>: Send + Sync {
+    /// Resets or powers off the machine. Only returns, with an error, if the
+    /// firmware refused.
+    fn reset(&self, kind: ResetKind) -> P::Error;
+
+    fn time(&self) -> Result<DateTime, P::Error>;
+    fn set_time(&self, time: &DateTime) -> Result<(), P::Error>;
+
+    /// Reads a variable into `buffer`, returning its length. Fails if there's
+    /// no such variable, or it doesn't fit.
+    fn variable(&self, vendor: &Guid, name: &str, buffer: &mut [u8]) -> Result<usize, P::Error>;
+
+    /// Creates or replaces a variable. An empty value deletes it.
+    fn set_variable(&self, vendor: &Guid, name: &str, value: &[u8], storage: Storage) -> Result<(), P::Error>;
+
+    /// The names of every variable in `vendor`'s namespace.
+    fn variable_names(&self, vendor: &Guid) -> Result<Vec<String>, P::Error>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResetKind {
+    /// Power cycles the machine.
+    Cold,
+
+    /// Restarts without cutting power, which may keep some hardware state.
+    Warm,
+    Shutdown
+}
+
+/// Whether a variable survives a reboot.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Storage {
+    Volatile,
+    NonVolatile
+}
+
+/// The namespace a variable belongs to, laid out like an `EFI_GUID` so
+/// platforms can hand it to the firmware as it is.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Guid {
+    data1: u32,
+    data2: u16,
+    data3: u16,
+    data4: [u8; 8]
+}
+
+impl Guid {
+    /// From the fields of the usual `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`
+    /// form, in the order they're written.
+    pub const fn new(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Self {
+        Guid { data1, data2, data3, data4 }
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let d = &self.data4;
+        write!(f, "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}", self.data1, self.data2,
+            self.data3, d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7])
+    }
+}
+
+/// A reading of the real-time clock.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+
+    /// From 1.
+    pub month: u8,
+
+    /// From 1.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+
+    /// The offset from UTC in minutes, as the firmware keeps it, or `None`
+    /// if the clock is in local time.
+    pub time_zone: Option<i16>
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute,
+            self.second)?;
+
+        if let Some(time_zone) = self.time_zone {
+            let sign = if time_zone < 0 { '-' } else { '+' };
+            let minutes = time_zone.abs();
+            write!(f, " {}{:02}{:02}", sign, minutes / 60, minutes % 60)?;
+        }
+
+        Ok(())
+    }
+}
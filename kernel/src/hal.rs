@@ -0,0 +1,224 @@
+//! How portable drivers reach the hardware.
+//!
+//! A driver in this crate can't touch ports or physical memory directly,
+//! because how that's done depends on the platform. Instead the platform
+//! hands it a `Hal`, and the driver goes through that.
+
+use core::{
+    fmt,
+    ptr
+};
+
+use crate::Platform;
+
+/// Everything a portable driver needs from the platform.
+pub struct Hal<P: Platform> {
+    pub ports: &'static dyn PortIo,
+    pub mmio: &'static dyn Mmio<P>,
+    pub pci: &'static dyn PciConfig,
+    pub dma: &'static dyn Dma<P>,
+    pub irq: &'static dyn Irq<P>
+}
+
+impl <P: Platform> Clone for Hal<P> {
+    fn clone(&self) -> Self {
+        Hal { ports: self.ports, mmio: self.mmio, pci: self.pci, dma: self.dma, irq: self.irq }
+    }
+}
+
+impl <P: Platform> Copy for Hal<P> {}
+
+/// I/O port access. Platforms without I/O ports can panic here, since no
+/// device on them will have an I/O port resource.
+pub trait PortIo: Send + Sync {
+    unsafe fn read_u8(&self, port: u16) -> u8;
+    unsafe fn read_u16(&self, port: u16) -> u16;
+    unsafe fn read_u32(&self, port: u16) -> u32;
+
+    unsafe fn write_u8(&self, port: u16, value: u8);
+    unsafe fn write_u16(&self, port: u16, value: u16);
+    unsafe fn write_u32(&self, port: u16, value: u32);
+}
+
+pub trait Mmio<P: Platform>: Send + Sync {
+    /// Makes `size` bytes of device memory at `physical` accessible. The
+    /// mapping is uncached.
+    fn map(&self, physical: u64, size: usize) -> Result<MmioRegion, P::Error>;
+    fn unmap(&self, region: MmioRegion);
+}
+
+/// Mapped device memory. Every access is volatile and bounds-checked.
+pub struct MmioRegion {
+    base: *mut u8,
+    physical: u64,
+    size: usize
+}
+
+// The region is just an address; what's behind it is up to the device.
+unsafe impl Send for MmioRegion {}
+unsafe impl Sync for MmioRegion {}
+
+impl MmioRegion {
+    /// `base` must be valid for volatile access to `size` bytes for as long
+    /// as the region lives.
+    pub unsafe fn new(base: *mut u8, physical: u64, size: usize) -> Self {
+        MmioRegion { base, physical, size }
+    }
+
+    pub fn base(&self) -> *mut u8 {
+        self.base
+    }
+
+    pub fn physical(&self) -> u64 {
+        self.physical
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn read_u8(&self, offset: usize) -> u8 {
+        unsafe { ptr::read_volatile(self.at::<u8>(offset)) }
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        unsafe { ptr::read_volatile(self.at::<u16>(offset)) }
+    }
+
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile(self.at::<u32>(offset)) }
+    }
+
+    pub fn read_u64(&self, offset: usize) -> u64 {
+        unsafe { ptr::read_volatile(self.at::<u64>(offset)) }
+    }
+
+    pub fn write_u8(&self, offset: usize, value: u8) {
+        unsafe { ptr::write_volatile(self.at::<u8>(offset), value) }
+    }
+
+    pub fn write_u16(&self, offset: usize, value: u16) {
+        unsafe { ptr::write_volatile(self.at::<u16>(offset), value) }
+    }
+
+    pub fn write_u32(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile(self.at::<u32>(offset), value) }
+    }
+
+    pub fn write_u64(&self, offset: usize, value: u64) {
+        unsafe { ptr::write_volatile(self.at::<u64>(offset), value) }
+    }
+
+    fn at<T>(&self, offset: usize) -> *mut T {
+        let size = core::mem::size_of::<T>();
+        assert!(offset + size <= self.size, "MMIO access at {:#x} is outside a {:#x} byte region", offset, self.size);
+        assert!(offset % size == 0, "Unaligned MMIO access at {:#x}", offset);
+
+        unsafe { self.base.add(offset) as *mut T }
+    }
+}
+
+impl fmt::Debug for MmioRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MmioRegion({:#x}, {:#x} bytes)", self.physical, self.size)
+    }
+}
+
+/// A PCI function, by segment, bus, device and function number.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciLocation {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8
+}
+
+impl fmt::Display for PciLocation {
+    /// The `bus:device.function` form, with the segment in front if it isn't
+    /// zero.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.segment != 0 {
+            write!(f, "{:04x}:", self.segment)?;
+        }
+
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// PCI configuration space. Only whole, aligned dwords have to be supported;
+/// narrower accesses are built on them.
+pub trait PciConfig: Send + Sync {
+    fn read_u32(&self, location: PciLocation, offset: u16) -> u32;
+    fn write_u32(&self, location: PciLocation, offset: u16, value: u32);
+
+    fn read_u16(&self, location: PciLocation, offset: u16) -> u16 {
+        (self.read_u32(location, offset & !3) >> ((offset & 2) * 8)) as u16
+    }
+
+    fn read_u8(&self, location: PciLocation, offset: u16) -> u8 {
+        (self.read_u32(location, offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// Writes a word by rewriting the dword around it. Registers with
+    /// write-one-to-clear bits next to the word need a dword write instead.
+    fn write_u16(&self, location: PciLocation, offset: u16, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(location, offset & !3) & !(0xffff << shift);
+        self.write_u32(location, offset & !3, dword | (value as u32) << shift);
+    }
+}
+
+/// Memory a device can read and write by itself.
+pub trait Dma<P: Platform>: Send + Sync {
+    /// Allocates `size` zeroed bytes, physically contiguous and aligned to
+    /// `align`.
+    fn allocate(&self, size: usize, align: usize) -> Result<DmaBuffer, P::Error>;
+    fn free(&self, buffer: DmaBuffer);
+}
+
+/// A DMA allocation. It isn't freed on drop, since the device may still be
+/// using it; give it back with `Dma::free` once the device has stopped.
+#[derive(Debug)]
+pub struct DmaBuffer {
+    virt: *mut u8,
+    physical: u64,
+    size: usize,
+    align: usize
+}
+
+unsafe impl Send for DmaBuffer {}
+unsafe impl Sync for DmaBuffer {}
+
+impl DmaBuffer {
+    pub unsafe fn new(virt: *mut u8, physical: u64, size: usize, align: usize) -> Self {
+        DmaBuffer { virt, physical, size, align }
+    }
+
+    /// Where the CPU sees the buffer.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.virt
+    }
+
+    /// Where the device sees the buffer.
+    pub fn physical(&self) -> u64 {
+        self.physical
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn align(&self) -> usize {
+        self.align
+    }
+}
+
+pub trait Irq<P: Platform>: Send + Sync {
+    /// Unmasks `irq` and has `device` polled whenever it fires. Other devices
+    /// may share it.
+    fn enable(&self, irq: u32, device: P::DeviceID) -> Result<(), P::Error>;
+
+    /// Stops polling `device` when `irq` fires, masking it once no other
+    /// device shares it.
+    fn disable(&self, irq: u32, device: P::DeviceID);
+}
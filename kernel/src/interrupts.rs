@@ -0,0 +1,56 @@
+//! Platform-independent control over interrupts on the current CPU.
+//!
+//! The platform registers its functions with `init` as early as it can, since
+//! IRQ-safe locks are used from the very start of boot. Until then, the
+//! functions here do nothing and `disable` reports interrupts as disabled.
+
+use spin::Once;
+
+use crate::Platform;
+
+struct InterruptControl {
+    disable: fn() -> bool,
+    restore: fn(bool),
+    current_cpu: fn() -> usize
+}
+
+static CONTROL: Once<InterruptControl> = Once::new();
+
+pub fn init<P: Platform>() {
+    CONTROL.call_once(|| InterruptControl {
+        disable: P::disable_interrupts,
+        restore: P::restore_interrupts,
+        current_cpu: P::current_cpu
+    });
+}
+
+/// Disables interrupts, returning whether they were enabled before.
+pub fn disable() -> bool {
+    match CONTROL.r#try() {
+        Some(control) => (control.disable)(),
+        None => false
+    }
+}
+
+/// Re-enables interrupts if `enabled` is true. Pass it what `disable`
+/// returned to undo that call.
+pub fn restore(enabled: bool) {
+    if let Some(control) = CONTROL.r#try() {
+        (control.restore)(enabled);
+    }
+}
+
+pub fn without_interrupts<R, F: FnOnce() -> R>(f: F) -> R {
+    let enabled = disable();
+    let result = f();
+    restore(enabled);
+
+    result
+}
+
+pub fn current_cpu() -> usize {
+    match CONTROL.r#try() {
+        Some(control) => (control.current_cpu)(),
+        None => 0
+    }
+}
@@ -1,117 +1,387 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(associated_type_defaults)]
 #![feature(alloc_error_handler)]
 #![feature(panic_info_message)]
+#![feature(const_in_array_repeat_expressions)]
 
 extern crate alloc;
 
+mod command_line;
 mod device;
-mod panic;
+mod device_tree;
+pub mod dmesg;
+mod driver;
+mod error;
+pub mod executor;
+pub mod firmware;
+pub mod hal;
+pub mod interrupts;
+pub mod log_sink;
+mod log_staging;
+pub mod panic;
 mod platform;
+mod scheduler;
+pub mod settings;
+mod shell;
+mod statistics;
+pub mod sync;
+pub mod thread;
+
+use alloc::string::String;
+
+use crate::{
+  executor::{Executor, Reactor, Spawner},
+  shell::Shell
+};
 
 pub use crate::{
-  device::{Device, DeviceRegistry, Filesystem, GraphicsDevice},
-  platform::Platform
+  command_line::CommandLine,
+  device::{
+    BlockDevice, CharDevice, ClockDevice, Device, DeviceEvent, DeviceHandle, DeviceRegistry, Filesystem,
+    GraphicsDevice, InputDevice, InputEvent, NetworkDevice, SubscriptionId
+  },
+  device_tree::{DeviceClass, DeviceDescription, DeviceNode, DeviceTree, Resource},
+  driver::{PciDeviceInfo, PciDriver, PciMatch, ProbeContext},
+  error::{ErrorKind, PlatformError},
+  platform::Platform,
+  statistics::{
+    EventStatistics, InterruptStatistics, LatencyCounter, MemoryKind, MemoryRegion, MemoryStatistics, VectorStatistics
+  },
+  thread::{ThreadContext, ThreadEntry}
 };
 
 #[derive(Debug, Clone)]
 pub enum PlatformEvent<P: Platform> {
   ClockTicked,
-  DeviceConnected(P::DeviceID, P::Device),
+  /// The device can be fetched with `Platform::device`.
+  DeviceConnected(P::DeviceID),
+  DeviceDisconnected(P::DeviceID),
+  DeviceError(P::DeviceID, P::Error),
   DevicePollable(P::DeviceID)
 }
 
+impl <P: Platform> PlatformEvent<P> {
+  /// Critical events must never be dropped when the event buffer is full.
+  /// Losing a `DeviceConnected` would lose the device for good, and losing a
+  /// `DeviceDisconnected` would leave us driving hardware that isn't there.
+  pub fn is_critical(&self) -> bool {
+    match self {
+      PlatformEvent::DeviceConnected(_) => true,
+      PlatformEvent::DeviceDisconnected(_) => true,
+      _ => false
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      PlatformEvent::ClockTicked => "ClockTicked",
+      PlatformEvent::DeviceConnected(_) => "DeviceConnected",
+      PlatformEvent::DeviceDisconnected(_) => "DeviceDisconnected",
+      PlatformEvent::DeviceError(_, _) => "DeviceError",
+      PlatformEvent::DevicePollable(_) => "DevicePollable"
+    }
+  }
+}
+
+/// A `PlatformEvent` as it comes out of the event buffer.
+#[derive(Debug, Clone)]
+pub struct EventEnvelope<P: Platform> {
+  /// Assigned in push order, with no gaps. A gap means events were dropped.
+  pub sequence: u64,
+
+  /// `Platform::timestamp` at the time the event was pushed.
+  pub timestamp: u64,
+
+  pub event: PlatformEvent<P>
+}
+
 pub struct Kernel<P: Platform> {
   pub platform: P,
-  pub device_registry: DeviceRegistry<P>
+  pub device_registry: DeviceRegistry<P>,
+  event_statistics: EventStatistics,
+  next_sequence: u64,
+  executor: Executor,
+  reactor: Reactor<P>,
+
+  /// `None` when the shell is turned off with the `noshell` boot option.
+  shell: Option<Shell<P>>
 }
 
 impl <P: Platform> Kernel<P>  {
   pub fn new(platform: P) -> Self {
     Self {
       platform,
-      device_registry: DeviceRegistry::new()
+      device_registry: DeviceRegistry::new(),
+      event_statistics: EventStatistics::default(),
+      next_sequence: 0,
+      executor: Executor::new(),
+      reactor: Reactor::new(),
+      shell: None
     }
   }
 
+  /// Spawns kernel tasks. Tasks run on the kernel's event loop, between
+  /// batches of platform events.
+  pub fn spawner(&self) -> Spawner {
+    self.executor.spawner()
+  }
+
+  /// What kernel tasks await to hear about clock ticks and devices.
+  pub fn reactor(&self) -> Reactor<P> {
+    self.reactor.clone()
+  }
+
+  /// Adds a portable PCI driver. Register drivers before `start`, so that
+  /// they're there when the platform first scans the bus.
+  pub fn register_pci_driver(&self, driver: PciDriver<P>) {
+    self.platform.register_pci_driver(driver);
+  }
+
+  pub fn event_statistics(&self) -> &EventStatistics {
+    &self.event_statistics
+  }
+
+  pub fn interrupt_statistics(&self) -> InterruptStatistics {
+    self.platform.interrupt_statistics()
+  }
+
   pub fn start(mut self) -> ! {
     log::info!("Kernel starting up");
 
     self.platform.init();
+    log_sink::set_timestamp_frequency(self.platform.timestamp_frequency());
+
+    // Like Linux, `panic=N` reboots N seconds after a panic.
+    match self.platform.command_line().get_parsed::<u64>("panic") {
+      Some(0) | None => panic::set_reboot_timeout(None),
+      Some(seconds) => panic::set_reboot_timeout(Some(seconds))
+    }
+
+    if !self.platform.command_line().flag("noshell") {
+      self.shell = Some(Shell::new());
+    }
+
+    thread::init::<P>("kernel");
     self.process_events();
 
-    self.clear_screen().unwrap();
+    log::info!("Device tree:\n{}", self.device_registry.tree());
 
-    //self.execute("EFI\\Binaries\\init.efi").unwrap();
+    if let Err(error) = self.clear_screen() {
+      log::warn!("Couldn't clear the screen: {}", error);
+    }
+
+    if let Some(init) = self.platform.command_line().get("init").map(String::from) {
+      if let Err(error) = self.execute(&init) {
+        log::error!("Couldn't run {}: {}", init, error);
+      }
+    }
 
     loop {
       self.process_events();
+      self.executor.run_ready();
 
-      log::debug!("Kernel idle");
-      self.platform.sleep();
+      if thread::has_ready() {
+        thread::yield_now();
+      } else if !self.executor.has_ready() {
+        log::debug!("Kernel idle");
+        self.platform.sleep();
+      }
     }
   }
 
   fn process_events(&mut self) {
-    while let Some(event) = self.platform.poll_event() {
-      match event {
-        PlatformEvent::ClockTicked => {
-          log::info!("Tick!");
-        },
-
-        PlatformEvent::DeviceConnected(id, device) => {
-          log::info!("Device {:?} connected", id);
-          self.device_registry.insert(id, device);
+    dmesg::flush();
+    self.event_statistics.overflows = self.platform.event_overflows();
+
+    while let Some(envelope) = self.platform.poll_event() {
+      let start = self.platform.timestamp();
+
+      if envelope.sequence > self.next_sequence {
+        log::warn!("Event buffer overflowed, {} event(s) dropped before event {}",
+          envelope.sequence - self.next_sequence, envelope.sequence);
+      }
+      self.next_sequence = envelope.sequence + 1;
+
+      self.event_statistics.queued.record(start.wrapping_sub(envelope.timestamp));
+
+      let event = envelope.event;
+      let name = event.name();
+
+      self.dispatch_event(event);
+
+      let duration = self.platform.timestamp().wrapping_sub(start);
+      self.event_statistics.dispatched.entry(name)
+        .or_insert_with(LatencyCounter::default)
+        .record(duration);
+    }
+  }
+
+  fn dispatch_event(&mut self, event: PlatformEvent<P>) {
+    match event {
+      PlatformEvent::ClockTicked => {
+        log::info!("Tick!");
+        self.reactor.clock_ticked();
+      },
+
+      PlatformEvent::DeviceConnected(id) => {
+        match self.platform.device(id) {
+          Some(device) => self.device_registry.insert(id, device),
+          None => {
+            log::warn!("Device {:?} was disconnected before we saw it", id);
+            return;
+          }
+        }
+
+        if let Some(node) = self.device_registry.tree().node(&id) {
+          log::info!("Device {:?} connected at {}", id, node.path);
         }
 
-        PlatformEvent::DevicePollable(id) => {
-          if let Some(device) = self.device_registry.device(&id) {
-            device.poll();
-          } else {
-            log::error!("Unknown device ID: {:?}", id);
+        self.attach_shell(id);
+        self.attach_log_device(id);
+      },
+
+      PlatformEvent::DeviceDisconnected(id) => {
+        log::info!("Device {:?} disconnected", id);
+        if self.device_registry.remove(&id).is_none() {
+          log::warn!("Unknown device ID: {:?}", id);
+        }
+        self.reactor.device_removed(id);
+
+        if let Some(shell) = self.shell.as_mut() {
+          shell.detach(id);
+        }
+      },
+
+      PlatformEvent::DeviceError(id, error) => {
+        log::error!("Device {:?} reported an error ({}): {}", id, error.kind(), error);
+        self.device_registry.report_error(id, &error);
+      },
+
+      PlatformEvent::DevicePollable(id) => {
+        if let Some(device) = self.device_registry.device(&id) {
+          device.poll();
+          self.reactor.device_ready(id);
+
+          if self.shell.as_ref().and_then(|shell| shell.console()) == Some(id) {
+            self.poll_shell();
           }
+        } else {
+          log::error!("Unknown device ID: {:?}", id);
         }
+
+        // Even for a device that's gone, so that its IRQ isn't left masked
+        // for others sharing it.
+        self.platform.device_polled(id);
       }
     }
   }
 
+  /// Gives the shell its console, if it hasn't got one and this is a
+  /// character device.
+  fn attach_shell(&mut self, id: P::DeviceID) {
+    if let Some(mut shell) = self.shell.take() {
+      let is_char_device = self.device_registry.device(&id)
+        .map(|device| device.as_char_device().is_some())
+        .unwrap_or(false);
+
+      if shell.console().is_none() && is_char_device {
+        shell.attach(id, self);
+      }
+
+      self.shell = Some(shell);
+    }
+  }
+
+  /// Logs to a character device if the `log.devices` boot option names it.
+  fn attach_log_device(&self, id: P::DeviceID) {
+    let device = match self.device_registry.device(&id) {
+      Some(device) => device,
+      None => return
+    };
+
+    let name = device.description().name;
+    if device.as_char_device().is_some() && self.platform.command_line().list("log.devices").contains(&name.as_str()) {
+      if log_sink::register_device::<P>(&name, &device) {
+        log::info!("Logging to {}", name);
+      } else {
+        log::warn!("No room for another log sink, not logging to {}", name);
+      }
+    }
+  }
+
+  fn poll_shell(&mut self) {
+    // The shell looks at the rest of the kernel, so it's taken out while it
+    // runs.
+    if let Some(mut shell) = self.shell.take() {
+      shell.poll(self);
+      self.shell = Some(shell);
+    }
+  }
+
   fn clear_screen(&mut self) -> Result<(), P::Error> {
     let devices = self.device_registry.graphics_devices();
     
     for id in devices.iter() {
       log::info!("Found graphics device {:?}", id);
-      let device = self.device_registry.device(id).unwrap();
-      let device = device.as_graphics_device().unwrap();
+      let device = match self.device_registry.device(id) {
+        Some(device) => device,
+        None => continue
+      };
 
-      device.clear()?;
+      if let Some(graphics) = device.as_graphics_device() {
+        graphics.clear()?;
+      }
     }
 
     Ok(())
   }
 
+  /// Loads `path` from the first filesystem that has it.
   fn execute(&mut self, path: &str) -> Result<(), P::Error> {
-
     let filesystem_devices = self.device_registry.filesystem_devices();
     for id in filesystem_devices.iter() {
-      let device = self.device_registry.device(id).unwrap();
-      let fs = device.as_filesystem().unwrap();
-      let contents = fs.read(path)?;
-
-      let binary = goblin::pe::PE::parse(&contents).expect("Failed to parse PE64");
-      log::info!("Parsed object: {:#?}", binary);
-
-      for section in binary.sections {
-        let name = alloc::string::String::from_utf8(
-          section.name.iter()
-            .take_while(|c| **c != 0)
-            .map(|c| *c)
-            .collect::<alloc::vec::Vec<u8>>()
-          ).unwrap();
-        log::info!(" - Section {:?} ({} bytes to be loaded at {:#016x})", 
-          name, section.size_of_raw_data, section.virtual_address);
-      }
+      let device = match self.device_registry.device(id) {
+        Some(device) => device,
+        None => continue
+      };
+      let fs = match device.as_filesystem() {
+        Some(fs) => fs,
+        None => continue
+      };
+
+      let contents = match fs.read(path) {
+        Ok(contents) => contents,
+        Err(ref error) if error.kind() == ErrorKind::NotFound => continue,
+        Err(error) => return Err(error)
+      };
+
+      // Parsing a large binary takes a while, so do it on its own thread and
+      // let the event loop get on with handling devices.
+      thread::spawn("execute", move || {
+        let binary = match goblin::pe::PE::parse(&contents) {
+          Ok(binary) => binary,
+          Err(error) => {
+            log::error!("Couldn't run binary ({}): {:?}", ErrorKind::InvalidExecutable, error);
+            return;
+          }
+        };
+        log::info!("Parsed object: {:#?}", binary);
+
+        for section in binary.sections {
+          let name = String::from_utf8_lossy(
+            &section.name.iter()
+              .take_while(|c| **c != 0)
+              .map(|c| *c)
+              .collect::<alloc::vec::Vec<u8>>()
+            ).into_owned();
+          log::info!(" - Section {:?} ({} bytes to be loaded at {:#016x})", 
+            name, section.size_of_raw_data, section.virtual_address);
+        }
+      });
+
+      return Ok(());
     }
 
-    Ok(())
+    Err(ErrorKind::NotFound.into())
   }
 }
@@ -0,0 +1,336 @@
+//! Where log records go.
+//!
+//! Every record the `dmesg` filters let through is offered to each
+//! registered sink, and written to those whose level allows it, formatted
+//! the way the sink asked for. The log buffer is a sink called `dmesg` from
+//! the start. The platform adds its consoles, and the kernel adds a sink for
+//! each character device named in the `log.devices` boot option once the
+//! device connects. `log.sink.<name>` sets a sink's level and format, as in
+//! `log.sink.serial=debug,ansi`, whenever the sink is registered.
+//!
+//! Sinks are called without any lock held, so they can take their own.
+
+use alloc::{
+    boxed::Box,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec
+};
+use core::{
+    any::Any,
+    fmt::{self, Write},
+    str::{self, FromStr},
+    sync::atomic::{AtomicU64, Ordering}
+};
+use log::{Level, LevelFilter};
+use spin::Once;
+
+use crate::{
+    CommandLine,
+    Device,
+    DeviceHandle,
+    Platform,
+    dmesg::{self, Record, Truncated},
+    sync::{IrqSpinLock, LockClass}
+};
+
+const MAX_SINKS: usize = 8;
+
+/// Longest sink name `log.sink.<name>` is looked up for.
+const MAX_NAME: usize = 32;
+
+/// Longest formatted record. Longer ones are cut off.
+const MAX_TEXT: usize = 1024;
+
+static SINKS_CLASS: LockClass = LockClass::new("log sinks", 95);
+
+const NO_SINK: Option<Entry> = None;
+static SINKS: IrqSpinLock<[Option<Entry>; MAX_SINKS]> = IrqSpinLock::ranked([
+    Some(Entry { name: "dmesg", sink: &dmesg::RING_SINK, device: None, level: LevelFilter::Trace, format: LogFormat::Plain }),
+    NO_SINK, NO_SINK, NO_SINK, NO_SINK, NO_SINK, NO_SINK, NO_SINK
+], &SINKS_CLASS);
+
+static COMMAND_LINE: Once<&'static CommandLine> = Once::new();
+
+/// How many timestamp units make a second, or zero until the platform knows.
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+pub trait LogSink: Send + Sync {
+    /// Takes a record, along with its text in the sink's format, ending in
+    /// a newline.
+    fn write(&self, record: &Record, text: &str);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `INFO - message`
+    Plain,
+
+    /// The time, the level coloured with ANSI escapes, the module and the
+    /// message.
+    Ansi,
+
+    /// `key=value` pairs, with the message quoted.
+    Structured
+}
+
+impl LogFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogFormat::Plain => "plain",
+            LogFormat::Ansi => "ansi",
+            LogFormat::Structured => "structured"
+        }
+    }
+
+    fn write(&self, out: &mut dyn Write, record: &Record) -> fmt::Result {
+        let micros = record.micros(FREQUENCY.load(Ordering::Relaxed));
+
+        match self {
+            LogFormat::Plain => writeln!(out, "{} - {}", record.level, record.message),
+
+            LogFormat::Ansi => {
+                let colour = match record.level {
+                    Level::Error => "31",
+                    Level::Warn => "33",
+                    Level::Info => "32",
+                    Level::Debug => "36",
+                    Level::Trace => "90"
+                };
+
+                writeln!(out, "[{:>5}.{:06}] \x1b[{}m{:<5}\x1b[0m {}: {}", micros / 1_000_000, micros % 1_000_000,
+                    colour, record.level, record.module, record.message)
+            },
+
+            LogFormat::Structured => {
+                write!(out, "time={}.{:06} seq={} cpu={} level={} module={} msg=\"", micros / 1_000_000,
+                    micros % 1_000_000, record.sequence, record.cpu, record.level, record.module)?;
+
+                for c in record.message.chars() {
+                    match c {
+                        '"' | '\\' => write!(out, "\\{}", c)?,
+                        '\n' => out.write_str("\\n")?,
+                        c => out.write_char(c)?
+                    }
+                }
+                out.write_str("\"\n")
+            }
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(text: &str) -> Result<Self, ()> {
+        match text {
+            "plain" => Ok(LogFormat::Plain),
+            "ansi" => Ok(LogFormat::Ansi),
+            "structured" => Ok(LogFormat::Structured),
+            _ => Err(())
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Entry {
+    name: &'static str,
+    sink: &'static dyn LogSink,
+
+    /// The same sink, if it's a `DeviceSink`, so it can be found again and
+    /// pointed at another device.
+    device: Option<&'static (dyn Any + Send + Sync)>,
+    level: LevelFilter,
+    format: LogFormat
+}
+
+/// A sink as `sinks` lists it.
+#[derive(Debug, Clone)]
+pub struct SinkInfo {
+    pub name: String,
+    pub level: LevelFilter,
+    pub format: LogFormat
+}
+
+/// Adds a sink, replacing any other with the same name, then applies the
+/// `log.sink.<name>` boot option to it. Returns false if there's no room
+/// for another sink.
+pub fn register(name: &'static str, sink: &'static dyn LogSink, level: LevelFilter, format: LogFormat) -> bool {
+    add(Entry { name, sink, device: None, level, format })
+}
+
+fn add(entry: Entry) -> bool {
+    let name = entry.name;
+
+    let registered = {
+        let mut sinks = SINKS.lock();
+        let slot = sinks.iter()
+            .position(|existing| existing.map(|existing| existing.name == name).unwrap_or(false))
+            .or_else(|| sinks.iter().position(Option::is_none));
+
+        match slot {
+            Some(slot) => {
+                sinks[slot] = Some(entry);
+                true
+            },
+            None => false
+        }
+    };
+
+    if registered {
+        if let Some(command_line) = COMMAND_LINE.r#try() {
+            configure_sink(command_line, name);
+        }
+    }
+
+    registered
+}
+
+pub fn unregister(name: &str) {
+    for slot in SINKS.lock().iter_mut() {
+        if slot.map(|entry| entry.name == name).unwrap_or(false) {
+            *slot = None;
+        }
+    }
+}
+
+/// Sets the most verbose level a sink is sent. Returns false if there's no
+/// such sink.
+pub fn set_level(name: &str, level: LevelFilter) -> bool {
+    update(name, |entry| entry.level = level)
+}
+
+/// Returns false if there's no such sink.
+pub fn set_format(name: &str, format: LogFormat) -> bool {
+    update(name, |entry| entry.format = format)
+}
+
+fn update<F: FnMut(&mut Entry)>(name: &str, mut change: F) -> bool {
+    let mut found = false;
+
+    for entry in SINKS.lock().iter_mut().filter_map(Option::as_mut) {
+        if entry.name == name {
+            change(entry);
+            found = true;
+        }
+    }
+
+    found
+}
+
+pub fn sinks() -> Vec<SinkInfo> {
+    let sinks = *SINKS.lock();
+
+    sinks.iter()
+        .filter_map(Option::as_ref)
+        .map(|entry| SinkInfo { name: entry.name.into(), level: entry.level, format: entry.format })
+        .collect()
+}
+
+/// Lets formats that show the time convert timestamps to seconds.
+pub fn set_timestamp_frequency(frequency: u64) {
+    FREQUENCY.store(frequency, Ordering::Relaxed);
+}
+
+/// Applies the `log.sink.<name>` boot options to the sinks registered so
+/// far, and keeps them for sinks registered later.
+pub fn configure(command_line: &'static CommandLine) {
+    COMMAND_LINE.call_once(|| command_line);
+
+    for (key, _) in command_line.iter() {
+        if key.starts_with("log.sink.") {
+            configure_sink(command_line, &key["log.sink.".len()..]);
+        }
+    }
+}
+
+/// `log.sink.<name>=<level>,<format>`, where either can be left out.
+fn configure_sink(command_line: &CommandLine, name: &str) {
+    // `log.sink.` and the name, built without allocating.
+    if name.len() > MAX_NAME {
+        return;
+    }
+    let mut buffer = [0; 9 + MAX_NAME];
+    let mut key = Truncated::new(&mut buffer);
+    let _ = write!(key, "log.sink.{}", name);
+
+    for item in command_line.list(key.as_str()) {
+        if let Ok(level) = item.parse::<LevelFilter>() {
+            set_level(name, level);
+        } else if let Ok(format) = item.parse::<LogFormat>() {
+            set_format(name, format);
+        } else {
+            log::warn!("Ignoring {}: `{}` is neither a log level nor a format", key.as_str(), item);
+        }
+    }
+}
+
+/// Hands a record to every sink that wants it.
+pub(crate) fn dispatch(record: &Record) {
+    let sinks = *SINKS.lock();
+    let mut buffer = [0; MAX_TEXT];
+    let mut text = Truncated::new(&mut buffer);
+
+    for entry in sinks.iter().filter_map(Option::as_ref) {
+        if record.level > entry.level {
+            continue;
+        }
+
+        text.clear();
+        let _ = entry.format.write(&mut text, record);
+        entry.sink.write(record, text.as_str());
+    }
+}
+
+/// Writes records to a character device, turning `\n` into `\r\n` for
+/// terminals. It holds the device weakly, so once the device disconnects
+/// it writes nowhere until a device of the same name connects again.
+struct DeviceSink<P: Platform> {
+    device: IrqSpinLock<Weak<P::Device>>
+}
+
+impl<P: Platform> LogSink for DeviceSink<P> {
+    fn write(&self, _record: &Record, text: &str) {
+        let device = match self.device.lock().upgrade() {
+            Some(device) => device,
+            None => return
+        };
+
+        if let Some(console) = device.as_char_device() {
+            for (index, line) in text.split('\n').enumerate() {
+                if index > 0 {
+                    let _ = console.write(b"\r\n");
+                }
+                let _ = console.write(line.as_bytes());
+            }
+        }
+    }
+}
+
+/// Adds a sink for a character device named `name`, or points the existing
+/// one at it when a device of that name reconnects, keeping its level and
+/// format. New sinks and their names are leaked, since a sink may still be
+/// in use after it's unregistered.
+pub(crate) fn register_device<P: Platform>(name: &str, device: &DeviceHandle<P>) -> bool {
+    let existing = SINKS.lock().iter()
+        .filter_map(Option::as_ref)
+        .find(|entry| entry.name == name)
+        .and_then(|entry| entry.device)
+        .and_then(|sink| sink.downcast_ref::<DeviceSink<P>>());
+
+    if let Some(sink) = existing {
+        *sink.device.lock() = Arc::downgrade(device);
+        return true;
+    }
+
+    let name: &'static str = Box::leak(String::from(name).into_boxed_str());
+    let sink: &'static DeviceSink<P> = Box::leak(Box::new(DeviceSink { device: IrqSpinLock::new(Arc::downgrade(device)) }));
+
+    add(Entry { name, sink, device: Some(sink), level: LevelFilter::Trace, format: LogFormat::Plain })
+}
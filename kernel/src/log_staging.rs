@@ -0,0 +1,221 @@
+//! Somewhere to put log records when handing them to the sinks isn't safe.
+//!
+//! A record logged from an interrupt handler or the allocator, or from
+//! inside the logger itself, could otherwise wait on a lock the code it
+//! interrupted holds, or recurse. Instead it's copied into one of its CPU's
+//! staging slots, which are claimed with a compare-and-swap and never
+//! waited on, and handed to the sinks by the next record logged normally, or
+//! the next time the kernel's event loop comes round. If every slot is full
+//! the record is dropped, and the drop is counted and reported at the next
+//! flush.
+//!
+//! Formatting the message still runs the record's `Display` impls, so those
+//! mustn't allocate from allocator context.
+
+use core::{
+    cell::UnsafeCell,
+    fmt::Write,
+    str,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering}
+};
+use log::Level;
+
+use crate::{dmesg::{self, Record, Truncated}, interrupts};
+
+/// CPUs beyond this share slots, which is safe, just more crowded.
+const CPUS: usize = 16;
+const SLOTS: usize = 16;
+
+/// The most slots flushed at a time, in sequence order.
+const BATCH: usize = 32;
+
+const MAX_MODULE: usize = 64;
+
+/// Staged messages are cut off sooner than others, to keep the slots small.
+const MAX_MESSAGE: usize = 256;
+
+const EMPTY: u8 = 0;
+const WRITING: u8 = 1;
+const READY: u8 = 2;
+const FLUSHING: u8 = 3;
+
+struct Slot {
+    state: AtomicU8,
+    record: UnsafeCell<Staged>
+}
+
+/// Only the CPU that moved `state` out of `EMPTY` or `READY` touches `record`.
+unsafe impl Sync for Slot {}
+
+struct Staged {
+    sequence: u64,
+    timestamp: u64,
+    cpu: usize,
+    level: Level,
+    module: [u8; MAX_MODULE],
+    module_length: usize,
+    message: [u8; MAX_MESSAGE],
+    message_length: usize
+}
+
+const EMPTY_SLOT: Slot = Slot {
+    state: AtomicU8::new(EMPTY),
+    record: UnsafeCell::new(Staged {
+        sequence: 0,
+        timestamp: 0,
+        cpu: 0,
+        level: Level::Trace,
+        module: [0; MAX_MODULE],
+        module_length: 0,
+        message: [0; MAX_MESSAGE],
+        message_length: 0
+    })
+};
+const EMPTY_SLOTS: [Slot; SLOTS] = [EMPTY_SLOT; SLOTS];
+
+static STAGING: [[Slot; SLOTS]; CPUS] = [EMPTY_SLOTS; CPUS];
+
+/// How many slots are `READY`, or about to be, so flushing can skip the scan.
+static STAGED: AtomicUsize = AtomicUsize::new(0);
+
+/// Records dropped because their CPU's slots were full.
+static LOST: AtomicU64 = AtomicU64::new(0);
+
+const NOT_LOGGING: AtomicBool = AtomicBool::new(false);
+const NOT_DEFERRED: AtomicUsize = AtomicUsize::new(0);
+
+/// Set while a CPU is handing records to the sinks.
+static LOGGING: [AtomicBool; CPUS] = [NOT_LOGGING; CPUS];
+
+/// How many `DeferGuard`s each CPU holds.
+static DEFERRED: [AtomicUsize; CPUS] = [NOT_DEFERRED; CPUS];
+
+/// Stages every record the current CPU logs until it's dropped. Take one
+/// with interrupts disabled, and drop it before they're enabled again, so
+/// that the thread holding it can't move to another CPU.
+pub fn defer() -> DeferGuard {
+    let cpu = interrupts::current_cpu() % CPUS;
+    DEFERRED[cpu].fetch_add(1, Ordering::Relaxed);
+    DeferGuard { cpu }
+}
+
+pub struct DeferGuard {
+    cpu: usize
+}
+
+impl Drop for DeferGuard {
+    fn drop(&mut self) {
+        DEFERRED[self.cpu].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Runs `log` if records can go straight to the sinks on this CPU, and
+/// returns false if they have to be staged. Interrupts are disabled while
+/// `log` runs, so the CPU can't switch threads with its flag set.
+pub(crate) fn with_sinks<F: FnOnce()>(log: F) -> bool {
+    let interrupts = interrupts::disable();
+    let cpu = interrupts::current_cpu() % CPUS;
+
+    if DEFERRED[cpu].load(Ordering::Relaxed) > 0 || LOGGING[cpu].swap(true, Ordering::Acquire) {
+        interrupts::restore(interrupts);
+        return false;
+    }
+
+    log();
+
+    LOGGING[cpu].store(false, Ordering::Release);
+    interrupts::restore(interrupts);
+    true
+}
+
+/// Copies a record into a free slot.
+pub(crate) fn stage(record: &log::Record, sequence: u64, timestamp: u64) {
+    let cpu = interrupts::current_cpu();
+
+    for slot in STAGING[cpu % CPUS].iter() {
+        if slot.state.compare_exchange(EMPTY, WRITING, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            continue;
+        }
+
+        let staged = unsafe { &mut *slot.record.get() };
+        staged.sequence = sequence;
+        staged.timestamp = timestamp;
+        staged.cpu = cpu;
+        staged.level = record.level();
+
+        let module = record.module_path().unwrap_or(record.target());
+        let mut text = Truncated::new(&mut staged.module);
+        let _ = text.write_str(module);
+        staged.module_length = text.length();
+
+        let mut text = Truncated::new(&mut staged.message);
+        let _ = write!(text, "{}", record.args());
+        staged.message_length = text.length();
+
+        STAGED.fetch_add(1, Ordering::Release);
+        slot.state.store(READY, Ordering::Release);
+        return;
+    }
+
+    LOST.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Hands staged records to `dispatch`, oldest first within each batch.
+/// Only call it from inside `with_sinks`.
+pub(crate) fn flush(dispatch: fn(&Record)) {
+    let lost = LOST.swap(0, Ordering::Relaxed);
+    if lost > 0 {
+        let mut buffer = [0u8; 64];
+        let mut message = Truncated::new(&mut buffer);
+        let _ = write!(message, "{} log records lost while the staging buffers were full", lost);
+
+        dispatch(&Record {
+            sequence: dmesg::next_sequence(),
+            timestamp: 0,
+            cpu: interrupts::current_cpu(),
+            level: Level::Warn,
+            module: module_path!(),
+            message: message.as_str()
+        });
+    }
+
+    while STAGED.load(Ordering::Acquire) > 0 {
+        let mut batch: [Option<(u64, &Slot)>; BATCH] = [None; BATCH];
+        let mut count = 0;
+
+        for slot in STAGING.iter().flat_map(|slots| slots.iter()) {
+            if count == BATCH {
+                break;
+            }
+
+            if slot.state.compare_exchange(READY, FLUSHING, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                let sequence = unsafe { (*slot.record.get()).sequence };
+                batch[count] = Some((sequence, slot));
+                count += 1;
+            }
+        }
+
+        // Another CPU took them.
+        if count == 0 {
+            break;
+        }
+
+        batch[..count].sort_unstable_by_key(|entry| entry.map(|(sequence, _)| sequence));
+
+        for (_, slot) in batch[..count].iter().filter_map(|entry| *entry) {
+            let staged = unsafe { &*slot.record.get() };
+
+            dispatch(&Record {
+                sequence: staged.sequence,
+                timestamp: staged.timestamp,
+                cpu: staged.cpu,
+                level: staged.level,
+                module: str::from_utf8(&staged.module[..staged.module_length]).unwrap_or("?"),
+                message: str::from_utf8(&staged.message[..staged.message_length]).unwrap_or("?")
+            });
+
+            slot.state.store(EMPTY, Ordering::Release);
+            STAGED.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
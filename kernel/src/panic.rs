@@ -1,24 +1,143 @@
+//! What happens when the kernel panics.
+//!
+//! The panic handler can't trust anything: a lock may be held by the code
+//! that panicked, the allocator may be broken, and other CPUs and IRQ
+//! handlers would carry on logging over the message. So it disables
+//! interrupts, stops the other CPUs, and writes the message and a backtrace
+//! through functions the platform registers with `init`, which take no
+//! locks. Then it halts, or reboots once `set_reboot_timeout` has passed.
 
-/// This function is called on panic.
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering}
+};
+use spin::Once;
+
+use crate::Platform;
+
+struct PanicOps {
+    disable_interrupts: fn() -> bool,
+    current_cpu: fn() -> usize,
+    stop_other_cpus: fn(),
+    write: fn(&str),
+    backtrace: fn(&mut dyn FnMut(usize)),
+    delay: fn(u64),
+    halt: fn() -> !,
+    reboot: fn() -> !
+}
+
+static OPS: Once<PanicOps> = Once::new();
+
+/// How many times the panic handler has been entered.
+static PANICS: AtomicUsize = AtomicUsize::new(0);
+
+/// The CPU that panicked first.
+static PANIC_CPU: AtomicUsize = AtomicUsize::new(0);
+
+const NO_REBOOT: u64 = u64::max_value();
+static REBOOT_TIMEOUT: AtomicU64 = AtomicU64::new(NO_REBOOT);
+
+/// The most frames printed, in case the frame pointers lead somewhere silly.
+const MAX_FRAMES: usize = 32;
+
+pub fn init<P: Platform>() {
+    OPS.call_once(|| PanicOps {
+        disable_interrupts: P::disable_interrupts,
+        current_cpu: P::current_cpu,
+        stop_other_cpus: P::stop_other_cpus,
+        write: P::panic_write,
+        backtrace: P::backtrace,
+        delay: P::delay,
+        halt: P::halt,
+        reboot: P::reboot
+    });
+}
+
+/// Reboots this many seconds after a panic, instead of halting. `None`, the
+/// default, halts.
+pub fn set_reboot_timeout(seconds: Option<u64>) {
+    REBOOT_TIMEOUT.store(seconds.unwrap_or(NO_REBOOT), Ordering::Relaxed);
+}
+
+/// Whether some CPU has panicked. Handlers that could be interrupting a panic,
+/// like the NMI handler, should halt instead of carrying on.
+pub fn is_panicking() -> bool {
+    PANICS.load(Ordering::Relaxed) > 0
+}
+
+struct PanicWriter(fn(&str));
+
+impl Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        (self.0)(s);
+        Ok(())
+    }
+}
+
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
+    let ops = match OPS.r#try() {
+        Some(ops) => ops,
+        // Too early to do anything about it.
+        None => loop {}
+    };
+
+    (ops.disable_interrupts)();
+    let cpu = (ops.current_cpu)();
+
+    match PANICS.fetch_add(1, Ordering::SeqCst) {
+        0 => PANIC_CPU.store(cpu, Ordering::SeqCst),
+
+        // Another CPU got here first and is reporting its own panic.
+        _ if PANIC_CPU.load(Ordering::SeqCst) != cpu => (ops.halt)(),
+
+        // Reporting the panic panicked. Say so once, in case it was the
+        // report that was broken, and give up.
+        1 => {
+            (ops.write)("\nPanicked while handling a panic, halting\n");
+            (ops.halt)()
+        },
+        _ => (ops.halt)()
+    }
+
+    (ops.stop_other_cpus)();
+
+    let mut out = PanicWriter(ops.write);
     let message = info.message();
     let default = &format_args!("No message given");
     let message = message.unwrap_or(default);
-    
-    if let Some(location) = info.location() {
-        log::error!("Panic at {}:{}: {:?}", 
-            location.file(),
-            location.line(),
-            message
-        );
-    } else {
-        log::error!("Panic: {:?}", message);
+
+    let _ = writeln!(out, "\nKernel panic on CPU {}", cpu);
+    match info.location() {
+        Some(location) => { let _ = writeln!(out, "  at {}:{}: {}", location.file(), location.line(), message); },
+        None => { let _ = writeln!(out, "  {}", message); }
+    }
+
+    let _ = writeln!(out, "Backtrace:");
+    let mut frame = 0;
+    (ops.backtrace)(&mut |address| {
+        if frame < MAX_FRAMES {
+            let _ = writeln!(out, "  #{:<2} {:#018x}", frame, address);
+        }
+        frame += 1;
+    });
+
+    match REBOOT_TIMEOUT.load(Ordering::Relaxed) {
+        NO_REBOOT => {
+            let _ = writeln!(out, "System halted");
+            (ops.halt)()
+        },
+        seconds => {
+            let _ = writeln!(out, "Rebooting in {} seconds", seconds);
+            (ops.delay)(seconds * 1000);
+            (ops.reboot)()
+        }
     }
-    loop {}
 }
 
+#[cfg(not(test))]
 #[alloc_error_handler]
-fn alloc_error(_layout: core::alloc::Layout) -> ! {
-    unimplemented!()
-}
\ No newline at end of file
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    panic!("Out of memory allocating {} bytes aligned to {}", layout.size(), layout.align())
+}
@@ -1,16 +1,97 @@
 
+use alloc::{
+    sync::Arc,
+    vec::Vec
+};
+
 use super::{
-    PlatformEvent,
-    device::Device
+    EventEnvelope,
+    command_line::CommandLine,
+    device::Device,
+    driver::PciDriver,
+    error::PlatformError,
+    firmware::Firmware,
+    hal::Hal,
+    statistics::{InterruptStatistics, MemoryRegion, MemoryStatistics},
+    thread::{ThreadContext, ThreadEntry}
 };
 
-pub trait Platform: Sized {
+pub trait Platform: Sized + 'static {
     type DeviceID: core::marker::Copy + core::fmt::Debug + core::cmp::PartialEq + core::cmp::Eq + core::hash::Hash;
     type Device: Device<Self>;
-    type Error: core::fmt::Debug;
+    type Error: PlatformError;
     type File;
 
     fn init(&mut self);
-    fn poll_event(&self) -> Option<PlatformEvent<Self>>;
+    fn poll_event(&self) -> Option<EventEnvelope<Self>>;
+
+    /// The device a `DeviceConnected` event is about. Returns `None` once the
+    /// device has been disconnected.
+    fn device(&self, id: Self::DeviceID) -> Option<Arc<Self::Device>>;
     fn sleep(&self);
+
+    /// Options from the boot configuration file and command line.
+    fn command_line(&self) -> &CommandLine;
+
+    /// Offers PCI devices found from now on to a portable driver, after the
+    /// platform's own drivers.
+    fn register_pci_driver(&self, driver: PciDriver<Self>);
+
+    /// Called once a device has been polled for a `DevicePollable` event, so
+    /// that an interrupt masked until then can be unmasked.
+    fn device_polled(&self, id: Self::DeviceID);
+
+    /// How portable drivers reach the hardware.
+    fn hal() -> Hal<Self>;
+
+    /// The firmware's runtime services, if it left any.
+    fn firmware() -> Option<&'static dyn Firmware<Self>>;
+
+    /// A monotonic counter used to measure latencies.
+    fn timestamp(&self) -> u64;
+
+    /// How many times `timestamp` increments per second.
+    fn timestamp_frequency(&self) -> u64;
+
+    /// The total number of events dropped because the event buffer was full.
+    fn event_overflows(&self) -> u64;
+
+    fn interrupt_statistics(&self) -> InterruptStatistics;
+    fn memory_statistics(&self) -> MemoryStatistics;
+
+    /// The firmware's memory map, in address order.
+    fn memory_map(&self) -> Vec<MemoryRegion>;
+
+    /// Lays out `stack` so that switching to the returned context calls
+    /// `entry(argument)`, with interrupts still disabled.
+    fn init_thread_context(stack: &mut [u8], entry: ThreadEntry, argument: usize) -> ThreadContext;
+
+    /// Saves the running thread's registers into `from` and resumes `to`.
+    /// Returns when something switches back to `from`.
+    unsafe fn switch_thread(from: *mut ThreadContext, to: *const ThreadContext);
+
+    /// Disables interrupts, returning whether they were enabled before.
+    fn disable_interrupts() -> bool;
+    fn restore_interrupts(enabled: bool);
+
+    /// The number of the CPU we're running on, below `cpu_count`.
+    fn current_cpu() -> usize;
+    fn cpu_count() -> usize;
+
+    /// Stops every other CPU for good. Called while panicking, with
+    /// interrupts disabled.
+    fn stop_other_cpus();
+
+    /// Writes to the serial port and the screen for the panic handler. Mustn't
+    /// take locks or allocate, since whatever panicked may be holding them.
+    fn panic_write(text: &str);
+
+    /// Calls `frame` with the return address of each frame on the stack,
+    /// innermost first.
+    fn backtrace(frame: &mut dyn FnMut(usize));
+
+    /// Busy-waits, with interrupts disabled.
+    fn delay(milliseconds: u64);
+    fn halt() -> !;
+    fn reboot() -> !;
 }
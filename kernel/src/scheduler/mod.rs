@@ -0,0 +1,398 @@
+//! Scheduling policy for kernel threads.
+//!
+//! Threads belong to a priority class and are run round-robin within it,
+//! always preferring the highest class with anything ready. Each CPU has its
+//! own run queue; a CPU with nothing to do steals from the busiest other one.
+//!
+//! Nothing in here switches stacks or touches hardware. The caller feeds in
+//! clock ticks and asks what to run next, which keeps the policy testable on
+//! the host.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    vec::Vec
+};
+
+use crate::thread::ThreadId;
+
+#[cfg(test)]
+mod tests;
+
+pub const PRIORITY_CLASSES: usize = 5;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Realtime = 0,
+    High = 1,
+    Normal = 2,
+    Low = 3,
+    Idle = 4
+}
+
+impl Priority {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Clock ticks a thread runs for before others in its class get a turn.
+    /// Lower classes get longer slices, since they're preempted by everything
+    /// above them anyway.
+    pub fn time_slice(self) -> u64 {
+        match self {
+            Priority::Realtime => 1,
+            Priority::High => 2,
+            Priority::Normal => 4,
+            Priority::Low => 8,
+            Priority::Idle => 8
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ThreadState {
+    Ready,
+    Running,
+
+    /// Waiting for something else to call `wake` or `unpark`.
+    Blocked,
+
+    /// Waiting for the clock to reach a deadline.
+    Sleeping,
+
+    Finished
+}
+
+/// A decision to stop running `from` on a CPU and start running `to`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Switch {
+    pub from: ThreadId,
+    pub to: ThreadId
+}
+
+struct Entry {
+    priority: Priority,
+    state: ThreadState,
+
+    /// The CPU whose run queue the thread goes back on.
+    cpu: usize,
+    slice_remaining: u64,
+    wake_at: Option<u64>,
+
+    /// Set by `unpark` on a thread that wasn't parked, so its next `park`
+    /// returns straight away.
+    unpark_pending: bool
+}
+
+#[derive(Default)]
+struct RunQueue {
+    classes: [VecDeque<ThreadId>; PRIORITY_CLASSES],
+    current: Option<ThreadId>
+}
+
+impl RunQueue {
+    fn len(&self) -> usize {
+        self.classes.iter().map(|class| class.len()).sum()
+    }
+
+    fn highest_ready(&self) -> Option<Priority> {
+        PRIORITIES.iter().cloned().find(|priority| !self.classes[priority.index()].is_empty())
+    }
+
+    fn remove(&mut self, id: ThreadId) {
+        for class in self.classes.iter_mut() {
+            class.retain(|queued| *queued != id);
+        }
+    }
+}
+
+const PRIORITIES: [Priority; PRIORITY_CLASSES] = [
+    Priority::Realtime,
+    Priority::High,
+    Priority::Normal,
+    Priority::Low,
+    Priority::Idle
+];
+
+pub struct Scheduler {
+    entries: BTreeMap<ThreadId, Entry>,
+    cpus: Vec<RunQueue>,
+
+    /// Sleeping threads ordered by deadline.
+    sleepers: BTreeSet<(u64, ThreadId)>
+}
+
+impl Scheduler {
+    pub fn new(cpus: usize) -> Self {
+        let mut run_queues = Vec::with_capacity(cpus);
+        for _ in 0..cpus {
+            run_queues.push(RunQueue::default());
+        }
+
+        Scheduler {
+            entries: BTreeMap::new(),
+            cpus: run_queues,
+            sleepers: BTreeSet::new()
+        }
+    }
+
+    /// Adds a new thread, ready to run on `cpu`.
+    pub fn add(&mut self, id: ThreadId, priority: Priority, cpu: usize) {
+        self.entries.insert(id, Entry {
+            priority,
+            state: ThreadState::Ready,
+            cpu,
+            slice_remaining: priority.time_slice(),
+            wake_at: None,
+            unpark_pending: false
+        });
+
+        self.cpus[cpu].classes[priority.index()].push_back(id);
+    }
+
+    /// Adds a thread that's already running on `cpu`, like the boot thread.
+    pub fn add_running(&mut self, id: ThreadId, priority: Priority, cpu: usize) {
+        self.entries.insert(id, Entry {
+            priority,
+            state: ThreadState::Running,
+            cpu,
+            slice_remaining: priority.time_slice(),
+            wake_at: None,
+            unpark_pending: false
+        });
+
+        self.cpus[cpu].current = Some(id);
+    }
+
+    /// Forgets a thread altogether. It mustn't be running on any CPU.
+    pub fn remove(&mut self, id: ThreadId) {
+        if let Some(entry) = self.entries.remove(&id) {
+            self.cpus[entry.cpu].remove(id);
+            if let Some(deadline) = entry.wake_at {
+                self.sleepers.remove(&(deadline, id));
+            }
+        }
+    }
+
+    pub fn state(&self, id: ThreadId) -> Option<ThreadState> {
+        self.entries.get(&id).map(|entry| entry.state)
+    }
+
+    pub fn priority(&self, id: ThreadId) -> Option<Priority> {
+        self.entries.get(&id).map(|entry| entry.priority)
+    }
+
+    pub fn set_priority(&mut self, id: ThreadId, priority: Priority) {
+        let (state, cpu) = match self.entries.get_mut(&id) {
+            Some(entry) => {
+                entry.priority = priority;
+                entry.slice_remaining = priority.time_slice();
+                (entry.state, entry.cpu)
+            },
+            None => return
+        };
+
+        if state == ThreadState::Ready {
+            self.cpus[cpu].remove(id);
+            self.cpus[cpu].classes[priority.index()].push_back(id);
+        }
+    }
+
+    pub fn current(&self, cpu: usize) -> Option<ThreadId> {
+        self.cpus[cpu].current
+    }
+
+    /// Threads waiting in `cpu`'s run queue, not counting the running one.
+    pub fn ready_count(&self, cpu: usize) -> usize {
+        self.cpus[cpu].len()
+    }
+
+    /// Threads that have finished and aren't running anywhere, so their
+    /// resources can be freed.
+    pub fn finished(&self) -> Vec<ThreadId> {
+        self.entries.iter()
+            .filter(|(id, entry)| {
+                entry.state == ThreadState::Finished
+                    && !self.cpus.iter().any(|cpu| cpu.current == Some(**id))
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Accounts for a clock tick on `cpu` at time `now`: wakes any sleepers
+    /// whose deadline has passed and charges the running thread for its time.
+    /// Returns true if `cpu` should call `schedule`.
+    pub fn tick(&mut self, cpu: usize, now: u64) -> bool {
+        self.wake_sleepers(now);
+
+        let highest_ready = self.cpus[cpu].highest_ready();
+
+        let current = match self.cpus[cpu].current {
+            Some(current) => current,
+            None => return highest_ready.is_some()
+        };
+
+        let entry = self.entries.get_mut(&current).unwrap();
+        if entry.state != ThreadState::Running {
+            return true;
+        }
+
+        entry.slice_remaining = entry.slice_remaining.saturating_sub(1);
+
+        match highest_ready {
+            Some(priority) if priority < entry.priority => true,
+            Some(priority) if priority == entry.priority => entry.slice_remaining == 0,
+            _ => {
+                // Nobody else wants the CPU, so start a fresh slice.
+                if entry.slice_remaining == 0 {
+                    entry.slice_remaining = entry.priority.time_slice();
+                }
+                false
+            }
+        }
+    }
+
+    /// Picks what `cpu` should run next. A running thread goes to the back of
+    /// its class, so this also serves as a yield. Returns `None` if the
+    /// current thread should carry on, or if there's nothing runnable at all.
+    pub fn schedule(&mut self, cpu: usize) -> Option<Switch> {
+        let current = self.cpus[cpu].current;
+
+        if let Some(current) = current {
+            let entry = self.entries.get_mut(&current).unwrap();
+            if entry.state == ThreadState::Running {
+                entry.state = ThreadState::Ready;
+                entry.slice_remaining = entry.priority.time_slice();
+                self.cpus[cpu].classes[entry.priority.index()].push_back(current);
+            }
+        }
+
+        let next = match self.pop_ready(cpu).or_else(|| self.steal(cpu)) {
+            Some(next) => next,
+            None => return None
+        };
+
+        let entry = self.entries.get_mut(&next).unwrap();
+        entry.state = ThreadState::Running;
+        entry.cpu = cpu;
+        self.cpus[cpu].current = Some(next);
+
+        match current {
+            Some(current) if current != next => Some(Switch { from: current, to: next }),
+            _ => None
+        }
+    }
+
+    /// Stops `id` from running until `wake` is called.
+    pub fn block(&mut self, id: ThreadId) {
+        self.set_waiting(id, ThreadState::Blocked);
+    }
+
+    /// Blocks `id` unless an `unpark` arrived since it last parked. Returns
+    /// true if the thread was blocked.
+    pub fn park(&mut self, id: ThreadId) -> bool {
+        let pending = match self.entries.get_mut(&id) {
+            Some(entry) => core::mem::replace(&mut entry.unpark_pending, false),
+            None => return false
+        };
+
+        if pending {
+            false
+        } else {
+            self.block(id);
+            true
+        }
+    }
+
+    /// Wakes a parked thread, or makes its next `park` return immediately.
+    pub fn unpark(&mut self, id: ThreadId) {
+        match self.state(id) {
+            Some(ThreadState::Blocked) => self.wake(id),
+            Some(ThreadState::Finished) | None => {},
+            Some(_) => self.entries.get_mut(&id).unwrap().unpark_pending = true
+        }
+    }
+
+    /// Puts `id` to sleep until the clock reaches `deadline`.
+    pub fn sleep_until(&mut self, id: ThreadId, deadline: u64) {
+        self.set_waiting(id, ThreadState::Sleeping);
+
+        if let Some(entry) = self.entries.get_mut(&id) {
+            entry.wake_at = Some(deadline);
+            self.sleepers.insert((deadline, id));
+        }
+    }
+
+    /// Makes a blocked or sleeping thread ready again.
+    pub fn wake(&mut self, id: ThreadId) {
+        let entry = match self.entries.get_mut(&id) {
+            Some(entry) => entry,
+            None => return
+        };
+
+        match entry.state {
+            ThreadState::Blocked | ThreadState::Sleeping => {},
+            _ => return
+        }
+
+        if let Some(deadline) = entry.wake_at.take() {
+            self.sleepers.remove(&(deadline, id));
+        }
+
+        let is_current = self.cpus[entry.cpu].current == Some(id);
+        if is_current {
+            // It hasn't been switched away from yet, so just let it carry on.
+            entry.state = ThreadState::Running;
+        } else {
+            entry.state = ThreadState::Ready;
+            entry.slice_remaining = entry.priority.time_slice();
+            self.cpus[entry.cpu].classes[entry.priority.index()].push_back(id);
+        }
+    }
+
+    pub fn exit(&mut self, id: ThreadId) {
+        self.set_waiting(id, ThreadState::Finished);
+    }
+
+    fn set_waiting(&mut self, id: ThreadId, state: ThreadState) {
+        let entry = match self.entries.get_mut(&id) {
+            Some(entry) => entry,
+            None => return
+        };
+
+        if entry.state == ThreadState::Ready {
+            self.cpus[entry.cpu].remove(id);
+        }
+
+        entry.state = state;
+    }
+
+    fn wake_sleepers(&mut self, now: u64) {
+        let due: Vec<ThreadId> = self.sleepers.iter()
+            .take_while(|(deadline, _)| *deadline <= now)
+            .map(|(_, id)| *id)
+            .collect();
+
+        for id in due {
+            self.wake(id);
+        }
+    }
+
+    fn pop_ready(&mut self, cpu: usize) -> Option<ThreadId> {
+        let queue = &mut self.cpus[cpu];
+        queue.highest_ready().and_then(|priority| queue.classes[priority.index()].pop_front())
+    }
+
+    /// Takes the highest-priority thread from the back of the busiest other
+    /// CPU's queue. The newest thread there has the least in that CPU's
+    /// cache, so it loses the least by moving, and the ones at the front
+    /// still run next where they are.
+    fn steal(&mut self, cpu: usize) -> Option<ThreadId> {
+        let victim = (0..self.cpus.len())
+            .filter(|other| *other != cpu)
+            .max_by_key(|other| self.cpus[*other].len())?;
+
+        let queue = &mut self.cpus[victim];
+        let priority = queue.highest_ready()?;
+        queue.classes[priority.index()].pop_back()
+    }
+}
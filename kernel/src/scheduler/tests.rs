@@ -0,0 +1,149 @@
+use super::*;
+
+/// Stands in for the platform: a clock and a fixed set of CPUs, driving the
+/// scheduler the way the timer interrupt and thread calls would.
+struct MockPlatform {
+    scheduler: Scheduler,
+    now: u64
+}
+
+impl MockPlatform {
+    fn new(cpus: usize) -> Self {
+        MockPlatform { scheduler: Scheduler::new(cpus), now: 0 }
+    }
+
+    /// Advances the clock by one tick on `cpu`, switching threads if the
+    /// scheduler asks for it. Returns the thread running afterwards.
+    fn tick(&mut self, cpu: usize) -> Option<ThreadId> {
+        self.now += 1;
+        if self.scheduler.tick(cpu, self.now) {
+            self.scheduler.schedule(cpu);
+        }
+        self.scheduler.current(cpu)
+    }
+
+    fn run(&mut self, cpu: usize, ticks: usize) -> Vec<ThreadId> {
+        (0..ticks).map(|_| self.tick(cpu).unwrap()).collect()
+    }
+}
+
+fn id(n: u64) -> ThreadId {
+    ThreadId(n)
+}
+
+#[test]
+fn round_robin_within_a_class() {
+    let mut platform = MockPlatform::new(1);
+    platform.scheduler.add_running(id(0), Priority::High, 0);
+    platform.scheduler.add(id(1), Priority::High, 0);
+
+    assert_eq!(Priority::High.time_slice(), 2);
+
+    // Each switch happens on the tick that uses up the slice.
+    assert_eq!(platform.run(0, 8), vec![id(0), id(1), id(1), id(0), id(0), id(1), id(1), id(0)]);
+}
+
+#[test]
+fn higher_priority_runs_first() {
+    let mut platform = MockPlatform::new(1);
+    platform.scheduler.add_running(id(0), Priority::Normal, 0);
+    platform.scheduler.add(id(1), Priority::Low, 0);
+    platform.scheduler.add(id(2), Priority::Realtime, 0);
+
+    assert_eq!(platform.tick(0), Some(id(2)));
+    assert_eq!(platform.scheduler.state(id(0)), Some(ThreadState::Ready));
+
+    // The low-priority thread never gets a look in while others are ready.
+    assert!(!platform.run(0, 20).contains(&id(1)));
+}
+
+#[test]
+fn lone_thread_keeps_running() {
+    let mut platform = MockPlatform::new(1);
+    platform.scheduler.add_running(id(0), Priority::Normal, 0);
+
+    assert_eq!(platform.run(0, 10), vec![id(0); 10]);
+}
+
+#[test]
+fn yield_goes_to_the_back_of_the_class() {
+    let mut scheduler = Scheduler::new(1);
+    scheduler.add_running(id(0), Priority::Normal, 0);
+    scheduler.add(id(1), Priority::Normal, 0);
+    scheduler.add(id(2), Priority::Normal, 0);
+
+    assert_eq!(scheduler.schedule(0), Some(Switch { from: id(0), to: id(1) }));
+    assert_eq!(scheduler.schedule(0), Some(Switch { from: id(1), to: id(2) }));
+    assert_eq!(scheduler.schedule(0), Some(Switch { from: id(2), to: id(0) }));
+}
+
+#[test]
+fn sleeping_thread_wakes_at_its_deadline() {
+    let mut platform = MockPlatform::new(1);
+    platform.scheduler.add_running(id(0), Priority::Normal, 0);
+    platform.scheduler.add(id(1), Priority::High, 0);
+    platform.scheduler.schedule(0);
+
+    platform.scheduler.sleep_until(id(1), 5);
+    assert_eq!(platform.scheduler.state(id(1)), Some(ThreadState::Sleeping));
+    platform.scheduler.schedule(0);
+
+    assert_eq!(platform.run(0, 4), vec![id(0); 4]);
+    assert_eq!(platform.tick(0), Some(id(1)));
+}
+
+#[test]
+fn blocked_thread_waits_for_wake() {
+    let mut platform = MockPlatform::new(1);
+    platform.scheduler.add_running(id(0), Priority::Normal, 0);
+    platform.scheduler.add(id(1), Priority::Normal, 0);
+
+    platform.scheduler.block(id(1));
+    assert_eq!(platform.scheduler.ready_count(0), 0);
+    assert_eq!(platform.run(0, 10), vec![id(0); 10]);
+
+    platform.scheduler.wake(id(1));
+    assert_eq!(platform.scheduler.state(id(1)), Some(ThreadState::Ready));
+    assert!(platform.run(0, 10).contains(&id(1)));
+}
+
+#[test]
+fn unpark_before_park_is_not_lost() {
+    let mut scheduler = Scheduler::new(1);
+    scheduler.add_running(id(0), Priority::Normal, 0);
+
+    scheduler.unpark(id(0));
+    assert!(!scheduler.park(id(0)));
+    assert_eq!(scheduler.state(id(0)), Some(ThreadState::Running));
+
+    assert!(scheduler.park(id(0)));
+    assert_eq!(scheduler.state(id(0)), Some(ThreadState::Blocked));
+}
+
+#[test]
+fn idle_cpu_steals_work() {
+    let mut scheduler = Scheduler::new(2);
+    scheduler.add_running(id(0), Priority::Normal, 0);
+    scheduler.add(id(1), Priority::Normal, 0);
+    scheduler.add(id(2), Priority::High, 0);
+    scheduler.add_running(id(3), Priority::Normal, 1);
+    scheduler.exit(id(3));
+
+    assert_eq!(scheduler.schedule(1), Some(Switch { from: id(3), to: id(2) }));
+    assert_eq!(scheduler.current(1), Some(id(2)));
+    assert_eq!(scheduler.ready_count(0), 1);
+}
+
+#[test]
+fn finished_threads_are_not_rescheduled() {
+    let mut scheduler = Scheduler::new(1);
+    scheduler.add_running(id(0), Priority::Normal, 0);
+    scheduler.add(id(1), Priority::Normal, 0);
+
+    scheduler.exit(id(0));
+    assert!(scheduler.finished().is_empty(), "still on the CPU");
+
+    assert_eq!(scheduler.schedule(0), Some(Switch { from: id(0), to: id(1) }));
+    assert_eq!(scheduler.finished(), vec![id(0)]);
+    assert_eq!(scheduler.schedule(0), None);
+}
@@ -0,0 +1,106 @@
+//! Boot options kept in firmware variables, so they can be changed without
+//! rebuilding the ESP.
+//!
+//! Each setting is a non-volatile variable in the kernel's own namespace,
+//! named after the option and holding its value as UTF-8. The platform reads
+//! them at boot, after the configuration file and before the load options,
+//! so a setting overrides the file and the load options override both.
+//! Changing one from the shell takes effect at the next boot.
+
+use alloc::{
+    string::String,
+    vec::Vec
+};
+use core::str;
+
+use crate::{
+    CommandLine,
+    ErrorKind,
+    Platform,
+    PlatformError,
+    firmware::{Firmware, Guid, Storage}
+};
+
+/// The namespace settings are kept in.
+pub const VENDOR: Guid = Guid::new(0x3c1d7a52, 0x9e4b, 0x4f1a, [0xb6, 0x27, 0x5d, 0x80, 0xe3, 0x19, 0xa4, 0x6c]);
+
+/// The longest value, in bytes.
+pub const MAX_VALUE: usize = 1024;
+
+fn firmware<P: Platform>() -> Result<&'static dyn Firmware<P>, P::Error> {
+    P::firmware().ok_or_else(|| ErrorKind::Unsupported.into())
+}
+
+/// Every setting, as boot options. Settings that can't be read are logged
+/// and left out.
+pub fn load<P: Platform>() -> CommandLine {
+    let mut command_line = CommandLine::new();
+
+    let settings = match list::<P>() {
+        Ok(settings) => settings,
+        Err(error) => {
+            if error.kind() != ErrorKind::Unsupported {
+                log::warn!("Couldn't read the saved settings: {}", error);
+            }
+            return command_line;
+        }
+    };
+
+    for (key, value) in settings {
+        command_line.set(&key, &value);
+    }
+
+    command_line
+}
+
+/// Every setting, as `(key, value)` pairs.
+pub fn list<P: Platform>() -> Result<Vec<(String, String)>, P::Error> {
+    let mut settings = Vec::new();
+
+    for key in firmware::<P>()?.variable_names(&VENDOR)? {
+        match get::<P>(&key) {
+            Ok(Some(value)) => settings.push((key, value)),
+            Ok(None) => {},
+            Err(error) => log::warn!("Couldn't read setting {}: {}", key, error)
+        }
+    }
+
+    Ok(settings)
+}
+
+/// A setting's value, or `None` if it isn't set.
+pub fn get<P: Platform>(key: &str) -> Result<Option<String>, P::Error> {
+    let mut buffer = [0u8; MAX_VALUE];
+
+    match firmware::<P>()?.variable(&VENDOR, key, &mut buffer) {
+        Ok(length) => match str::from_utf8(&buffer[..length]) {
+            Ok(value) => Ok(Some(value.into())),
+            Err(_) => {
+                log::warn!("Ignoring setting {}: the value isn't UTF-8", key);
+                Ok(None)
+            }
+        },
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error)
+    }
+}
+
+/// Saves a setting. Flags, which have no value, are saved as `1`, since an
+/// empty variable can't be stored. Values longer than `MAX_VALUE` are
+/// refused as unsupported.
+pub fn set<P: Platform>(key: &str, value: &str) -> Result<(), P::Error> {
+    let value = if value.is_empty() { "1" } else { value };
+    if value.len() > MAX_VALUE {
+        return Err(ErrorKind::Unsupported.into());
+    }
+
+    firmware::<P>()?.set_variable(&VENDOR, key, value.as_bytes(), Storage::NonVolatile)
+}
+
+/// Forgets a setting. Removing one that isn't set isn't an error.
+pub fn remove<P: Platform>(key: &str) -> Result<(), P::Error> {
+    match firmware::<P>()?.set_variable(&VENDOR, key, &[], Storage::NonVolatile) {
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        result => result
+    }
+}
@@ -0,0 +1,478 @@
+//! A debug shell on the serial console.
+//!
+//! The shell attaches to the first character device to connect, reads lines
+//! from it whenever it's polled, and writes the results back. Commands look
+//! at the kernel's state directly, so they run on the event loop between
+//! events.
+
+use alloc::{
+    string::String,
+    vec::Vec
+};
+use core::fmt::{self, Write};
+
+use log::LevelFilter;
+
+use crate::{
+    CharDevice,
+    Device,
+    Kernel,
+    Platform,
+    dmesg,
+    firmware::ResetKind,
+    hal::PciLocation,
+    log_sink::{self, LogFormat},
+    settings
+};
+
+const PROMPT: &str = "> ";
+
+/// Longer lines are cut off.
+const MAX_LINE: usize = 256;
+
+/// The most `hexdump` will print in one go.
+const MAX_DUMP: usize = 4096;
+
+const COMMANDS: &[(&str, &str)] = &[
+    ("help", "list commands"),
+    ("devices", "show the device tree"),
+    ("mem", "show heap usage"),
+    ("memmap", "show the firmware's memory map"),
+    ("ls", "list files on every filesystem"),
+    ("hexdump <address> [length]", "dump memory"),
+    ("pci <bus:device.function> [offset] [length]", "dump PCI configuration space"),
+    ("irqs", "show interrupt counters"),
+    ("events", "show event loop statistics"),
+    ("dmesg [count]", "show the kernel log, or its last few records"),
+    ("loglevel [module] [level|default]", "show or set log levels"),
+    ("logsink [name] [level|format]", "show log sinks, or set one's level or format"),
+    ("settings", "show the settings saved in firmware variables"),
+    ("set <key> [value]", "save a setting for the next boot"),
+    ("unset <key>", "forget a saved setting"),
+    ("date", "show the firmware's clock"),
+    ("reboot", "reboot now"),
+    ("poweroff", "turn the machine off")
+];
+
+pub struct Shell<P: Platform> {
+    console: Option<P::DeviceID>,
+    line: String,
+
+    /// Set after a carriage return, so that a line feed right after it
+    /// doesn't end a second, empty line.
+    after_cr: bool
+}
+
+impl <P: Platform> Shell<P> {
+    pub fn new() -> Self {
+        Shell { console: None, line: String::new(), after_cr: false }
+    }
+
+    /// The device the shell reads from, if it has one.
+    pub fn console(&self) -> Option<P::DeviceID> {
+        self.console
+    }
+
+    pub fn attach(&mut self, id: P::DeviceID, kernel: &Kernel<P>) {
+        log::info!("Debug shell attached to device {:?}", id);
+
+        self.console = Some(id);
+        self.line.clear();
+
+        if let Some(device) = kernel.device_registry.device(&id) {
+            if let Some(console) = device.as_char_device() {
+                let mut out = Console(console);
+                let _ = write!(out, "\nKernel debug shell. Type `help` for commands.\n{}", PROMPT);
+            }
+        }
+    }
+
+    pub fn detach(&mut self, id: P::DeviceID) {
+        if self.console == Some(id) {
+            self.console = None;
+            self.line.clear();
+        }
+    }
+
+    /// Handles whatever has been typed since the last poll.
+    pub fn poll(&mut self, kernel: &Kernel<P>) {
+        let device = match self.console.and_then(|id| kernel.device_registry.device(&id)) {
+            Some(device) => device,
+            None => return
+        };
+        let console = match device.as_char_device() {
+            Some(console) => console,
+            None => return
+        };
+
+        let mut buffer = [0u8; 64];
+        loop {
+            let count = match console.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(count) => count
+            };
+
+            for byte in buffer[..count].iter() {
+                self.input(*byte, console, kernel);
+            }
+        }
+    }
+
+    fn input(&mut self, byte: u8, console: &dyn CharDevice<P>, kernel: &Kernel<P>) {
+        let mut out = Console(console);
+        let after_cr = self.after_cr;
+        self.after_cr = byte == b'\r';
+
+        match byte {
+            b'\n' if after_cr => {},
+            b'\r' | b'\n' => {
+                let _ = write!(out, "\n");
+                let line = core::mem::replace(&mut self.line, String::new());
+
+                if let Err(error) = execute(line.trim(), &mut out, kernel) {
+                    let _ = writeln!(out, "{}", error);
+                }
+                let _ = write!(out, "{}", PROMPT);
+            },
+
+            // Backspace and delete both rub out the last character.
+            0x08 | 0x7f => {
+                if self.line.pop().is_some() {
+                    let _ = write!(out, "\x08 \x08");
+                }
+            },
+
+            0x20..=0x7e if self.line.len() < MAX_LINE => {
+                self.line.push(byte as char);
+                let _ = out.write_char(byte as char);
+            },
+
+            _ => {}
+        }
+    }
+}
+
+/// Writes to a character device, turning `\n` into `\r\n` for terminals.
+struct Console<'a, P: Platform>(&'a dyn CharDevice<P>);
+
+impl <'a, P: Platform> Write for Console<'a, P> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (index, piece) in s.split('\n').enumerate() {
+            if index > 0 {
+                self.0.write(b"\r\n").map_err(|_| fmt::Error)?;
+            }
+            self.0.write(piece.as_bytes()).map_err(|_| fmt::Error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Why a command didn't run.
+enum CommandError {
+    Unknown(String),
+    Usage(&'static str),
+    BadNumber(String),
+    BadLevel(String),
+    NotRam(u64),
+    Output
+}
+
+impl From<fmt::Error> for CommandError {
+    fn from(_: fmt::Error) -> Self {
+        CommandError::Output
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Unknown(command) => write!(f, "Unknown command `{}`. Type `help` for commands.", command),
+            CommandError::Usage(usage) => write!(f, "Usage: {}", usage),
+            CommandError::BadNumber(text) => write!(f, "`{}` isn't a number", text),
+            CommandError::BadLevel(text) => write!(f, "`{}` isn't a log level", text),
+            CommandError::NotRam(address) => write!(f, "{:#x} isn't in RAM", address),
+            CommandError::Output => write!(f, "Couldn't write to the console")
+        }
+    }
+}
+
+fn execute<P: Platform>(line: &str, out: &mut dyn Write, kernel: &Kernel<P>) -> Result<(), CommandError> {
+    let arguments: Vec<&str> = line.split_whitespace().collect();
+
+    let command = match arguments.first() {
+        Some(command) => *command,
+        None => return Ok(())
+    };
+
+    match command {
+        "help" => {
+            for (usage, description) in COMMANDS.iter() {
+                writeln!(out, "  {:<44} {}", usage, description)?;
+            }
+        },
+
+        "devices" => write!(out, "{}", kernel.device_registry.tree())?,
+        "mem" => write!(out, "{}", kernel.platform.memory_statistics())?,
+
+        "memmap" => {
+            for region in kernel.platform.memory_map() {
+                writeln!(out, "  {:#014x}-{:#014x}  {:>8} KiB  {}", region.start,
+                    region.start + region.length.saturating_sub(1), region.length / 1024, region.kind)?;
+            }
+        },
+
+        "ls" => {
+            for id in kernel.device_registry.filesystem_devices() {
+                let device = match kernel.device_registry.device(&id) {
+                    Some(device) => device,
+                    None => continue
+                };
+
+                if let Some(filesystem) = device.as_filesystem() {
+                    writeln!(out, "Device {:?}:", id)?;
+                    match filesystem.list() {
+                        Ok(files) => for file in files { writeln!(out, "  {}", file)?; },
+                        Err(error) => writeln!(out, "  {}", error)?
+                    }
+                }
+            }
+        },
+
+        "hexdump" => {
+            const USAGE: &str = "hexdump <address> [length]";
+            let address = number(arguments.get(1).ok_or(CommandError::Usage(USAGE))?)?;
+            let length = match arguments.get(2) {
+                Some(length) => number(length)? as usize,
+                None => 256
+            };
+
+            // Memory is identity mapped, but only RAM is safe to read: holes
+            // fault and device registers can have side effects. The dump
+            // stops at the end of the region it starts in.
+            let region = kernel.platform.memory_map().into_iter()
+                .find(|region| region.contains(address) && region.kind.is_ram());
+            let region = match region {
+                Some(region) if address != 0 => region,
+                _ => return Err(CommandError::NotRam(address))
+            };
+
+            let end = region.start + region.length;
+            let length = (length.min(MAX_DUMP) as u64).min(end - address) as usize;
+
+            let bytes: Vec<u8> = (0..length)
+                .map(|offset| unsafe { core::ptr::read_volatile((address as usize + offset) as *const u8) })
+                .collect();
+            hexdump(out, address, &bytes)?;
+        },
+
+        "pci" => {
+            const USAGE: &str = "pci <bus:device.function> [offset] [length]";
+            let location = pci_location(arguments.get(1).ok_or(CommandError::Usage(USAGE))?)
+                .ok_or(CommandError::Usage(USAGE))?;
+            let offset = match arguments.get(2) {
+                Some(offset) => number(offset)? as u16 & !3,
+                None => 0
+            };
+            let length = match arguments.get(3) {
+                Some(length) => number(length)? as u16,
+                None => 64
+            };
+
+            let pci = P::hal().pci;
+            let mut bytes = Vec::new();
+            let mut register = offset;
+            while register < offset.saturating_add(length) && register < 0x1000 {
+                bytes.extend_from_slice(&pci.read_u32(location, register).to_le_bytes());
+                register += 4;
+            }
+
+            hexdump(out, offset as u64, &bytes)?;
+        },
+
+        "irqs" => write!(out, "{}", kernel.interrupt_statistics())?,
+        "events" => write!(out, "{}", kernel.event_statistics())?,
+        "dmesg" => {
+            let snapshot = dmesg::snapshot();
+            let total = snapshot.records().count();
+            let count = match arguments.get(1) {
+                Some(count) => number(count)? as usize,
+                None => total
+            };
+
+            if snapshot.dropped() > 0 && count >= total {
+                writeln!(out, "({} older records dropped)", snapshot.dropped())?;
+            }
+
+            let frequency = kernel.platform.timestamp_frequency();
+            for record in snapshot.records().skip(total.saturating_sub(count)) {
+                let micros = record.micros(frequency);
+                writeln!(out, "[{:>5}.{:06}] {} {:<5} {}: {}", micros / 1_000_000, micros % 1_000_000,
+                    record.cpu, record.level, record.module, record.message)?;
+            }
+        },
+
+        "loglevel" => {
+            const USAGE: &str = "loglevel [module] [level|default]";
+            match arguments.len() {
+                1 => {
+                    let (default, filters) = dmesg::filters();
+                    writeln!(out, "  {:<40} {}", "(default)", default)?;
+                    for (module, level) in filters {
+                        writeln!(out, "  {:<40} {}", module, level)?;
+                    }
+                },
+                2 => dmesg::set_default_level(level_filter(arguments[1])?),
+                3 if arguments[2] == "default" => dmesg::clear_filter(arguments[1]),
+                3 => if !dmesg::set_filter(arguments[1], level_filter(arguments[2])?) {
+                    writeln!(out, "Too many log filters")?;
+                },
+                _ => return Err(CommandError::Usage(USAGE))
+            }
+        },
+
+        "logsink" => {
+            const USAGE: &str = "logsink [name] [level|format]";
+            match arguments.len() {
+                1 => {
+                    for sink in log_sink::sinks() {
+                        writeln!(out, "  {:<20} {:<6} {}", sink.name, sink.level, sink.format)?;
+                    }
+                },
+                3 => {
+                    let found = match (arguments[2].parse::<LevelFilter>(), arguments[2].parse::<LogFormat>()) {
+                        (Ok(level), _) => log_sink::set_level(arguments[1], level),
+                        (_, Ok(format)) => log_sink::set_format(arguments[1], format),
+                        _ => return Err(CommandError::Usage(USAGE))
+                    };
+
+                    if !found {
+                        writeln!(out, "No log sink called {}", arguments[1])?;
+                    }
+                },
+                _ => return Err(CommandError::Usage(USAGE))
+            }
+        },
+
+        "settings" => match settings::list::<P>() {
+            Ok(settings) => for (key, value) in settings {
+                writeln!(out, "  {}={}", key, value)?;
+            },
+            Err(error) => writeln!(out, "Couldn't read the settings: {}", error)?
+        },
+
+        "set" => {
+            const USAGE: &str = "set <key> [value]";
+            let key = arguments.get(1).ok_or(CommandError::Usage(USAGE))?;
+
+            // The value is the rest of the line, spaces and all.
+            let value = line.trim_start()[command.len()..].trim_start()[key.len()..].trim();
+
+            match settings::set::<P>(key, value) {
+                Ok(()) => writeln!(out, "Saved. It takes effect at the next boot.")?,
+                Err(error) => writeln!(out, "Couldn't save {}: {}", key, error)?
+            }
+        },
+
+        "unset" => {
+            let key = arguments.get(1).ok_or(CommandError::Usage("unset <key>"))?;
+
+            if let Err(error) = settings::remove::<P>(key) {
+                writeln!(out, "Couldn't remove {}: {}", key, error)?;
+            }
+        },
+
+        "date" => match P::firmware() {
+            Some(firmware) => match firmware.time() {
+                Ok(time) => writeln!(out, "{}", time)?,
+                Err(error) => writeln!(out, "Couldn't read the clock: {}", error)?
+            },
+            None => writeln!(out, "No firmware clock")?
+        },
+
+        "reboot" => {
+            if let Some(firmware) = P::firmware() {
+                writeln!(out, "Couldn't reboot through the firmware: {}", firmware.reset(ResetKind::Cold))?;
+            }
+            P::reboot()
+        },
+
+        "poweroff" => match P::firmware() {
+            Some(firmware) => writeln!(out, "Couldn't power off: {}", firmware.reset(ResetKind::Shutdown))?,
+            None => writeln!(out, "No firmware to power off with")?
+        },
+
+        command => return Err(CommandError::Unknown(command.into()))
+    }
+
+    Ok(())
+}
+
+/// A decimal number, or hex with a `0x` prefix.
+fn number(text: &str) -> Result<u64, CommandError> {
+    let parsed = if text.starts_with("0x") {
+        u64::from_str_radix(&text[2..], 16)
+    } else {
+        text.parse()
+    };
+
+    parsed.map_err(|_| CommandError::BadNumber(text.into()))
+}
+
+fn level_filter(text: &str) -> Result<LevelFilter, CommandError> {
+    text.parse().map_err(|_| CommandError::BadLevel(text.into()))
+}
+
+/// Parses `bus:device.function` in hex, with an optional segment in front,
+/// the way `PciLocation` prints.
+fn pci_location(text: &str) -> Option<PciLocation> {
+    let mut parts: Vec<&str> = text.split(':').collect();
+    let (device, function) = {
+        let mut last = parts.pop()?.split('.');
+        (last.next()?, last.next().unwrap_or("0"))
+    };
+    let bus = parts.pop()?;
+    let segment = parts.pop().unwrap_or("0");
+
+    if !parts.is_empty() {
+        return None;
+    }
+
+    let location = PciLocation {
+        segment: u16::from_str_radix(segment, 16).ok()?,
+        bus: u8::from_str_radix(bus, 16).ok()?,
+        device: u8::from_str_radix(device, 16).ok()?,
+        function: u8::from_str_radix(function, 16).ok()?
+    };
+
+    if location.device >= 32 || location.function >= 8 {
+        return None;
+    }
+
+    Some(location)
+}
+
+fn hexdump(out: &mut dyn Write, address: u64, bytes: &[u8]) -> fmt::Result {
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        write!(out, "{:016x}  ", address + line as u64 * 16)?;
+
+        for index in 0..16 {
+            match chunk.get(index) {
+                Some(byte) => write!(out, "{:02x} ", byte)?,
+                None => write!(out, "   ")?
+            }
+        }
+
+        write!(out, " |")?;
+        for byte in chunk {
+            let c = match byte {
+                0x20..=0x7e => *byte as char,
+                _ => '.'
+            };
+            out.write_char(c)?;
+        }
+        writeln!(out, "|")?;
+    }
+
+    Ok(())
+}
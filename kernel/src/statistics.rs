@@ -0,0 +1,202 @@
+use alloc::{
+    collections::BTreeMap,
+    vec::Vec
+};
+use core::fmt;
+
+/// How many times something happened and how long it took, in platform
+/// timestamp units.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LatencyCounter {
+    pub count: u64,
+    pub total: u64,
+    pub max: u64
+}
+
+impl LatencyCounter {
+    pub fn record(&mut self, duration: u64) {
+        self.count += 1;
+        self.total += duration;
+
+        if duration > self.max {
+            self.max = duration;
+        }
+    }
+
+    pub fn mean(&self) -> u64 {
+        if self.count == 0 { 0 } else { self.total / self.count }
+    }
+}
+
+impl fmt::Display for LatencyCounter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} times, mean {}, max {}", self.count, self.mean(), self.max)
+    }
+}
+
+/// Counts kept by the kernel's event loop.
+#[derive(Debug, Clone, Default)]
+pub struct EventStatistics {
+    /// Dispatch latency for each kind of event, keyed by `PlatformEvent::name`.
+    pub dispatched: BTreeMap<&'static str, LatencyCounter>,
+
+    /// Time events spent in the event buffer before being dispatched.
+    pub queued: LatencyCounter,
+
+    /// Events the platform dropped because the event buffer was full.
+    pub overflows: u64
+}
+
+impl fmt::Display for EventStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Event buffer overflows: {}", self.overflows)?;
+        writeln!(f, "  {:<20} {}", "(queued)", self.queued)?;
+
+        for (name, counter) in self.dispatched.iter() {
+            writeln!(f, "  {:<20} {}", name, counter)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VectorStatistics {
+    pub vector: u8,
+
+    /// How many times the vector fired on each CPU, indexed by CPU number.
+    pub per_cpu: Vec<u64>,
+
+    /// Time spent in the handler.
+    pub handler: LatencyCounter
+}
+
+impl VectorStatistics {
+    pub fn total(&self) -> u64 {
+        self.per_cpu.iter().sum()
+    }
+}
+
+/// Counts kept by the platform's interrupt handlers.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptStatistics {
+    /// Only vectors that have fired at least once are listed.
+    pub vectors: Vec<VectorStatistics>,
+    pub spurious: u64,
+    pub unknown: u64
+}
+
+impl fmt::Display for InterruptStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Spurious: {}, unknown: {}", self.spurious, self.unknown)?;
+
+        for vector in self.vectors.iter() {
+            write!(f, "  {:#04x}: {:>8}  [", vector.vector, vector.total())?;
+            for (cpu, count) in vector.per_cpu.iter().enumerate() {
+                if cpu > 0 { write!(f, " ")?; }
+                write!(f, "{}", count)?;
+            }
+            writeln!(f, "]  handler {}", vector.handler)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A range of the physical address space, from the firmware's memory map.
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryRegion {
+    pub start: u64,
+    pub length: u64,
+    pub kind: MemoryKind
+}
+
+impl MemoryRegion {
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.start && address - self.start < self.length
+    }
+}
+
+/// What a memory region is used for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Free RAM. The platform gives it to the heap.
+    Conventional,
+
+    /// The kernel image and what the boot loader allocated for it.
+    Loader,
+
+    /// RAM the firmware used until boot services exited. It's free now, and
+    /// also given to the heap.
+    BootServices,
+
+    /// The firmware's runtime services, which it still uses.
+    RuntimeServices,
+
+    /// ACPI tables, which can be reused once they've been read.
+    AcpiReclaimable,
+
+    /// Firmware storage that has to be kept, even across sleep.
+    AcpiNonVolatile,
+
+    /// Device registers. Reading them can have side effects.
+    Mmio,
+
+    /// RAM with errors in it.
+    Unusable,
+    Reserved
+}
+
+impl MemoryKind {
+    /// Whether the region is RAM, which can be read without side effects.
+    pub fn is_ram(&self) -> bool {
+        match self {
+            MemoryKind::Mmio | MemoryKind::Unusable | MemoryKind::Reserved => false,
+            _ => true
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemoryKind::Conventional => "conventional",
+            MemoryKind::Loader => "loader",
+            MemoryKind::BootServices => "boot services",
+            MemoryKind::RuntimeServices => "runtime services",
+            MemoryKind::AcpiReclaimable => "ACPI reclaimable",
+            MemoryKind::AcpiNonVolatile => "ACPI NVS",
+            MemoryKind::Mmio => "MMIO",
+            MemoryKind::Unusable => "unusable",
+            MemoryKind::Reserved => "reserved"
+        }
+    }
+}
+
+impl fmt::Display for MemoryKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How much of the heap is in use.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MemoryStatistics {
+    /// Bytes of RAM given to the heap.
+    pub total: u64,
+
+    /// Bytes currently allocated, as requested rather than as rounded up by
+    /// the allocator.
+    pub allocated: u64,
+
+    /// Allocations not yet freed.
+    pub allocations: u64,
+
+    /// Allocations that failed for lack of memory.
+    pub failures: u64
+}
+
+impl fmt::Display for MemoryStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Heap: {} KiB of {} KiB allocated", self.allocated / 1024, self.total / 1024)?;
+        writeln!(f, "  {} live allocations, {} failed", self.allocations, self.failures)
+    }
+}
@@ -0,0 +1,55 @@
+use core::mem;
+
+use super::{MutexGuard, WaitQueue};
+
+/// A condition variable for use with the sleeping `Mutex`.
+pub struct Condvar {
+    waiters: WaitQueue
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Condvar { waiters: WaitQueue::new() }
+    }
+
+    /// Releases `guard`'s mutex and sleeps until notified, then takes the
+    /// mutex again. We join the queue before the mutex is released, so a
+    /// notification sent by whoever takes it next can't be missed. Wakeups
+    /// can be spurious.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let mut first = true;
+
+        self.waiters.wait_while(
+            || mem::replace(&mut first, false),
+            || mem::drop(guard));
+
+        mutex.lock()
+    }
+
+    /// Waits until `condition` returns false, rechecking it each time we're
+    /// woken.
+    pub fn wait_while<'a, T, F>(&self, mut guard: MutexGuard<'a, T>, mut condition: F) -> MutexGuard<'a, T>
+        where F: FnMut(&mut T) -> bool
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+
+        guard
+    }
+
+    pub fn notify_one(&self) -> bool {
+        self.waiters.notify_one()
+    }
+
+    pub fn notify_all(&self) -> usize {
+        self.waiters.notify_all()
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
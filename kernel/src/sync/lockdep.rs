@@ -0,0 +1,107 @@
+//! Lock-ordering checks, compiled in with the `lock-debug` feature.
+//!
+//! Each CPU keeps a stack of the ranked spinlocks it holds. Since interrupts
+//! are disabled while a spinlock is held, the stack can't change under us.
+//! Taking a lock whose rank isn't higher than every lock already held means
+//! two code paths could take the same pair in opposite orders, and panics.
+
+/// A group of locks that share a place in the lock order. Locks with a lower
+/// rank must be taken before locks with a higher one.
+#[derive(Debug)]
+pub struct LockClass {
+    pub name: &'static str,
+    pub rank: u32
+}
+
+impl LockClass {
+    pub const fn new(name: &'static str, rank: u32) -> Self {
+        LockClass { name, rank }
+    }
+}
+
+#[cfg(feature = "lock-debug")]
+mod checks {
+    use core::{
+        ptr,
+        sync::atomic::{AtomicPtr, AtomicUsize, Ordering}
+    };
+
+    use super::LockClass;
+    use crate::interrupts;
+
+    const MAX_CPUS: usize = 64;
+    const MAX_HELD: usize = 16;
+
+    const NOT_HELD: AtomicPtr<LockClass> = AtomicPtr::new(ptr::null_mut());
+    const NONE_HELD: [AtomicPtr<LockClass>; MAX_HELD] = [NOT_HELD; MAX_HELD];
+
+    static HELD: [[AtomicPtr<LockClass>; MAX_HELD]; MAX_CPUS] = [NONE_HELD; MAX_CPUS];
+    static DEPTH: [AtomicUsize; MAX_CPUS] = [AtomicUsize::new(0); MAX_CPUS];
+
+    fn held(cpu: usize) -> impl Iterator<Item = &'static LockClass> {
+        let depth = DEPTH[cpu].load(Ordering::Relaxed);
+        HELD[cpu][..depth].iter()
+            .map(|class| unsafe { &*class.load(Ordering::Relaxed) })
+    }
+
+    pub fn acquire(class: &'static LockClass) {
+        let cpu = interrupts::current_cpu() % MAX_CPUS;
+
+        for held in held(cpu) {
+            if ptr::eq(held, class) {
+                panic!("Lock order: recursive acquisition of {}", class.name);
+            }
+
+            if held.rank >= class.rank {
+                panic!("Lock order: taking {} (rank {}) while holding {} (rank {})",
+                    class.name, class.rank, held.name, held.rank);
+            }
+        }
+
+        let depth = DEPTH[cpu].load(Ordering::Relaxed);
+        if depth < MAX_HELD {
+            HELD[cpu][depth].store(class as *const LockClass as *mut LockClass, Ordering::Relaxed);
+        }
+        DEPTH[cpu].store(depth + 1, Ordering::Relaxed);
+    }
+
+    pub fn release(class: &'static LockClass) {
+        let cpu = interrupts::current_cpu() % MAX_CPUS;
+        let depth = DEPTH[cpu].load(Ordering::Relaxed);
+
+        // Locks are usually released in reverse order, but don't have to be.
+        let position = held(cpu).position(|held| ptr::eq(held, class));
+        if let Some(position) = position {
+            for index in position..depth.min(MAX_HELD) - 1 {
+                let next = HELD[cpu][index + 1].load(Ordering::Relaxed);
+                HELD[cpu][index].store(next, Ordering::Relaxed);
+            }
+        }
+
+        DEPTH[cpu].store(depth.saturating_sub(1), Ordering::Relaxed);
+    }
+
+    pub fn assert_can_sleep(what: &str) {
+        let cpu = interrupts::current_cpu() % MAX_CPUS;
+
+        if let Some(held) = held(cpu).next() {
+            panic!("Lock order: {} may sleep, but {} is held", what, held.name);
+        }
+    }
+}
+
+#[cfg(not(feature = "lock-debug"))]
+mod checks {
+    use super::LockClass;
+
+    #[inline(always)]
+    pub fn acquire(_class: &'static LockClass) {}
+
+    #[inline(always)]
+    pub fn release(_class: &'static LockClass) {}
+
+    #[inline(always)]
+    pub fn assert_can_sleep(_what: &str) {}
+}
+
+pub(super) use self::checks::{acquire, assert_can_sleep, release};
@@ -0,0 +1,25 @@
+//! Locks and other synchronization primitives for kernel code.
+//!
+//! `IrqSpinLock` is for data shared with interrupt handlers: it disables
+//! interrupts while held, so a handler can never spin on a lock the code it
+//! interrupted is holding. Everything else here puts waiting threads to sleep
+//! instead of spinning, and must not be used from interrupt handlers.
+//!
+//! With the `lock-debug` feature, locks given a `LockClass` check that they're
+//! always taken in order of increasing rank.
+
+mod condvar;
+mod lockdep;
+mod mutex;
+mod semaphore;
+mod spinlock;
+mod wait_queue;
+
+pub use self::{
+    condvar::Condvar,
+    lockdep::LockClass,
+    mutex::{Mutex, MutexGuard},
+    semaphore::Semaphore,
+    spinlock::{IrqSpinLock, IrqSpinLockGuard},
+    wait_queue::WaitQueue
+};
@@ -0,0 +1,50 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{lockdep, WaitQueue};
+
+/// A counting semaphore. `acquire` sleeps while the count is zero.
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue
+}
+
+impl Semaphore {
+    pub fn new(count: usize) -> Self {
+        Semaphore {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new()
+        }
+    }
+
+    pub fn acquire(&self) {
+        lockdep::assert_can_sleep("Semaphore::acquire");
+
+        while !self.try_acquire() {
+            self.waiters.wait_until(|| self.count.load(Ordering::Relaxed) > 0);
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut count = self.count.load(Ordering::Relaxed);
+
+        while count > 0 {
+            match self.count.compare_exchange_weak(count, count - 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(actual) => count = actual
+            }
+        }
+
+        false
+    }
+
+    /// Increments the count and wakes a waiter. Unlike the sleeping calls,
+    /// this is safe from interrupt handlers.
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,105 @@
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex as SpinMutex, MutexGuard as SpinMutexGuard};
+
+use super::lockdep::{self, LockClass};
+use crate::interrupts;
+
+/// A spinlock that disables interrupts on the local CPU while held.
+///
+/// Use this for anything an interrupt handler might touch. With a plain
+/// spinlock, a handler that interrupts the lock's holder spins forever; so
+/// does every other thread if the holder is preempted.
+pub struct IrqSpinLock<T> {
+    inner: SpinMutex<T>,
+    class: Option<&'static LockClass>
+}
+
+pub struct IrqSpinLockGuard<'a, T> {
+    /// Always `Some` until dropped. The lock must be released before
+    /// interrupts are restored, which field drop order can't express.
+    guard: Option<SpinMutexGuard<'a, T>>,
+    class: Option<&'static LockClass>,
+    interrupts: bool
+}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        IrqSpinLock { inner: SpinMutex::new(value), class: None }
+    }
+
+    /// A lock whose acquisition order is checked against other locks when
+    /// the `lock-debug` feature is enabled.
+    pub const fn ranked(value: T, class: &'static LockClass) -> Self {
+        IrqSpinLock { inner: SpinMutex::new(value), class: Some(class) }
+    }
+
+    pub fn lock(&self) -> IrqSpinLockGuard<T> {
+        let interrupts = interrupts::disable();
+
+        if let Some(class) = self.class {
+            lockdep::acquire(class);
+        }
+
+        IrqSpinLockGuard {
+            guard: Some(self.inner.lock()),
+            class: self.class,
+            interrupts
+        }
+    }
+
+    /// Takes the lock if it's free. This is the only safe way to take a lock
+    /// from an interrupt handler that the interrupted code might hold.
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<T>> {
+        let interrupts = interrupts::disable();
+
+        match self.inner.try_lock() {
+            Some(guard) => {
+                if let Some(class) = self.class {
+                    lockdep::acquire(class);
+                }
+
+                Some(IrqSpinLockGuard { guard: Some(guard), class: self.class, interrupts })
+            },
+
+            None => {
+                interrupts::restore(interrupts);
+                None
+            }
+        }
+    }
+
+    /// Releases the lock without a guard, for when its holder was interrupted
+    /// and will never resume, such as on panic.
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+    }
+}
+
+unsafe impl<T: Send> Sync for IrqSpinLock<T> {}
+unsafe impl<T: Send> Send for IrqSpinLock<T> {}
+
+impl<'a, T> Deref for IrqSpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<'a, T> DerefMut for IrqSpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<'a, T> Drop for IrqSpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.guard.take();
+
+        if let Some(class) = self.class {
+            lockdep::release(class);
+        }
+
+        interrupts::restore(self.interrupts);
+    }
+}
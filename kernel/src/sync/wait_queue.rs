@@ -0,0 +1,118 @@
+use alloc::collections::VecDeque;
+use core::sync::atomic::spin_loop_hint;
+
+use super::{lockdep, IrqSpinLock};
+use crate::thread::{self, ThreadId};
+
+/// A queue of threads waiting for something to happen.
+///
+/// Wakeups can't be lost: a thread joins the queue before it checks its
+/// condition for the last time, and `thread::park` returns straight away if it
+/// was unparked in between. Before threads are initialised there's nobody to
+/// switch to, so waiting spins instead.
+pub struct WaitQueue {
+    waiters: IrqSpinLock<VecDeque<ThreadId>>
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        WaitQueue { waiters: IrqSpinLock::new(VecDeque::new()) }
+    }
+
+    /// Sleeps until `notify_one` or `notify_all` is called. Wakeups can be
+    /// spurious, so callers should recheck what they were waiting for.
+    pub fn wait(&self) {
+        self.wait_while(|| true, || {});
+    }
+
+    /// Sleeps for as long as `condition` holds. `condition` is checked with
+    /// the queue locked, so a notification sent after the condition changes
+    /// always wakes us. `release` runs once we're queued, just before the
+    /// first sleep; `Condvar` uses it to drop its mutex.
+    pub(super) fn wait_while<C, R>(&self, mut condition: C, release: R)
+        where C: FnMut() -> bool, R: FnOnce()
+    {
+        lockdep::assert_can_sleep("WaitQueue::wait");
+
+        let current = match thread::current() {
+            Some(current) => current,
+            None => {
+                release();
+                while condition() {
+                    spin_loop_hint();
+                }
+                return;
+            }
+        };
+
+        let mut release = Some(release);
+
+        loop {
+            {
+                let mut waiters = self.waiters.lock();
+                if !condition() {
+                    waiters.retain(|waiter| *waiter != current);
+                    break;
+                }
+
+                if !waiters.contains(&current) {
+                    waiters.push_back(current);
+                }
+            }
+
+            if let Some(release) = release.take() {
+                release();
+            }
+
+            thread::park();
+        }
+
+        if let Some(release) = release.take() {
+            release();
+        }
+    }
+
+    /// Sleeps until `condition` is true. It must only become true in ways
+    /// that are followed by a notification on this queue.
+    pub fn wait_until<C: FnMut() -> bool>(&self, mut condition: C) {
+        self.wait_while(|| !condition(), || {});
+    }
+
+    /// Wakes the longest-waiting thread. Returns false if nobody was waiting.
+    pub fn notify_one(&self) -> bool {
+        let waiter = self.waiters.lock().pop_front();
+
+        match waiter {
+            Some(waiter) => {
+                thread::unpark(waiter);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Wakes every waiting thread, returning how many there were.
+    pub fn notify_all(&self) -> usize {
+        let waiters: VecDeque<ThreadId> = core::mem::replace(&mut *self.waiters.lock(), VecDeque::new());
+
+        for waiter in &waiters {
+            thread::unpark(*waiter);
+        }
+
+        waiters.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiters.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        WaitQueue::new()
+    }
+}
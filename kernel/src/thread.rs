@@ -0,0 +1,323 @@
+//! Preemptive kernel threads.
+//!
+//! Each thread has its own stack and a saved `ThreadContext`. The platform
+//! does the actual register save/restore, and calls `clock_tick` from its
+//! timer interrupt so that a long-running thread can't hold up the event loop.
+//! Which thread runs when is up to the `Scheduler`.
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::String,
+    vec::Vec
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Once;
+
+use crate::{
+    Platform,
+    interrupts,
+    scheduler::{Scheduler, Switch},
+    sync::{IrqSpinLock, LockClass}
+};
+
+pub use crate::scheduler::{Priority, ThreadState};
+
+/// The stack each spawned thread gets.
+pub const STACK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(pub(crate) u64);
+
+/// What the platform needs to resume a thread. Everything other than the
+/// stack pointer is saved on the thread's own stack.
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct ThreadContext {
+    pub stack_pointer: usize
+}
+
+/// The entry point the platform arranges for a new thread to start in.
+pub type ThreadEntry = extern "C" fn(usize) -> !;
+
+/// Platform functions the scheduler calls. They're captured as plain function
+/// pointers at `init` so that the scheduler itself isn't generic, and can be
+/// reached from the platform's interrupt handlers.
+struct ThreadOps {
+    init_context: fn(&mut [u8], ThreadEntry, usize) -> ThreadContext,
+    switch: unsafe fn(*mut ThreadContext, *const ThreadContext)
+}
+
+struct Thread {
+    name: String,
+    context: ThreadContext,
+
+    /// `None` for the boot thread, which runs on the stack firmware gave us.
+    stack: Option<Box<[u8]>>
+}
+
+struct Threads {
+    /// Threads are boxed so their contexts don't move while we're switching.
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    scheduler: Scheduler,
+    next_id: u64
+}
+
+static OPS: Once<ThreadOps> = Once::new();
+static THREADS_CLASS: LockClass = LockClass::new("threads", 50);
+static THREADS: IrqSpinLock<Option<Threads>> = IrqSpinLock::ranked(None, &THREADS_CLASS);
+
+/// Clock ticks seen by `clock_tick`, used for sleep deadlines.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Turns the caller into the first thread, called `name`.
+pub fn init<P: Platform>(name: &str) {
+    OPS.call_once(|| ThreadOps {
+        init_context: P::init_thread_context,
+        switch: P::switch_thread
+    });
+    interrupts::init::<P>();
+
+    let boot = ThreadId(0);
+    let mut threads = BTreeMap::new();
+    threads.insert(boot, Box::new(Thread {
+        name: name.into(),
+        context: ThreadContext::default(),
+        stack: None
+    }));
+
+    let mut scheduler = Scheduler::new(P::cpu_count());
+    scheduler.add_running(boot, Priority::Normal, interrupts::current_cpu());
+
+    *THREADS.lock() = Some(Threads {
+        threads,
+        scheduler,
+        next_id: 1
+    });
+}
+
+/// Starts a new thread running `f` at normal priority. It'll first run at the
+/// next reschedule.
+pub fn spawn<F: FnOnce() + Send + 'static>(name: &str, f: F) -> ThreadId {
+    spawn_with_priority(name, Priority::Normal, f)
+}
+
+pub fn spawn_with_priority<F: FnOnce() + Send + 'static>(name: &str, priority: Priority, f: F) -> ThreadId {
+    let ops = OPS.r#try().expect("Threads haven't been initialised");
+
+    let mut stack = alloc::vec![0u8; STACK_SIZE].into_boxed_slice();
+
+    let closure: Box<Box<dyn FnOnce() + Send>> = Box::new(Box::new(f));
+    let argument = Box::into_raw(closure) as usize;
+    let context = (ops.init_context)(&mut stack, thread_entry, argument);
+
+    let id = with_threads(|threads| {
+        let id = ThreadId(threads.next_id);
+        threads.next_id += 1;
+
+        threads.threads.insert(id, Box::new(Thread {
+            name: name.into(),
+            context,
+            stack: Some(stack)
+        }));
+        threads.scheduler.add(id, priority, interrupts::current_cpu());
+
+        id
+    });
+
+    log::debug!("Spawned thread {:?} ({}) at {:?} priority", id, name, priority);
+    id
+}
+
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: String,
+    pub priority: Priority,
+    pub state: ThreadState,
+    pub stack_size: usize
+}
+
+/// A snapshot of every thread that hasn't been freed yet.
+pub fn threads() -> Vec<ThreadInfo> {
+    with_threads(|threads| {
+        threads.threads.iter()
+            .map(|(id, thread)| ThreadInfo {
+                id: *id,
+                name: thread.name.clone(),
+                priority: threads.scheduler.priority(*id).unwrap(),
+                state: threads.scheduler.state(*id).unwrap(),
+                stack_size: thread.stack.as_ref().map(|stack| stack.len()).unwrap_or(0)
+            })
+            .collect()
+    })
+}
+
+pub fn current() -> Option<ThreadId> {
+    OPS.r#try()?;
+    with_threads(|threads| threads.scheduler.current(interrupts::current_cpu()))
+}
+
+/// True if some thread other than the current one is waiting to run here.
+pub fn has_ready() -> bool {
+    match OPS.r#try() {
+        Some(_) => with_threads(|threads| threads.scheduler.ready_count(interrupts::current_cpu()) > 0),
+        None => false
+    }
+}
+
+pub fn set_priority(id: ThreadId, priority: Priority) {
+    with_threads(|threads| threads.scheduler.set_priority(id, priority));
+}
+
+/// Gives up the CPU to the next ready thread, if there is one.
+pub fn yield_now() {
+    reschedule(|_, _| {});
+}
+
+/// Sleeps for at least `ticks` clock ticks.
+pub fn sleep(ticks: u64) {
+    let deadline = TICKS.load(Ordering::Relaxed) + ticks;
+    reschedule(|scheduler, current| scheduler.sleep_until(current, deadline));
+}
+
+/// Blocks until another thread calls `unpark` on this one. If `unpark` was
+/// called since the last `park`, returns straight away.
+pub fn park() {
+    reschedule(|scheduler, current| { scheduler.park(current); });
+}
+
+pub fn unpark(id: ThreadId) {
+    with_threads(|threads| threads.scheduler.unpark(id));
+}
+
+/// Ends the current thread.
+pub fn exit() -> ! {
+    reschedule(|scheduler, current| scheduler.exit(current));
+    unreachable!("Finished thread was rescheduled");
+}
+
+/// Called by the platform's timer interrupt. Wakes sleepers, and switches
+/// threads if the current one has used up its time slice. If the interrupted
+/// code holds the thread lock we leave it alone until the next tick.
+pub fn clock_tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+
+    let ops = match OPS.r#try() {
+        Some(ops) => ops,
+        None => return
+    };
+
+    let enabled = interrupts::disable();
+
+    let switch = match THREADS.try_lock() {
+        Some(mut guard) => match guard.as_mut() {
+            Some(threads) => {
+                let cpu = interrupts::current_cpu();
+                if threads.scheduler.tick(cpu, now) {
+                    threads.switch(cpu)
+                } else {
+                    None
+                }
+            },
+            None => None
+        },
+        None => None
+    };
+
+    if let Some((from, to)) = switch {
+        unsafe { (ops.switch)(from, to); }
+    }
+
+    interrupts::restore(enabled);
+}
+
+extern "C" fn thread_entry(argument: usize) -> ! {
+    let closure = unsafe { Box::from_raw(argument as *mut Box<dyn FnOnce() + Send>) };
+
+    // We arrive here from the middle of a switch, with interrupts disabled.
+    interrupts::restore(true);
+
+    closure();
+    exit()
+}
+
+fn with_threads<R, F: FnOnce(&mut Threads) -> R>(f: F) -> R {
+    f(THREADS.lock().as_mut().expect("Threads haven't been initialised"))
+}
+
+/// Applies `change` to the current thread's scheduling state, then switches
+/// to whatever should run next. If nothing is runnable and the current thread
+/// can't continue, waits with interrupts enabled until something is.
+fn reschedule<F: FnOnce(&mut Scheduler, ThreadId)>(change: F) {
+    let ops = match OPS.r#try() {
+        Some(ops) => ops,
+        None => return
+    };
+
+    let enabled = interrupts::disable();
+    let cpu = interrupts::current_cpu();
+
+    let mut change = Some(change);
+
+    loop {
+        let (switch, must_wait) = {
+            let mut guard = THREADS.lock();
+            let threads = guard.as_mut().unwrap();
+            let current = threads.scheduler.current(cpu).unwrap();
+
+            if let Some(change) = change.take() {
+                change(&mut threads.scheduler, current);
+            }
+
+            let switch = threads.switch(cpu);
+            let must_wait = switch.is_none()
+                && threads.scheduler.state(current) != Some(ThreadState::Running);
+
+            (switch, must_wait)
+        };
+
+        // The lock has to be dropped before switching, since the next thread
+        // will want it too.
+        if let Some((from, to)) = switch {
+            unsafe { (ops.switch)(from, to); }
+            break;
+        }
+
+        if !must_wait {
+            break;
+        }
+
+        // Let the timer interrupt wake sleepers, or another CPU hand us work.
+        interrupts::restore(true);
+        core::sync::atomic::spin_loop_hint();
+        interrupts::disable();
+    }
+
+    interrupts::restore(enabled);
+}
+
+impl Threads {
+    /// Asks the scheduler what `cpu` should run next, returning the contexts
+    /// to switch between if it's a different thread.
+    fn switch(&mut self, cpu: usize) -> Option<(*mut ThreadContext, *const ThreadContext)> {
+        self.reap();
+
+        let Switch { from, to } = self.scheduler.schedule(cpu)?;
+
+        let from = &mut self.threads.get_mut(&from).unwrap().context as *mut ThreadContext;
+        let to = &self.threads.get(&to).unwrap().context as *const ThreadContext;
+
+        Some((from, to))
+    }
+
+    /// Frees threads that have finished and aren't running anywhere.
+    fn reap(&mut self) {
+        for id in self.scheduler.finished() {
+            self.scheduler.remove(id);
+            if let Some(thread) = self.threads.remove(&id) {
+                log::debug!("Thread {:?} ({}) finished", id, thread.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use core::ptr;
+use x86_64::instructions::port::Port;
+
+/// Offsets of the fields we use, from the start of the table.
+const OFFSET_SCI_INTERRUPT: u64 = 46;
+const OFFSET_SMI_COMMAND: u64 = 48;
+const OFFSET_ACPI_ENABLE: u64 = 52;
+const OFFSET_PM1A_CONTROL_BLOCK: u64 = 64;
+const OFFSET_GPE0_BLOCK: u64 = 80;
+const OFFSET_GPE0_BLOCK_LENGTH: u64 = 92;
+
+/// PM1 control register bit that routes power management events to the SCI
+/// instead of SMM.
+const PM1_CONTROL_SCI_ENABLE: u16 = 1 << 0;
+
+/// How long to wait for firmware to hand over to ACPI mode, in polls.
+const ACPI_ENABLE_TIMEOUT: usize = 1_000_000;
+
+/// The parts of the Fixed ACPI Description Table needed to take system
+/// control interrupts and general purpose events.
+#[derive(Debug, Copy, Clone)]
+pub struct Fadt {
+  /// The ISA IRQ the SCI is wired to.
+  pub sci_interrupt: u8,
+  pub smi_command: u16,
+  pub acpi_enable: u8,
+  pub pm1a_control_block: u16,
+
+  /// Port of the GPE0 register block: status registers in the first half,
+  /// enable registers in the second.
+  pub gpe0_block: u16,
+  pub gpe0_block_length: u8
+}
+
+impl Fadt {
+  pub(super) unsafe fn parse(address: u64) -> Self {
+    let read_u8 = |offset| ptr::read_unaligned((address + offset) as *const u8);
+    let read_u16 = |offset| ptr::read_unaligned((address + offset) as *const u16);
+    let read_u32 = |offset| ptr::read_unaligned((address + offset) as *const u32);
+
+    Fadt {
+      sci_interrupt: read_u16(OFFSET_SCI_INTERRUPT) as u8,
+      smi_command: read_u32(OFFSET_SMI_COMMAND) as u16,
+      acpi_enable: read_u8(OFFSET_ACPI_ENABLE),
+      pm1a_control_block: read_u32(OFFSET_PM1A_CONTROL_BLOCK) as u16,
+      gpe0_block: read_u32(OFFSET_GPE0_BLOCK) as u16,
+      gpe0_block_length: read_u8(OFFSET_GPE0_BLOCK_LENGTH)
+    }
+  }
+
+  /// Switches the chipset into ACPI mode if firmware hasn't already, so
+  /// that events raise the SCI.
+  pub(super) fn enable_acpi_mode(&self) {
+    let mut pm1a_control: Port<u16> = Port::new(self.pm1a_control_block);
+
+    if unsafe { pm1a_control.read() } & PM1_CONTROL_SCI_ENABLE != 0 {
+      return;
+    }
+
+    if self.smi_command == 0 || self.acpi_enable == 0 {
+      log::warn!("ACPI: not in ACPI mode, and no way to switch to it");
+      return;
+    }
+
+    let mut smi_command: Port<u8> = Port::new(self.smi_command);
+    unsafe { smi_command.write(self.acpi_enable); }
+
+    for _ in 0..ACPI_ENABLE_TIMEOUT {
+      if unsafe { pm1a_control.read() } & PM1_CONTROL_SCI_ENABLE != 0 {
+        log::info!("ACPI: switched to ACPI mode");
+        return;
+      }
+    }
+
+    log::warn!("ACPI: firmware didn't switch to ACPI mode");
+  }
+
+  fn gpe0_register_count(&self) -> u16 {
+    self.gpe0_block_length as u16 / 2
+  }
+
+  /// Lets general purpose event `gpe` raise the SCI.
+  pub(super) fn enable_gpe(&self, gpe: u8) {
+    let register = gpe as u16 / 8;
+    if register >= self.gpe0_register_count() {
+      log::warn!("ACPI: GPE {} is outside the GPE0 block", gpe);
+      return;
+    }
+
+    let mut enable: Port<u8> = Port::new(self.gpe0_block + self.gpe0_register_count() + register);
+    unsafe {
+      let value = enable.read();
+      enable.write(value | 1 << (gpe % 8));
+    }
+  }
+
+  /// Clears and returns the status of the first 64 general purpose events.
+  /// Status bits are write-one-to-clear, so we write back what we read.
+  pub(super) fn take_gpe_status(&self) -> u64 {
+    let mut pending = 0;
+
+    for register in 0..self.gpe0_register_count().min(8) {
+      let mut status: Port<u8> = Port::new(self.gpe0_block + register);
+      unsafe {
+        let value = status.read();
+        status.write(value);
+        pending |= (value as u64) << (register * 8);
+      }
+    }
+
+    pending
+  }
+}
@@ -0,0 +1,182 @@
+use alloc::vec::Vec;
+use core::ptr;
+
+use super::SdtHeader;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+/// The bus number the MADT uses for ISA interrupt source overrides.
+const BUS_ISA: u8 = 0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Polarity {
+  ActiveHigh,
+  ActiveLow
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TriggerMode {
+  Edge,
+  Level
+}
+
+/// How an interrupt from a bus is wired to the I/O APIC.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IrqRoute {
+  pub gsi: u32,
+  pub polarity: Polarity,
+  pub trigger: TriggerMode
+}
+
+impl IrqRoute {
+  pub fn isa_default(irq: u8) -> Self {
+    IrqRoute {
+      gsi: irq as u32,
+      polarity: Polarity::ActiveHigh,
+      trigger: TriggerMode::Edge
+    }
+  }
+
+  /// How a PCI interrupt is wired when nothing says otherwise.
+  pub fn pci_default(gsi: u32) -> Self {
+    IrqRoute {
+      gsi,
+      polarity: Polarity::ActiveLow,
+      trigger: TriggerMode::Level
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LocalApicEntry {
+  pub processor_id: u8,
+  pub apic_id: u8,
+  pub enabled: bool
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct IoApicEntry {
+  pub id: u8,
+  pub address: u32,
+  pub gsi_base: u32
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct InterruptSourceOverride {
+  pub bus: u8,
+  pub source: u8,
+  pub route: IrqRoute
+}
+
+#[derive(Debug)]
+pub struct Madt {
+  pub local_apic_address: u64,
+  pub processors: Vec<LocalApicEntry>,
+  pub io_apics: Vec<IoApicEntry>,
+  pub overrides: Vec<InterruptSourceOverride>
+}
+
+impl Madt {
+  pub(super) unsafe fn parse(address: u64) -> Self {
+    let header: SdtHeader = ptr::read_unaligned(address as *const SdtHeader);
+    let header_size = core::mem::size_of::<SdtHeader>() as u64;
+
+    let mut madt = Madt {
+      local_apic_address: ptr::read_unaligned((address + header_size) as *const u32) as u64,
+      processors: Vec::new(),
+      io_apics: Vec::new(),
+      overrides: Vec::new()
+    };
+
+    // The local APIC address and flags fields come before the entries.
+    let mut offset = header_size + 8;
+    let end = header.length as u64;
+
+    while offset + 2 <= end {
+      let entry = address + offset;
+      let entry_type = ptr::read(entry as *const u8);
+      let entry_length = ptr::read((entry + 1) as *const u8) as u64;
+
+      if entry_length < 2 {
+        log::warn!("ACPI: malformed MADT entry at offset {}", offset);
+        break;
+      }
+
+      match entry_type {
+        ENTRY_LOCAL_APIC => madt.processors.push(LocalApicEntry {
+          processor_id: ptr::read((entry + 2) as *const u8),
+          apic_id: ptr::read((entry + 3) as *const u8),
+          enabled: ptr::read_unaligned((entry + 4) as *const u32) & 1 != 0
+        }),
+
+        ENTRY_IO_APIC => madt.io_apics.push(IoApicEntry {
+          id: ptr::read((entry + 2) as *const u8),
+          address: ptr::read_unaligned((entry + 4) as *const u32),
+          gsi_base: ptr::read_unaligned((entry + 8) as *const u32)
+        }),
+
+        ENTRY_INTERRUPT_SOURCE_OVERRIDE => {
+          let bus = ptr::read((entry + 2) as *const u8);
+          let source = ptr::read((entry + 3) as *const u8);
+          let gsi = ptr::read_unaligned((entry + 4) as *const u32);
+          let flags = ptr::read_unaligned((entry + 8) as *const u16);
+
+          madt.overrides.push(InterruptSourceOverride {
+            bus,
+            source,
+            route: IrqRoute {
+              gsi,
+              polarity: decode_polarity(flags),
+              trigger: decode_trigger(flags)
+            }
+          });
+        },
+
+        ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+          madt.local_apic_address = ptr::read_unaligned((entry + 4) as *const u64);
+        },
+
+        _ => {}
+      }
+
+      offset += entry_length;
+    }
+
+    madt
+  }
+
+  pub fn isa_irq_route(&self, irq: u8) -> IrqRoute {
+    self.overrides.iter()
+      .find(|o| o.bus == BUS_ISA && o.source == irq)
+      .map(|o| o.route)
+      .unwrap_or_else(|| IrqRoute::isa_default(irq))
+  }
+
+  /// Where a PCI interrupt line ends up. Lines the firmware gave an ISA IRQ
+  /// number can be moved by an override like any ISA IRQ.
+  pub fn pci_irq_route(&self, line: u32) -> IrqRoute {
+    self.overrides.iter()
+      .find(|o| o.bus == BUS_ISA && o.source as u32 == line)
+      .map(|o| o.route)
+      .unwrap_or_else(|| IrqRoute::pci_default(line))
+  }
+}
+
+/// MPS INTI flags, bits 0-1. "Conforms to the bus" means active-high for ISA.
+fn decode_polarity(flags: u16) -> Polarity {
+  match flags & 0b11 {
+    0b11 => Polarity::ActiveLow,
+    _ => Polarity::ActiveHigh
+  }
+}
+
+/// MPS INTI flags, bits 2-3. "Conforms to the bus" means edge for ISA.
+fn decode_trigger(flags: u16) -> TriggerMode {
+  match (flags >> 2) & 0b11 {
+    0b11 => TriggerMode::Level,
+    _ => TriggerMode::Edge
+  }
+}
@@ -0,0 +1,186 @@
+//! Just enough ACPI table parsing to route interrupts: we find the MADT through
+//! the RSDP that UEFI hands us and keep what it says about APICs and ISA IRQ
+//! overrides, and take the SCI and GPE registers from the FADT. Firmware
+//! identity-maps the tables, so physical addresses can be dereferenced
+//! directly.
+
+pub mod fadt;
+pub mod madt;
+
+use core::ptr;
+use spin::Once;
+
+pub use fadt::Fadt;
+pub use madt::{Madt, Polarity, TriggerMode, IrqRoute};
+
+static MADT: Once<Option<Madt>> = Once::new();
+static FADT: Once<Option<Fadt>> = Once::new();
+
+#[repr(C, packed)]
+struct Rsdp {
+  signature: [u8; 8],
+  checksum: u8,
+  oem_id: [u8; 6],
+  revision: u8,
+  rsdt_address: u32,
+
+  // ACPI 2.0+
+  length: u32,
+  xsdt_address: u64,
+  extended_checksum: u8,
+  reserved: [u8; 3]
+}
+
+#[repr(C, packed)]
+pub(crate) struct SdtHeader {
+  pub signature: [u8; 4],
+  pub length: u32,
+  pub revision: u8,
+  pub checksum: u8,
+  pub oem_id: [u8; 6],
+  pub oem_table_id: [u8; 8],
+  pub oem_revision: u32,
+  pub creator_id: u32,
+  pub creator_revision: u32
+}
+
+pub fn init(rsdp_address: Option<u64>) {
+  init_madt(rsdp_address);
+  init_fadt(rsdp_address);
+}
+
+fn init_madt(rsdp_address: Option<u64>) {
+  MADT.call_once(|| {
+    let rsdp_address = match rsdp_address {
+      Some(address) => address,
+      None => {
+        log::warn!("ACPI: no RSDP from firmware, using legacy interrupt routing");
+        return None;
+      }
+    };
+
+    let madt = unsafe { find_table(rsdp_address, b"APIC") }
+      .map(|address| unsafe { Madt::parse(address) });
+
+    match madt {
+      Some(ref madt) => log::info!("ACPI: found MADT with {} I/O APIC(s) and {} interrupt override(s)",
+        madt.io_apics.len(), madt.overrides.len()),
+      None => log::warn!("ACPI: no MADT, using legacy interrupt routing")
+    }
+
+    madt
+  });
+}
+
+fn init_fadt(rsdp_address: Option<u64>) {
+  FADT.call_once(|| {
+    let fadt = rsdp_address
+      .and_then(|address| unsafe { find_table(address, b"FACP") })
+      .map(|address| unsafe { Fadt::parse(address) });
+
+    match fadt {
+      Some(ref fadt) => {
+        log::info!("ACPI: SCI on IRQ {}, {} GPE0 register(s) at {:#x}",
+          fadt.sci_interrupt, fadt.gpe0_block_length / 2, fadt.gpe0_block);
+        fadt.enable_acpi_mode();
+      },
+      None => log::warn!("ACPI: no FADT, general purpose events are unavailable")
+    }
+
+    fadt
+  });
+}
+
+pub fn fadt() -> Option<&'static Fadt> {
+  FADT.r#try().and_then(|fadt| fadt.as_ref())
+}
+
+/// The ISA IRQ that system control interrupts arrive on.
+pub fn sci_irq() -> Option<u8> {
+  fadt().map(|fadt| fadt.sci_interrupt)
+}
+
+pub fn enable_gpe(gpe: u8) {
+  if let Some(fadt) = fadt() {
+    fadt.enable_gpe(gpe);
+  }
+}
+
+/// Called from the SCI handler. Acknowledges every pending general purpose
+/// event and returns them as a bitmask.
+pub fn take_gpe_status() -> u64 {
+  fadt().map(|fadt| fadt.take_gpe_status()).unwrap_or(0)
+}
+
+/// The parsed MADT, if the firmware provided one.
+pub fn madt() -> Option<&'static Madt> {
+  MADT.r#try().and_then(|madt| madt.as_ref())
+}
+
+/// Where an ISA IRQ ends up on the I/O APIC, with its polarity and trigger
+/// mode. Without an override ISA interrupts are identity-mapped, edge-triggered
+/// and active-high.
+pub fn isa_irq_route(irq: u8) -> IrqRoute {
+  match madt() {
+    Some(madt) => madt.isa_irq_route(irq),
+    None => IrqRoute::isa_default(irq)
+  }
+}
+
+/// Where a PCI interrupt line ends up on the I/O APIC. The device's `_PRT`
+/// entry would say, but we don't run AML, so this goes by the line the
+/// firmware assigned. Below 16 it's an ISA IRQ, which the chipset also
+/// delivers to that I/O APIC input, and above that it's already a GSI.
+/// Either way it's level-triggered and active-low, unless an override says
+/// how the input is wired.
+pub fn pci_irq_route(line: u32) -> IrqRoute {
+  match madt() {
+    Some(madt) => madt.pci_irq_route(line),
+    None => IrqRoute::pci_default(line)
+  }
+}
+
+unsafe fn checksum_ok(address: u64, length: usize) -> bool {
+  let bytes = core::slice::from_raw_parts(address as *const u8, length);
+  bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// Walks the XSDT (or the RSDT on ACPI 1.0 firmware) looking for a table.
+unsafe fn find_table(rsdp_address: u64, signature: &[u8; 4]) -> Option<u64> {
+  let rsdp: Rsdp = ptr::read_unaligned(rsdp_address as *const Rsdp);
+
+  if &rsdp.signature != b"RSD PTR " || !checksum_ok(rsdp_address, 20) {
+    log::warn!("ACPI: bad RSDP at {:#x}", rsdp_address);
+    return None;
+  }
+
+  let (root_address, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+    (rsdp.xsdt_address, 8)
+  } else {
+    (rsdp.rsdt_address as u64, 4)
+  };
+
+  let root: SdtHeader = ptr::read_unaligned(root_address as *const SdtHeader);
+  let header_size = core::mem::size_of::<SdtHeader>();
+  let entries = (root.length as usize - header_size) / entry_size;
+
+  for index in 0..entries {
+    let entry_address = root_address + (header_size + index * entry_size) as u64;
+    let table_address = if entry_size == 8 {
+      ptr::read_unaligned(entry_address as *const u64)
+    } else {
+      ptr::read_unaligned(entry_address as *const u32) as u64
+    };
+
+    let table: SdtHeader = ptr::read_unaligned(table_address as *const SdtHeader);
+    if &table.signature == signature {
+      if !checksum_ok(table_address, table.length as usize) {
+        log::warn!("ACPI: table {:?} has a bad checksum", core::str::from_utf8(signature));
+      }
+
+      return Some(table_address);
+    }
+  }
+
+  None
+}
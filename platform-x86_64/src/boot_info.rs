@@ -1,3 +1,13 @@
+use kernel::{CommandLine, MemoryKind, MemoryRegion};
+use spin::Once;
+
+use crate::{X8664Platform, firmware::X8664Firmware};
+
+/// How much of the command line and configuration file we keep.
+pub const BOOT_TEXT_SIZE: usize = 4096;
+
+/// How many entries of the firmware's memory map we keep.
+pub const MAX_MEMORY_DESCRIPTORS: usize = 256;
 
 #[derive(Copy, Clone)]
 pub struct X8664MemorySegment {
@@ -5,7 +15,161 @@ pub struct X8664MemorySegment {
     pub length: u64
 }
 
+/// An entry from the firmware's memory map, with its `EFI_MEMORY_TYPE`.
+#[derive(Copy, Clone)]
+pub struct X8664MemoryDescriptor {
+    pub start_address: u64,
+    pub length: u64,
+    pub memory_type: u32
+}
+
+/// The mode the firmware left the display in.
+#[derive(Copy, Clone, Debug)]
+pub struct X8664DisplayMode {
+    pub width: usize,
+    pub height: usize,
+
+    /// Pixels from the start of one line to the start of the next.
+    pub stride: usize,
+    pub pixel_format: X8664PixelFormat
+}
+
+/// How a 32-bit pixel is laid out in memory. Other formats aren't supported,
+/// so they're never passed on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum X8664PixelFormat {
+    /// Red, green and blue bytes, then a reserved one.
+    Rgb,
+
+    /// Blue, green and red bytes, then a reserved one.
+    Bgr
+}
+
 #[derive(Clone)]
 pub struct X8664BootInfo {
-    pub memory_map: [X8664MemorySegment; 100]
+    /// The RAM given to the heap.
+    pub memory_map: [X8664MemorySegment; 100],
+
+    /// Every entry of the firmware's memory map, up to
+    /// `MAX_MEMORY_DESCRIPTORS`, followed by unused entries.
+    pub firmware_memory_map: [X8664MemoryDescriptor; MAX_MEMORY_DESCRIPTORS],
+    pub firmware_memory_map_length: usize,
+
+    /// Physical address of the ACPI RSDP, from the UEFI configuration table.
+    pub rsdp_address: Option<u64>,
+
+    /// The display's mode, if the firmware drove it in a format we can draw
+    /// in.
+    pub display_mode: Option<X8664DisplayMode>,
+
+    /// The load options the image was started with.
+    pub load_options: BootText,
+
+    /// The boot configuration file from the ESP, if there was one.
+    pub config_file: BootText,
+
+    /// The runtime services left once boot services exited.
+    pub firmware: X8664Firmware
+}
+
+/// Text read before exiting boot services. There's no heap yet at that
+/// point, so it's kept in a fixed buffer and parsed once there is.
+#[derive(Copy, Clone)]
+pub struct BootText {
+    bytes: [u8; BOOT_TEXT_SIZE],
+    length: usize
+}
+
+impl BootText {
+    pub const fn empty() -> Self {
+        BootText { bytes: [0; BOOT_TEXT_SIZE], length: 0 }
+    }
+
+    /// Copies `bytes`, dropping whatever doesn't fit.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut text = BootText::empty();
+        text.length = bytes.len().min(BOOT_TEXT_SIZE);
+        text.bytes[..text.length].copy_from_slice(&bytes[..text.length]);
+        text
+    }
+
+    /// The text up to the first invalid UTF-8, which is usually where it was
+    /// cut off.
+    pub fn as_str(&self) -> &str {
+        let bytes = &self.bytes[..self.length];
+
+        match core::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(error) => unsafe { core::str::from_utf8_unchecked(&bytes[..error.valid_up_to()]) }
+        }
+    }
+}
+
+static COMMAND_LINE: Once<CommandLine> = Once::new();
+
+/// Parses the configuration file, then the saved settings, then the load
+/// options, each overriding the last. Needs the heap and the firmware.
+pub fn init_command_line(boot_info: &X8664BootInfo) {
+    COMMAND_LINE.call_once(|| {
+        let mut command_line = CommandLine::parse_config(boot_info.config_file.as_str());
+        command_line.merge(kernel::settings::load::<X8664Platform>());
+        command_line.merge(CommandLine::parse(boot_info.load_options.as_str()));
+        command_line
+    });
+}
+
+/// The boot options. Panics if `init_command_line` hasn't run, since anything
+/// asking earlier would miss them.
+pub fn command_line() -> &'static CommandLine {
+    COMMAND_LINE.r#try().expect("Boot options asked for before they were read")
+}
+
+/// A copy of the firmware's memory map, for code that can't reach the
+/// platform or allocate, like the debugger.
+static MEMORY_MAP: Once<([X8664MemoryDescriptor; MAX_MEMORY_DESCRIPTORS], usize)> = Once::new();
+
+pub fn init_memory_map(boot_info: &X8664BootInfo) {
+    MEMORY_MAP.call_once(|| (boot_info.firmware_memory_map, boot_info.firmware_memory_map_length));
+}
+
+/// The firmware's memory map, in the order the firmware gave it. Empty until
+/// `init_memory_map` has run.
+pub fn memory_regions() -> impl Iterator<Item = MemoryRegion> {
+    let descriptors = match MEMORY_MAP.r#try() {
+        Some((descriptors, length)) => &descriptors[..*length],
+        None => &[]
+    };
+
+    descriptors.iter().map(|descriptor| MemoryRegion {
+        start: descriptor.start_address,
+        length: descriptor.length,
+        kind: memory_kind(descriptor.memory_type)
+    })
+}
+
+static DISPLAY_MODE: Once<Option<X8664DisplayMode>> = Once::new();
+
+pub fn init_display_mode(boot_info: &X8664BootInfo) {
+    DISPLAY_MODE.call_once(|| boot_info.display_mode);
+}
+
+/// The mode the firmware left the display in, for the display driver. None
+/// until `init_display_mode` has run.
+pub fn display_mode() -> Option<X8664DisplayMode> {
+    DISPLAY_MODE.r#try().and_then(|mode| *mode)
+}
+
+/// Sorts an `EFI_MEMORY_TYPE` into what the kernel cares about.
+fn memory_kind(memory_type: u32) -> MemoryKind {
+    match memory_type {
+        1 | 2 => MemoryKind::Loader,
+        3 | 4 => MemoryKind::BootServices,
+        5 | 6 => MemoryKind::RuntimeServices,
+        7 => MemoryKind::Conventional,
+        8 => MemoryKind::Unusable,
+        9 => MemoryKind::AcpiReclaimable,
+        10 => MemoryKind::AcpiNonVolatile,
+        11 | 12 => MemoryKind::Mmio,
+        _ => MemoryKind::Reserved
+    }
 }
@@ -0,0 +1,93 @@
+//! Kernel thread context switching.
+//!
+//! `switch_stacks` pushes the callee-saved registers and RFLAGS onto the
+//! current stack, saves RSP, then loads the other thread's RSP and pops the
+//! same registers back off. A new thread's stack is laid out to look as if it
+//! had been switched away from just before entering `thread_trampoline`.
+
+use core::mem::size_of;
+use kernel::{ThreadContext, ThreadEntry};
+
+/// RFLAGS for a new thread: just the reserved bit, so interrupts start
+/// disabled until the kernel's thread entry enables them.
+const INITIAL_RFLAGS: u64 = 0x2;
+
+/// Saved registers in the order `switch_stacks` pops them.
+#[repr(C)]
+struct InitialFrame {
+  rflags: u64,
+  r15: u64,
+  r14: u64,
+  r13: u64,
+  r12: u64,
+  rbx: u64,
+  rbp: u64,
+  return_address: u64
+}
+
+pub fn init_thread_context(stack: &mut [u8], entry: ThreadEntry, argument: usize) -> ThreadContext {
+  let top = (stack.as_mut_ptr() as usize + stack.len()) & !0xf;
+
+  // `ret` pops the return address, so it sits 8 bytes below a 16-byte
+  // boundary and the trampoline starts with RSP aligned.
+  let frame_address = top - size_of::<InitialFrame>();
+  let frame = InitialFrame {
+    rflags: INITIAL_RFLAGS,
+    r15: 0,
+    r14: 0,
+    r13: argument as u64,
+    r12: entry as usize as u64,
+    rbx: 0,
+    rbp: 0,
+    return_address: thread_trampoline as usize as u64
+  };
+
+  unsafe { core::ptr::write(frame_address as *mut InitialFrame, frame); }
+
+  ThreadContext { stack_pointer: frame_address }
+}
+
+pub unsafe fn switch_thread(from: *mut ThreadContext, to: *const ThreadContext) {
+  switch_stacks(&mut (*from).stack_pointer, &(*to).stack_pointer);
+}
+
+/// Uses the System V convention explicitly so the argument registers and the
+/// callee-saved set are the ones pushed below, whatever the target's C ABI.
+#[naked]
+#[inline(never)]
+unsafe extern "sysv64" fn switch_stacks(_from: *mut usize, _to: *const usize) {
+  asm!("
+    push rbp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    pushfq
+    mov [rdi], rsp
+    mov rsp, [rsi]
+    popfq
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    pop rbp
+    ret
+  " :::: "volatile", "intel");
+}
+
+/// First code a new thread runs. Calls the entry point in R12 with the
+/// argument in R13, using the target's C calling convention (Microsoft x64 on
+/// UEFI), which wants the argument in RCX and 32 bytes of shadow space.
+#[naked]
+#[inline(never)]
+unsafe extern "sysv64" fn thread_trampoline() -> ! {
+  asm!("
+    mov rcx, r13
+    sub rsp, 32
+    call r12
+    ud2
+  " :::: "volatile", "intel");
+  core::intrinsics::unreachable()
+}
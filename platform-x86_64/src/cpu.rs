@@ -0,0 +1,45 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::model_specific::Msr;
+
+/// The most CPUs we keep per-CPU state for.
+pub const MAX_CPUS: usize = 16;
+
+const IA32_GS_BASE: u32 = 0xc000_0101;
+
+/// What each CPU's GS base points at.
+#[repr(C)]
+struct PerCpu {
+  /// Must stay first: `current_id` reads it at `gs:0`.
+  index: AtomicUsize
+}
+
+const UNUSED: PerCpu = PerCpu { index: AtomicUsize::new(0) };
+static PER_CPU: [PerCpu; MAX_CPUS] = [UNUSED; MAX_CPUS];
+
+/// How many CPUs have called `init`.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Gives the CPU we're running on the next free index and points its GS base
+/// at its per-CPU data. Each CPU calls it once as it comes up, before anything
+/// asks which CPU it's on.
+pub fn init() {
+  let index = COUNT.fetch_add(1, Ordering::Relaxed);
+  assert!(index < MAX_CPUS, "Only {} CPUs are supported", MAX_CPUS);
+
+  PER_CPU[index].index.store(index, Ordering::Relaxed);
+  unsafe { Msr::new(IA32_GS_BASE).write(&PER_CPU[index] as *const PerCpu as u64); }
+}
+
+/// The index `init` gave the CPU we're running on, below `count`. It's one
+/// load through GS, since this is called on every interrupt, lock and log
+/// record.
+pub fn current_id() -> usize {
+  let index: usize;
+  unsafe { asm!("mov $0, qword ptr gs:[0]" : "=r"(index) ::: "volatile", "intel"); }
+  index
+}
+
+/// How many CPUs are up.
+pub fn count() -> usize {
+  COUNT.load(Ordering::Relaxed)
+}
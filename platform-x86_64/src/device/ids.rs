@@ -0,0 +1,92 @@
+//! Device IDs, handed out as devices are found.
+//!
+//! Each ID is tied to the address the device sits at and records which driver
+//! has it, along with an instance number counting that driver's devices from
+//! zero. Finding the same device again, say on a PCI rescan, gives back the
+//! same ID. The numbers themselves are never reused, so an event about a
+//! removed device can't be mistaken for one about its replacement.
+
+use alloc::{
+    collections::BTreeMap,
+    vec::Vec
+};
+use spin::Mutex;
+
+use super::{DeviceAddress, DeviceID};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub address: DeviceAddress,
+
+    /// The driver bound to the device, or `None` if it's unbound.
+    pub driver: Option<&'static str>,
+
+    /// Which of the driver's devices this is.
+    pub instance: u32
+}
+
+struct DeviceIds {
+    next: u32,
+    identities: BTreeMap<DeviceID, DeviceIdentity>
+}
+
+lazy_static! {
+    static ref DEVICE_IDS: Mutex<DeviceIds> = Mutex::new(DeviceIds {
+        next: 0,
+        identities: BTreeMap::new()
+    });
+}
+
+/// The ID for the device at `address`, allocating one if it doesn't have one.
+pub fn allocate(address: DeviceAddress, driver: Option<&'static str>) -> DeviceID {
+    let mut ids = DEVICE_IDS.lock();
+
+    let existing = ids.identities.iter()
+        .find(|(_, identity)| identity.address == address && identity.driver == driver)
+        .map(|(id, _)| *id);
+    if let Some(id) = existing {
+        return id;
+    }
+
+    // The lowest instance number not in use, so that a device unplugged and
+    // plugged back in keeps its number.
+    let mut instance = 0;
+    while ids.identities.values().any(|identity| identity.driver == driver && identity.instance == instance) {
+        instance += 1;
+    }
+
+    let id = DeviceID(ids.next);
+    ids.next += 1;
+
+    ids.identities.insert(id, DeviceIdentity { address, driver, instance });
+    id
+}
+
+/// Forgets a device that's gone away.
+pub fn release(id: DeviceID) {
+    DEVICE_IDS.lock().identities.remove(&id);
+}
+
+pub fn identity(id: DeviceID) -> Option<DeviceIdentity> {
+    DEVICE_IDS.lock().identities.get(&id).cloned()
+}
+
+/// The device at `address`, if it has an ID.
+pub fn find(address: DeviceAddress) -> Option<DeviceID> {
+    DEVICE_IDS.lock().identities.iter()
+        .find(|(_, identity)| identity.address == address)
+        .map(|(id, _)| *id)
+}
+
+/// Every device bound to `driver`, in instance order.
+pub fn instances(driver: &str) -> Vec<DeviceID> {
+    let ids = DEVICE_IDS.lock();
+
+    let mut instances: Vec<(u32, DeviceID)> = ids.identities.iter()
+        .filter(|(_, identity)| identity.driver == Some(driver))
+        .map(|(id, identity)| (identity.instance, *id))
+        .collect();
+    instances.sort();
+
+    instances.into_iter().map(|(_, id)| id).collect()
+}
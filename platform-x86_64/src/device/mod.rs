@@ -1,49 +1,160 @@
+pub mod ids;
 pub mod pci;
 pub mod pc_keyboard;
+pub mod pit;
+pub mod serial;
 
-use crate::X8664Platform;
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    sync::Arc
+};
+use core::fmt;
+use kernel::sync::{IrqSpinLock, LockClass};
 
-#[derive(Clone)]
+use crate::{X8664Platform, PlatformEvent, event_buffer};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum DeviceAddress {
+    /// A device on the legacy ISA bus, by its first I/O port.
+    ISA(u16),
+
+    /// A PCI host bridge, by segment number.
+    PCIRoot(u16),
     PCI(pci::PCIAddress)
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub enum DeviceID {
-  PCKeyboard,
-  Cirus5446
+/// Identifies a device for as long as it's connected. See `ids`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeviceID(pub(crate) u32);
+
+impl DeviceID {
+    pub fn identity(&self) -> Option<ids::DeviceIdentity> {
+        ids::identity(*self)
+    }
+}
+
+impl fmt::Debug for DeviceID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
 }
 
-#[derive(Clone)]
 pub enum Device {
     PCKeyboard(self::pc_keyboard::PCKeyboard),
-    Cirus5446(self::pci::graphics::cirus5446::Cirus5446)
+    PciBus(self::pci::PciBus),
+    Pit(self::pit::PitClock),
+    Serial(self::serial::SerialPort),
+    UnboundPci(self::pci::driver::UnboundPciDevice),
+
+    /// A device driven through the kernel's driver interface, by one of its
+    /// portable drivers or one of ours.
+    Portable(Box<dyn kernel::Device<X8664Platform>>)
 }
 
-impl kernel::Device<X8664Platform> for Device {
-    fn poll(&mut self) {
+impl Device {
+    /// The driver behind the variant, which every trait method goes to.
+    fn inner(&self) -> &dyn kernel::Device<X8664Platform> {
         match self {
-            Device::PCKeyboard(device) => device.poll(),
-            Device::Cirus5446(device) => device.poll(),
+            Device::PCKeyboard(device) => device,
+            Device::PciBus(device) => device,
+            Device::Pit(device) => device,
+            Device::Serial(device) => device,
+            Device::UnboundPci(device) => device,
+            Device::Portable(device) => device.as_ref()
         }
     }
+}
 
-    fn as_filesystem(&mut self) -> Option<&mut dyn kernel::Filesystem<X8664Platform>> {
-        match self {
-            Device::PCKeyboard(device) => device.as_filesystem(),
-            Device::Cirus5446(device) => device.as_filesystem(),
-        }
+impl kernel::Device<X8664Platform> for Device {
+    fn poll(&self) {
+        self.inner().poll()
     }
 
-    fn as_graphics_device(&mut self) -> Option<&mut dyn kernel::GraphicsDevice<X8664Platform>> {
-        match self {
-            Device::PCKeyboard(device) => device.as_graphics_device(),
-            Device::Cirus5446(device) => device.as_graphics_device(),
-        }
+    fn description(&self) -> kernel::DeviceDescription<X8664Platform> {
+        self.inner().description()
+    }
+
+    fn suspend(&self) {
+        self.inner().suspend()
+    }
+
+    fn resume(&self) {
+        self.inner().resume()
+    }
+
+    fn shutdown(&self) {
+        self.inner().shutdown()
+    }
+
+    fn as_filesystem(&self) -> Option<&dyn kernel::Filesystem<X8664Platform>> {
+        self.inner().as_filesystem()
+    }
+
+    fn as_graphics_device(&self) -> Option<&dyn kernel::GraphicsDevice<X8664Platform>> {
+        self.inner().as_graphics_device()
+    }
+
+    fn as_block_device(&self) -> Option<&dyn kernel::BlockDevice<X8664Platform>> {
+        self.inner().as_block_device()
+    }
+
+    fn as_char_device(&self) -> Option<&dyn kernel::CharDevice<X8664Platform>> {
+        self.inner().as_char_device()
+    }
+
+    fn as_input_device(&self) -> Option<&dyn kernel::InputDevice<X8664Platform>> {
+        self.inner().as_input_device()
+    }
+
+    fn as_network_device(&self) -> Option<&dyn kernel::NetworkDevice<X8664Platform>> {
+        self.inner().as_network_device()
     }
+
+    fn as_clock_device(&self) -> Option<&dyn kernel::ClockDevice<X8664Platform>> {
+        self.inner().as_clock_device()
+    }
+}
+
+static DEVICES_CLASS: LockClass = LockClass::new("devices", 40);
+
+lazy_static! {
+    /// Every connected device. The kernel fetches them from here when it sees
+    /// `DeviceConnected`.
+    static ref DEVICES: IrqSpinLock<BTreeMap<DeviceID, Arc<Device>>> = IrqSpinLock::ranked(BTreeMap::new(), &DEVICES_CLASS);
+}
+
+/// Makes a newly found device available and tells the kernel about it.
+pub(crate) fn connect(id: DeviceID, device: Device) {
+    let device = Arc::new(device);
+    DEVICES.lock().insert(id, device);
+
+    event_buffer::push_event(PlatformEvent::DeviceConnected(id));
+}
+
+/// Forgets a device that's gone away and tells the kernel. Anything still
+/// holding the device keeps it alive, but its ID is free to be forgotten.
+pub(crate) fn disconnect(id: DeviceID) {
+    let device = DEVICES.lock().remove(&id);
+    drop(device);
+
+    event_buffer::push_event(PlatformEvent::DeviceDisconnected(id));
+    ids::release(id);
+}
+
+pub fn get(id: DeviceID) -> Option<Arc<Device>> {
+    DEVICES.lock().get(&id).cloned()
+}
+
+/// Whether the boot options leave a driver on. Drivers are turned off by
+/// listing them in `drivers.disable`.
+pub fn driver_enabled(name: &str) -> bool {
+    !crate::boot_info::command_line().list("drivers.disable").contains(&name)
 }
 
 pub fn discover() {
+    pit::discover();
     pc_keyboard::discover();
+    serial::discover();
     pci::discover();
 }
\ No newline at end of file
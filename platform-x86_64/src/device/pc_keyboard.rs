@@ -1,27 +1,123 @@
+use alloc::collections::VecDeque;
+use kernel::{DeviceClass, DeviceDescription, InputEvent, Resource, sync::IrqSpinLock};
 use x86_64::instructions::port::Port;
 
-use crate::X8664Platform;
+use crate::{X8664Platform, device::DeviceID};
+
+pub const IRQ: u8 = 1;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+
+const STATUS_TIMEOUT: u8 = 1 << 6;
+const STATUS_PARITY_ERROR: u8 = 1 << 7;
+
+/// Scan code set 1 sends this before the codes of keys added after the XT.
+const SCANCODE_EXTENDED: u8 = 0xe0;
+const SCANCODE_RELEASED: u8 = 1 << 7;
+
+/// Key events kept for `next_event`. Older events are dropped past this.
+const EVENT_CAPACITY: usize = 64;
 
 pub fn discover() {
-  use crate::{event_buffer, PlatformEvent, device::DeviceID, Device};
+  use crate::{interrupts, device::{self, DeviceAddress, ids}, Device};
+
+  if !device::driver_enabled("i8042") {
+    log::info!("Keyboard driver disabled");
+    return;
+  }
 
-  event_buffer::push_event(PlatformEvent::DeviceConnected(
-    DeviceID::PCKeyboard, 
-    Device::PCKeyboard(PCKeyboard::new())
-  ));
+  let id = ids::allocate(DeviceAddress::ISA(DATA_PORT), Some("i8042"));
+  if let Err(error) = interrupts::route_isa_irq(IRQ, id) {
+    log::warn!("Keyboard IRQ not routed: {}", error);
+  }
+
+  device::connect(id, Device::PCKeyboard(PCKeyboard::new(id)));
+}
+
+pub struct PCKeyboard {
+  id: DeviceID,
+  state: IrqSpinLock<KeyboardState>
 }
 
-#[derive(Clone)]
-pub struct PCKeyboard;
+struct KeyboardState {
+  events: VecDeque<InputEvent>,
+
+  /// Set after an extended prefix, until the code that follows it.
+  extended: bool
+}
 
 impl PCKeyboard {
-  pub fn new() -> Self { PCKeyboard }
+  pub fn new(id: DeviceID) -> Self {
+    PCKeyboard {
+      id,
+      state: IrqSpinLock::new(KeyboardState { events: VecDeque::new(), extended: false })
+    }
+  }
+}
+
+impl KeyboardState {
+  /// Turns scan code set 1 bytes into key events. Extended keys get codes
+  /// with `0xe0` in the high byte.
+  fn decode(&mut self, scancode: u8) {
+    if scancode == SCANCODE_EXTENDED {
+      self.extended = true;
+      return;
+    }
+
+    let mut code = (scancode & !SCANCODE_RELEASED) as u16;
+    if self.extended {
+      code |= (SCANCODE_EXTENDED as u16) << 8;
+      self.extended = false;
+    }
+
+    if self.events.len() == EVENT_CAPACITY {
+      self.events.pop_front();
+    }
+    self.events.push_back(InputEvent::Key { code, pressed: scancode & SCANCODE_RELEASED == 0 });
+  }
 }
 
 impl kernel::Device<X8664Platform> for PCKeyboard {
-  fn poll(&mut self) {
-    let mut keyboard_controller: Port<u8> = Port::new(0x60);
+  fn description(&self) -> DeviceDescription<X8664Platform> {
+    DeviceDescription {
+      parent: None,
+      name: "i8042".into(),
+      class: DeviceClass::Input,
+      driver: Some("i8042"),
+      resources: alloc::vec![
+        Resource::IoPort { base: DATA_PORT, size: 1 },
+        Resource::IoPort { base: STATUS_PORT, size: 1 },
+        Resource::Irq(IRQ as u32)
+      ]
+    }
+  }
+
+  fn poll(&self) {
+    use crate::{event_buffer, PlatformEvent, error::X8664Error};
+
+    let mut status_register: Port<u8> = Port::new(STATUS_PORT);
+    let status = unsafe { status_register.read() };
+
+    // The byte still has to be read to clear the error.
+    let mut keyboard_controller: Port<u8> = Port::new(DATA_PORT);
     let scancode = unsafe { keyboard_controller.read() };
-    log::info!("Keyboard scancode {:#x}", scancode);
+
+    if status & STATUS_PARITY_ERROR != 0 {
+      event_buffer::push_event(PlatformEvent::DeviceError(self.id, X8664Error::Io("keyboard parity error")));
+    } else if status & STATUS_TIMEOUT != 0 {
+      event_buffer::push_event(PlatformEvent::DeviceError(self.id, X8664Error::DeviceTimeout("keyboard")));
+    } else {
+      log::debug!("Keyboard scancode {:#x}", scancode);
+      self.state.lock().decode(scancode);
+    }
+  }
+
+  fn as_input_device(&self) -> Option<&dyn kernel::InputDevice<X8664Platform>> { Some(self) }
+}
+
+impl kernel::InputDevice<X8664Platform> for PCKeyboard {
+  fn next_event(&self) -> Option<InputEvent> {
+    self.state.lock().events.pop_front()
   }
 }
\ No newline at end of file
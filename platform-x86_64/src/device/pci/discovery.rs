@@ -1,35 +1,92 @@
-use super::PCIAddress;
-use crate::device::DeviceAddress;
+use alloc::collections::BTreeMap;
+use spin::{Mutex, Once};
+
+use super::{
+    PCIAddress,
+    driver::{self, PciDeviceInfo, UnboundPciDevice}
+};
+use crate::device::{DeviceAddress, DeviceID, ids};
+
+/// Slot numbers are 5 bits; higher values alias slots on the next bus.
+const SLOTS_PER_BUS: u8 = 32;
+
+static BUS_ID: Once<DeviceID> = Once::new();
+
+lazy_static! {
+    /// Every occupied slot from the last scan, with the device we connected
+    /// for it.
+    static ref PRESENT: Mutex<BTreeMap<(u8, u8), DeviceID>> = Mutex::new(BTreeMap::new());
+}
 
 pub fn discover() {
-    use crate::{event_buffer, PlatformEvent, device::DeviceID, Device};
+    use crate::{device, Device};
+
+    driver::register_builtin();
+
+    let id = *BUS_ID.call_once(|| ids::allocate(DeviceAddress::PCIRoot(0), Some("pci")));
+    device::connect(id, Device::PciBus(super::PciBus));
+
+    rescan();
+}
+
+/// The ID of the PCI bus, the parent of every PCI device.
+pub fn bus_id() -> Option<DeviceID> {
+    BUS_ID.r#try().cloned()
+}
+
+/// Scans every slot, connecting devices that have appeared since the last
+/// scan and disconnecting those that have gone.
+pub fn rescan() {
+    let mut present = PRESENT.lock();
+    let mut found = BTreeMap::new();
 
     for bus in 0..255 {
-        for slot in 0..255 {
+        for slot in 0..SLOTS_PER_BUS {
             let address = PCIAddress::new(bus, slot);
             let vendor = address.read_word(0, 0);
 
             if vendor != 0xffff {
-                let device = address.read_word(0, 2);
+                let id = match present.remove(&(bus, slot)) {
+                    Some(id) => id,
+                    None => connect(address)
+                };
 
-                match (vendor, device) {
-                    (0x1234, 0x1111) => {
-                        event_buffer::push_event(PlatformEvent::DeviceConnected(
-                            DeviceID::Cirus5446, 
-                            Device::Cirus5446(super::graphics::cirus5446::Cirus5446::new(DeviceAddress::PCI(address)))
-                        ));
-                    },
+                found.insert((bus, slot), id);
+            }
+        }
+    }
 
-                    (0x1033, 0x194) => {
+    for ((bus, slot), id) in present.iter() {
+        log::info!("PCI device at {:02x}:{:02x} removed", bus, slot);
+        crate::device::disconnect(*id);
+    }
 
-                    },
+    *present = found;
+}
 
-                    _ => {
-                        log::info!("Unknown PCI device with vendor {:#04x}, device {:#04x}", vendor, device);
-                    }
-                }
-            }
+/// Binds a driver to a newly found device, or connects it unbound if no
+/// driver wants it.
+fn connect(address: PCIAddress) -> DeviceID {
+    use crate::{device, Device};
+
+    let info = PciDeviceInfo::read(&crate::hal::HAL, address.location());
+
+    match driver::bind(&info) {
+        Some((id, driver, device)) => {
+            log::info!("PCI device {:04x}:{:04x} at {} bound to {}",
+                info.vendor, info.device, address.name(), driver);
+
+            device::connect(id, device);
+            id
+        },
+
+        None => {
+            log::info!("PCI device {:04x}:{:04x} (class {:02x}:{:02x}:{:02x}) at {} has no driver",
+                info.vendor, info.device, info.class, info.subclass, info.prog_if, address.name());
+
+            let id = ids::allocate(DeviceAddress::PCI(address), None);
+            device::connect(id, Device::UnboundPci(UnboundPciDevice::new(info)));
+            id
         }
     }
 }
-
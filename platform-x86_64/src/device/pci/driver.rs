@@ -0,0 +1,94 @@
+//! PCI drivers and how they're matched to devices.
+//!
+//! Each driver lists the devices it handles in a match table. When discovery
+//! finds a device, the first registered driver with a matching entry gets to
+//! probe it. The platform's own drivers go ahead of the kernel's, which are
+//! registered before discovery starts.
+//! Devices nobody claims still show up in the device tree, unbound.
+
+use alloc::vec::Vec;
+use kernel::{DeviceDescription, Platform, ProbeContext, Resource};
+use spin::Mutex;
+
+use crate::{
+    X8664Platform,
+    boot_info,
+    device::{self, Device, DeviceAddress, DeviceID, ids}
+};
+use super::PCIAddress;
+
+pub use kernel::{PciDeviceInfo, PciDriver, PciMatch};
+
+lazy_static! {
+    static ref DRIVERS: Mutex<Vec<PciDriver<X8664Platform>>> = Mutex::new(Vec::new());
+}
+
+/// Drivers are tried in the order they're registered, so register specific
+/// drivers before class-wide ones.
+pub fn register(driver: PciDriver<X8664Platform>) {
+    log::debug!("Registered PCI driver {}", driver.name);
+    DRIVERS.lock().push(driver);
+}
+
+pub fn register_builtin() {
+    let driver = super::graphics::cirus5446::DRIVER;
+    log::debug!("Registered PCI driver {}", driver.name);
+    DRIVERS.lock().insert(0, driver);
+}
+
+/// Finds a driver for the device and probes it, returning the device's ID
+/// and the driver's name along with the device.
+pub fn bind(info: &PciDeviceInfo) -> Option<(DeviceID, &'static str, Device)> {
+    let address = DeviceAddress::PCI(PCIAddress::from(info.location));
+
+    // Drivers need their ID up front, to route IRQs to it.
+    let drivers = DRIVERS.lock().clone();
+    for driver in drivers.iter().filter(|driver| driver.matches(info) && device::driver_enabled(driver.name)) {
+        let id = ids::allocate(address, Some(driver.name));
+        let context = ProbeContext {
+            id,
+            parent: super::bus_id(),
+            resources: PCIAddress::from(info.location).resources(),
+            hal: X8664Platform::hal(),
+            command_line: boot_info::command_line()
+        };
+
+        match (driver.probe)(info, context) {
+            Ok(device) => return Some((id, driver.name, Device::Portable(device))),
+            Err(error) => {
+                log::warn!("PCI driver {} failed to probe {}: {:?}", driver.name, info.location, error);
+                ids::release(id);
+            }
+        }
+    }
+
+    None
+}
+
+/// A device no driver has claimed. It sits in the device tree so it can be
+/// seen, but does nothing.
+pub struct UnboundPciDevice {
+    info: PciDeviceInfo,
+    resources: Vec<Resource>
+}
+
+impl UnboundPciDevice {
+    pub fn new(info: PciDeviceInfo) -> Self {
+        let resources = PCIAddress::from(info.location).resources();
+        UnboundPciDevice { info, resources }
+    }
+}
+
+impl kernel::Device<X8664Platform> for UnboundPciDevice {
+    fn poll(&self) {}
+
+    fn description(&self) -> DeviceDescription<X8664Platform> {
+        DeviceDescription {
+            parent: super::bus_id(),
+            name: PCIAddress::from(self.info.location).name(),
+            class: self.info.device_class(),
+            driver: None,
+            resources: self.resources.clone()
+        }
+    }
+}
@@ -1,53 +1,102 @@
-use crate::{X8664Platform, error::X8664Error, device::DeviceAddress};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::ptr;
+use kernel::{DeviceClass, DeviceDescription, ProbeContext, Resource, sync::Mutex};
 
-#[derive(Clone)]
-pub struct Cirus5446 {
-    device_address: DeviceAddress,
-    framebuffer_address: usize
-}
+use crate::{
+    X8664Platform,
+    boot_info::{self, X8664DisplayMode},
+    error::X8664Error,
+    device::{DeviceID, pci::{PCIAddress, driver::{PciDeviceInfo, PciDriver, PciMatch}}},
+    logging::framebuffer
+};
+
+pub static DRIVER: PciDriver<X8664Platform> = PciDriver {
+    name: "cirus5446",
+    matches: &[PciMatch::device(0x1234, 0x1111)],
+    probe
+};
 
-impl Cirus5446 {
-    pub fn new(device_address: DeviceAddress) -> Self {
-        let framebuffer_address = match device_address {
-            DeviceAddress::PCI(ref pci_address) => pci_address.read_dword(0, 0x10) as usize
-        };
+/// Bytes in a pixel. The firmware only hands over 32-bit modes.
+const PIXEL_SIZE: usize = 4;
 
-        Self { device_address, framebuffer_address }
+fn probe(info: &PciDeviceInfo, context: ProbeContext<X8664Platform>) -> Result<Box<dyn kernel::Device<X8664Platform>>, X8664Error> {
+    // The mode isn't set here, so draw in the one the firmware left.
+    let mode = boot_info::display_mode().ok_or(X8664Error::DeviceFault("the display mode isn't known"))?;
+
+    // The framebuffer is BAR 0, the first memory resource.
+    let (framebuffer_address, framebuffer_size) = context.resources.iter()
+        .filter_map(|resource| match resource {
+            Resource::Memory { base, size, .. } => Some((*base as usize, *size as usize)),
+            _ => None
+        })
+        .next()
+        .filter(|&(base, _)| base != 0)
+        .ok_or(X8664Error::DeviceFault("framebuffer BAR isn't assigned"))?;
+
+    if mode.stride * mode.height * PIXEL_SIZE > framebuffer_size {
+        return Err(X8664Error::DeviceFault("the display mode doesn't fit in the framebuffer"));
     }
 
-    fn framebuffer(&mut self) -> &mut Framebuffer {
-        unsafe { &mut *(self.framebuffer_address as *mut Framebuffer) }
+    framebuffer::attach(framebuffer_address, mode);
+
+    Ok(Box::new(Cirus5446 {
+        parent: context.parent,
+        name: PCIAddress::from(info.location).name(),
+        mode,
+        framebuffer: Mutex::new(framebuffer_address),
+        resources: context.resources
+    }))
+}
+
+pub struct Cirus5446 {
+    parent: Option<DeviceID>,
+    name: String,
+    mode: X8664DisplayMode,
+
+    /// The framebuffer's address. It's locked while drawing so that users
+    /// sharing the device can't interleave their writes.
+    framebuffer: Mutex<usize>,
+    resources: Vec<Resource>
+}
+
+impl Drop for Cirus5446 {
+    /// The console mustn't draw on a framebuffer with no driver behind it.
+    fn drop(&mut self) {
+        framebuffer::detach();
     }
 }
 
 impl kernel::Device<X8664Platform> for Cirus5446 {
-    fn poll(&mut self) { unimplemented!() }
-    fn as_graphics_device(&mut self) -> Option<&mut dyn kernel::GraphicsDevice<X8664Platform>> { Some(self) }
+    fn poll(&self) { unimplemented!() }
+
+    fn shutdown(&self) {
+        framebuffer::detach();
+    }
+
+    fn description(&self) -> DeviceDescription<X8664Platform> {
+        DeviceDescription {
+            parent: self.parent,
+            name: self.name.clone(),
+            class: DeviceClass::Display,
+            driver: Some("cirus5446"),
+            resources: self.resources.clone()
+        }
+    }
+
+    fn as_graphics_device(&self) -> Option<&dyn kernel::GraphicsDevice<X8664Platform>> { Some(self) }
 }
 
 impl kernel::GraphicsDevice<X8664Platform> for Cirus5446 {
-    fn clear(&mut self) -> Result<(), X8664Error> {
-        let framebuffer = self.framebuffer();
+    fn clear(&self) -> Result<(), X8664Error> {
+        let address = self.framebuffer.lock();
+        let pixels = *address as *mut u32;
 
-        for x in 0..1024 {
-            for y in 0..768 {
-                framebuffer.pixels[x][y] = BGRA8888Pixel { b: 0, g: 0, r: 0, a: 0 };
+        for y in 0..self.mode.height {
+            for x in 0..self.mode.width {
+                unsafe { ptr::write_volatile(pixels.add(y * self.mode.stride + x), 0); }
             }
         }
 
         Ok(())
     }
 }
-
-#[repr(packed)]
-struct BGRA8888Pixel {
-    b: u8, 
-    g: u8, 
-    r: u8, 
-    a: u8
-}
-
-#[repr(transparent)]
-struct Framebuffer {
-    pixels: [[BGRA8888Pixel; 768]; 1024] 
-}
@@ -0,0 +1,120 @@
+//! PCI hotplug through QEMU's ACPI hotplug controller, as used by
+//! `device_add` and `device_del`.
+//!
+//! Both of QEMU's machines have the same controller at different ports: the
+//! PIIX4 on i440FX, where devices are plugged into bus 0, and the ICH9 on
+//! q35, where they're plugged into PCIe root ports. We tell the two apart by
+//! the host bridge. Each bus that takes hotplugged devices has a selector
+//! number, and the status and eject registers refer to whichever bus was
+//! selected last.
+//!
+//! Normally the firmware's AML handles the hotplug GPE and tells the OS which
+//! slots changed. We don't run AML, so we rescan the bus instead, and do the
+//! one thing AML would have done for us: eject slots QEMU asks to unplug.
+//! QEMU only completes a `device_del` once the guest ejects the slot.
+//!
+//! USB devices aren't covered. There's no USB host controller driver, so
+//! nothing would notice them come or go.
+
+use spin::Once;
+use x86_64::instructions::port::Port;
+
+use crate::acpi;
+use super::{PCIAddress, discovery};
+
+/// The general purpose event QEMU raises when a slot changes, on both
+/// machines.
+pub const GPE: u8 = 1;
+
+/// Host bridge vendor and device IDs.
+const I440FX: (u16, u16) = (0x8086, 0x1237);
+const Q35: (u16, u16) = (0x8086, 0x29c0);
+
+/// Where the controller's registers start on each machine.
+const PIIX4_BASE: u16 = 0xae00;
+const ICH9_BASE: u16 = 0x0cc4;
+
+/// Bitmask of the selected bus's slots QEMU wants to unplug.
+const PCI_DOWN: u16 = 0x04;
+
+/// Writing a slot's bit here ejects it from the selected bus.
+const PCI_EJECT: u16 = 0x08;
+
+/// Which bus `PCI_DOWN` and `PCI_EJECT` refer to.
+const PCI_SELECT: u16 = 0x10;
+
+/// How many bus selectors to check. QEMU numbers buses from 0, and shows
+/// nothing pending for numbers it hasn't given out.
+const BUS_SELECTORS: u32 = 32;
+
+/// What the registers read as when there's nothing behind the ports.
+const NO_CONTROLLER: u32 = 0xffff_ffff;
+
+/// The controller's base port, or `None` if there isn't one we know.
+static CONTROLLER: Once<Option<u16>> = Once::new();
+
+pub fn init() {
+    if CONTROLLER.call_once(probe).is_some() {
+        acpi::enable_gpe(GPE);
+    }
+}
+
+/// Finds the controller for the chipset we're on.
+fn probe() -> Option<u16> {
+    let host_bridge = PCIAddress::new(0, 0);
+    let base = match (host_bridge.read_word(0, 0), host_bridge.read_word(0, 2)) {
+        I440FX => PIIX4_BASE,
+        Q35 => ICH9_BASE,
+        (vendor, device) => {
+            log::info!("PCI hotplug: no controller known for host bridge {:04x}:{:04x}", vendor, device);
+            return None;
+        }
+    };
+
+    if read(base, 0, PCI_DOWN) == NO_CONTROLLER {
+        log::info!("PCI hotplug: no ACPI hotplug controller at {:#x}", base);
+        return None;
+    }
+
+    log::info!("PCI hotplug: ACPI hotplug controller at {:#x}", base);
+    Some(base)
+}
+
+fn read(base: u16, selector: u32, register: u16) -> u32 {
+    let mut select: Port<u32> = Port::new(base + PCI_SELECT);
+    let mut port: Port<u32> = Port::new(base + register);
+
+    unsafe {
+        select.write(selector);
+        port.read()
+    }
+}
+
+/// True if the pending GPEs include a hotplug notification.
+pub fn is_hotplug_event(gpes: u64) -> bool {
+    gpes & (1 << GPE) != 0
+}
+
+/// Ejects any slots waiting to be unplugged, then brings the device registry
+/// up to date with what's on the bus.
+pub fn handle() {
+    if let Some(base) = CONTROLLER.r#try().and_then(|base| *base) {
+        for selector in 0..BUS_SELECTORS {
+            let slots = read(base, selector, PCI_DOWN);
+            if slots == NO_CONTROLLER {
+                break;
+            }
+
+            for slot in 0..32 {
+                if slots & (1 << slot) != 0 {
+                    log::info!("PCI slot {} on hotplug bus {} unplug requested, ejecting", slot, selector);
+
+                    let mut eject: Port<u32> = Port::new(base + PCI_EJECT);
+                    unsafe { eject.write(1 << slot); }
+                }
+            }
+        }
+    }
+
+    discovery::rescan();
+}
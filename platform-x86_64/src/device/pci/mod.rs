@@ -1,10 +1,57 @@
 mod discovery;
+pub mod driver;
+pub mod hotplug;
 
 pub mod graphics;
 
-pub use discovery::discover;
+pub use discovery::{bus_id, discover, rescan};
 
-#[derive(Clone)]
+use alloc::{
+    format,
+    string::String,
+    vec::Vec
+};
+use kernel::{DeviceClass, DeviceDescription, Resource, hal::PciLocation};
+
+use crate::X8664Platform;
+
+/// Legacy configuration mechanism #1 ports.
+const CONFIG_PORTS: u16 = 0xcf8;
+
+/// The PCI bus itself. It's polled when the hotplug controller signals a
+/// change, and rescans the bus.
+pub struct PciBus;
+
+impl kernel::Device<X8664Platform> for PciBus {
+    fn poll(&self) {
+        hotplug::handle();
+    }
+
+    fn description(&self) -> DeviceDescription<X8664Platform> {
+        DeviceDescription {
+            parent: None,
+            name: "pci0".into(),
+            class: DeviceClass::Bus,
+            driver: Some("pci"),
+            resources: alloc::vec![Resource::IoPort { base: CONFIG_PORTS, size: 8 }]
+        }
+    }
+}
+
+const CONFIG_COMMAND: u8 = 0x04;
+const CONFIG_BAR0: u8 = 0x10;
+const CONFIG_INTERRUPT_LINE: u8 = 0x3c;
+
+const COMMAND_IO_SPACE: u16 = 1 << 0;
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+
+const BAR_COUNT: u8 = 6;
+const BAR_IO_SPACE: u32 = 1 << 0;
+const BAR_TYPE_MASK: u32 = 0x6;
+const BAR_TYPE_64: u32 = 0x4;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PCIAddress(u8, u8);
 
 impl PCIAddress {
@@ -13,6 +60,11 @@ impl PCIAddress {
     pub fn bus(&self) -> u8 { self.0 }
     pub fn slot(&self) -> u8 { self.1 }
 
+    /// Function 0 of the slot, as portable drivers see it.
+    pub fn location(&self) -> PciLocation {
+        PciLocation { segment: 0, bus: self.bus(), device: self.slot(), function: 0 }
+    }
+
     fn pci_address(&self, function: u8, offset: u8) -> u32 {
         let bus = self.bus() as u32;
         let slot = self.slot() as u32;
@@ -44,4 +96,93 @@ impl PCIAddress {
 
         word
     }
+
+    pub fn read_byte(&self, function: u8, offset: u8) -> u8 {
+        let dword = self.read_dword(function, offset & 0xfc);
+        (dword >> ((offset & 3) * 8) & 0xff) as u8
+    }
+
+    pub fn write_dword(&self, function: u8, offset: u8, value: u32) {
+        use x86_64::instructions::port::Port;
+
+        let mut config_address: Port<u32> = Port::new(0xCF8);
+        let mut config_data: Port<u32> = Port::new(0xCFC);
+
+        let address = self.pci_address(function, offset);
+
+        unsafe { config_address.write(address); }
+        unsafe { config_data.write(value); }
+    }
+
+    /// The `bus:slot.function` form used in device paths.
+    pub fn name(&self) -> String {
+        format!("{:02x}:{:02x}.0", self.bus(), self.slot())
+    }
+
+    /// The BARs and interrupt line of function 0. Sizing a BAR means briefly
+    /// overwriting it, so decoding is switched off while we do.
+    pub fn resources(&self) -> Vec<Resource> {
+        let mut resources = Vec::new();
+
+        let command = self.read_word(0, CONFIG_COMMAND);
+        self.write_dword(0, CONFIG_COMMAND, (command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE)) as u32);
+
+        let mut bar = 0;
+        while bar < BAR_COUNT {
+            let offset = CONFIG_BAR0 + bar * 4;
+            let original = self.read_dword(0, offset);
+
+            self.write_dword(0, offset, 0xffff_ffff);
+            let mask = self.read_dword(0, offset);
+            self.write_dword(0, offset, original);
+
+            bar += 1;
+
+            if mask == 0 {
+                continue;
+            }
+
+            if original & BAR_IO_SPACE != 0 {
+                let size = (!(mask & !0x3) as u16).wrapping_add(1);
+                resources.push(Resource::IoPort { base: (original & !0x3) as u16, size });
+                continue;
+            }
+
+            let prefetchable = original & BAR_PREFETCHABLE != 0;
+            let mut base = (original & !0xf) as u64;
+            let mut size_mask = (mask & !0xf) as u64 | 0xffff_ffff_0000_0000;
+
+            if original & BAR_TYPE_MASK == BAR_TYPE_64 && bar < BAR_COUNT {
+                let upper_offset = CONFIG_BAR0 + bar * 4;
+                let upper = self.read_dword(0, upper_offset);
+
+                self.write_dword(0, upper_offset, 0xffff_ffff);
+                let upper_mask = self.read_dword(0, upper_offset);
+                self.write_dword(0, upper_offset, upper);
+
+                base |= (upper as u64) << 32;
+                size_mask = (size_mask & 0xffff_ffff) | (upper_mask as u64) << 32;
+                bar += 1;
+            }
+
+            resources.push(Resource::Memory { base, size: (!size_mask).wrapping_add(1), prefetchable });
+        }
+
+        self.write_dword(0, CONFIG_COMMAND, command as u32);
+
+        let interrupt_line = self.read_byte(0, CONFIG_INTERRUPT_LINE);
+        if interrupt_line != 0xff && interrupt_line != 0 {
+            resources.push(Resource::Irq(interrupt_line as u32));
+        }
+
+        resources
+    }
+}
+
+impl From<PciLocation> for PCIAddress {
+    /// We only scan segment 0, and only function 0 of each slot, so those
+    /// parts of the location are dropped.
+    fn from(location: PciLocation) -> Self {
+        PCIAddress::new(location.bus, location.device)
+    }
 }
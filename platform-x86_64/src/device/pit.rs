@@ -0,0 +1,209 @@
+//! Driver for the 8254 programmable interval timer.
+//!
+//! Channel 0 drives ISA IRQ 0. We run it as a periodic rate generator for the
+//! clock tick, and briefly as a one-shot to calibrate the time-stamp counter
+//! during boot.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use spin::Mutex;
+use kernel::{DeviceClass, DeviceDescription, Resource};
+use x86_64::instructions::port::Port;
+
+use crate::X8664Platform;
+
+/// The PIT's input clock, in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+/// The tick rate we program at boot.
+pub const DEFAULT_RATE: u32 = 100;
+
+/// The ISA IRQ channel 0 is wired to.
+pub const IRQ: u8 = 0;
+
+const CHANNEL_0: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+const SELECT_CHANNEL_0: u8 = 0b00 << 6;
+const ACCESS_LOBYTE_HIBYTE: u8 = 0b11 << 4;
+const READ_BACK_STATUS_CHANNEL_0: u8 = 0b1110_0010;
+const STATUS_OUTPUT: u8 = 1 << 7;
+
+/// How long the TSC calibration one-shot runs for, in milliseconds.
+const CALIBRATION_MS: u32 = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Mode {
+  /// Counts down once, raising the output (and IRQ 0) at zero.
+  InterruptOnTerminalCount = 0,
+
+  /// Pulses the output every time the counter wraps.
+  RateGenerator = 2,
+}
+
+struct Pit {
+  channel_0: Port<u8>,
+  command: Port<u8>
+}
+
+lazy_static! {
+  static ref PIT: Mutex<Pit> = Mutex::new(Pit {
+    channel_0: Port::new(CHANNEL_0),
+    command: Port::new(COMMAND)
+  });
+}
+
+static RATE: AtomicU32 = AtomicU32::new(0);
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Set while the IRQ 0 a one-shot raises hasn't been handled. Calibration
+/// runs with interrupts off, so it's still pending once the periodic tick
+/// starts.
+static ONE_SHOT_PENDING: AtomicBool = AtomicBool::new(false);
+
+impl Pit {
+  fn program(&mut self, mode: Mode, count: u16) {
+    unsafe {
+      self.command.write(SELECT_CHANNEL_0 | ACCESS_LOBYTE_HIBYTE | ((mode as u8) << 1));
+      self.channel_0.write(count as u8);
+      self.channel_0.write((count >> 8) as u8);
+    }
+  }
+
+  fn output_high(&mut self) -> bool {
+    unsafe {
+      self.command.write(READ_BACK_STATUS_CHANNEL_0);
+      self.channel_0.read() & STATUS_OUTPUT != 0
+    }
+  }
+}
+
+/// Adds the PIT to the device registry as a clock. It's polled by nothing;
+/// the IRQ 0 handler drives it directly.
+pub fn discover() {
+  use crate::device::{self, Device, DeviceAddress, ids};
+
+  let id = ids::allocate(DeviceAddress::ISA(CHANNEL_0), Some("i8254"));
+  device::connect(id, Device::Pit(PitClock));
+}
+
+pub struct PitClock;
+
+impl kernel::Device<X8664Platform> for PitClock {
+  fn poll(&self) {}
+
+  fn description(&self) -> DeviceDescription<X8664Platform> {
+    DeviceDescription {
+      parent: None,
+      name: "i8254".into(),
+      class: DeviceClass::Clock,
+      driver: Some("i8254"),
+      resources: alloc::vec![
+        Resource::IoPort { base: CHANNEL_0, size: 4 },
+        Resource::Irq(IRQ as u32)
+      ]
+    }
+  }
+
+  fn as_clock_device(&self) -> Option<&dyn kernel::ClockDevice<X8664Platform>> { Some(self) }
+}
+
+impl kernel::ClockDevice<X8664Platform> for PitClock {
+  fn ticks(&self) -> u64 {
+    ticks()
+  }
+
+  fn frequency(&self) -> u64 {
+    rate() as u64
+  }
+}
+
+/// Calibrates the TSC and starts the periodic clock tick. Call this with
+/// interrupts disabled, since calibration fires IRQ 0 once.
+pub fn init() {
+  calibrate_tsc();
+  set_rate(DEFAULT_RATE);
+
+  log::info!("PIT: clock ticking at {} Hz, TSC running at {} MHz",
+    rate(), tsc_frequency() / 1_000_000);
+}
+
+/// Programs channel 0 as a rate generator firing `hz` times a second.
+pub fn set_rate(hz: u32) {
+  let divisor = divisor_for(hz);
+
+  PIT.lock().program(Mode::RateGenerator, divisor);
+  RATE.store(BASE_FREQUENCY / divisor_value(divisor), Ordering::Relaxed);
+}
+
+/// The rate channel 0 is actually firing at, after rounding the divisor.
+pub fn rate() -> u32 {
+  RATE.load(Ordering::Relaxed)
+}
+
+/// Programs channel 0 to count down `count` input clocks and raise IRQ 0
+/// once. The periodic tick stops until `set_rate` is called again.
+pub fn one_shot(count: u16) {
+  PIT.lock().program(Mode::InterruptOnTerminalCount, count);
+  RATE.store(0, Ordering::Relaxed);
+  ONE_SHOT_PENDING.store(true, Ordering::Relaxed);
+}
+
+/// Spins until a one-shot started by `one_shot` reaches zero.
+pub fn wait_one_shot() {
+  let mut pit = PIT.lock();
+  while !pit.output_high() {
+    core::sync::atomic::spin_loop_hint();
+  }
+}
+
+/// Called from the IRQ 0 handler. Returns false for the interrupt that ends
+/// a one-shot, which isn't a clock tick and isn't counted.
+pub(crate) fn tick() -> bool {
+  if ONE_SHOT_PENDING.swap(false, Ordering::Relaxed) {
+    return false;
+  }
+
+  TICKS.fetch_add(1, Ordering::Relaxed);
+  true
+}
+
+/// Clock ticks since the periodic timer was started.
+pub fn ticks() -> u64 {
+  TICKS.load(Ordering::Relaxed)
+}
+
+/// The TSC frequency in Hz, as measured against the PIT at boot.
+pub fn tsc_frequency() -> u64 {
+  TSC_FREQUENCY.load(Ordering::Relaxed)
+}
+
+fn calibrate_tsc() {
+  use core::arch::x86_64::_rdtsc;
+
+  let count = (BASE_FREQUENCY * CALIBRATION_MS / 1000) as u16;
+
+  one_shot(count);
+  let start = unsafe { _rdtsc() };
+  wait_one_shot();
+  let end = unsafe { _rdtsc() };
+
+  let frequency = (end - start) * 1000 / CALIBRATION_MS as u64;
+  TSC_FREQUENCY.store(frequency, Ordering::Relaxed);
+}
+
+/// A reload value of zero means 65536.
+fn divisor_for(hz: u32) -> u16 {
+  let divisor = BASE_FREQUENCY / hz.max(1);
+
+  if divisor >= 0x10000 {
+    0
+  } else {
+    divisor.max(1) as u16
+  }
+}
+
+fn divisor_value(divisor: u16) -> u32 {
+  if divisor == 0 { 0x10000 } else { divisor as u32 }
+}
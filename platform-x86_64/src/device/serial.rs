@@ -0,0 +1,192 @@
+//! Driver for the 16550 UART on COM1.
+//!
+//! The firmware has already set the line up, and the log writes straight to
+//! the transmit register, so all we add is receiving: the UART raises IRQ 4
+//! when a byte arrives, and `poll` moves whatever it has into a buffer for
+//! `read`.
+//!
+//! The GDB stub drives COM2 with the same type, polling it directly.
+
+use alloc::collections::VecDeque;
+use kernel::{DeviceClass, DeviceDescription, Resource, sync::IrqSpinLock};
+use x86_64::instructions::port::Port;
+
+use crate::{X8664Platform, error::X8664Error};
+
+pub const IRQ: u8 = 4;
+
+const COM1: u16 = 0x3f8;
+pub const COM2: u16 = 0x2f8;
+
+/// IRQ the UART on COM2 raises.
+pub const COM2_IRQ: u8 = 3;
+
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const INTERRUPT_DATA_AVAILABLE: u8 = 1 << 0;
+const FIFO_ENABLE_AND_CLEAR: u8 = 0b0000_0111;
+
+/// OUT2 gates the UART's interrupt line on PCs.
+const MODEM_DTR_RTS_OUT2: u8 = 0b0000_1011;
+
+/// With the divisor latch open, the first two registers hold the divisor of
+/// the 115200 baud base clock.
+const LINE_DIVISOR_LATCH: u8 = 1 << 7;
+const LINE_8N1: u8 = 0b0000_0011;
+
+const LINE_DATA_READY: u8 = 1 << 0;
+const LINE_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// Received bytes kept for `read`. Older bytes are dropped past this.
+const RECEIVE_CAPACITY: usize = 256;
+
+/// How many times to check the line before giving up on sending a byte.
+const TRANSMIT_SPINS: usize = 100_000;
+
+pub fn discover() {
+  use crate::{interrupts, device::{self, DeviceAddress, ids}, Device};
+
+  if !device::driver_enabled("serial") {
+    log::info!("Serial driver disabled");
+    return;
+  }
+
+  let id = ids::allocate(DeviceAddress::ISA(COM1), Some("serial"));
+  let port = SerialPort::new(COM1);
+  port.enable_receive_interrupt();
+
+  device::connect(id, Device::Serial(port));
+
+  if let Err(error) = interrupts::enable_isa_device_irq(IRQ, id) {
+    log::warn!("Serial port IRQ not enabled: {}", error);
+  }
+}
+
+pub struct SerialPort {
+  base: u16,
+  received: IrqSpinLock<VecDeque<u8>>
+}
+
+impl SerialPort {
+  pub fn new(base: u16) -> Self {
+    SerialPort { base, received: IrqSpinLock::new(VecDeque::new()) }
+  }
+
+  fn register(&self, offset: u16) -> Port<u8> {
+    Port::new(self.base + offset)
+  }
+
+  /// Sets the line to 8N1 at `115200 / divisor` baud, for ports the
+  /// firmware didn't set up.
+  pub fn init_line(&self, divisor: u16) {
+    unsafe {
+      self.register(INTERRUPT_ENABLE).write(0);
+      self.register(LINE_CONTROL).write(LINE_DIVISOR_LATCH);
+      self.register(DATA).write(divisor as u8);
+      self.register(INTERRUPT_ENABLE).write((divisor >> 8) as u8);
+      self.register(LINE_CONTROL).write(LINE_8N1);
+    }
+  }
+
+  pub fn enable_receive_interrupt(&self) {
+    unsafe {
+      self.register(FIFO_CONTROL).write(FIFO_ENABLE_AND_CLEAR);
+      self.register(MODEM_CONTROL).write(MODEM_DTR_RTS_OUT2);
+      self.register(INTERRUPT_ENABLE).write(INTERRUPT_DATA_AVAILABLE);
+    }
+  }
+
+  fn line_status(&self) -> u8 {
+    unsafe { self.register(LINE_STATUS).read() }
+  }
+
+  /// Takes a byte straight from the UART, bypassing the receive buffer.
+  pub fn read_byte(&self) -> Option<u8> {
+    if self.line_status() & LINE_DATA_READY != 0 {
+      Some(unsafe { self.register(DATA).read() })
+    } else {
+      None
+    }
+  }
+
+  /// Sends a byte, waiting for as long as the UART takes.
+  pub fn write_byte(&self, byte: u8) {
+    while self.line_status() & LINE_TRANSMIT_EMPTY == 0 {
+      core::sync::atomic::spin_loop_hint();
+    }
+
+    unsafe { self.register(DATA).write(byte); }
+  }
+}
+
+impl kernel::Device<X8664Platform> for SerialPort {
+  fn description(&self) -> DeviceDescription<X8664Platform> {
+    DeviceDescription {
+      parent: None,
+      name: "com1".into(),
+      class: DeviceClass::Other,
+      driver: Some("serial"),
+      resources: alloc::vec![
+        Resource::IoPort { base: self.base, size: 8 },
+        Resource::Irq(IRQ as u32)
+      ]
+    }
+  }
+
+  fn poll(&self) {
+    let mut received = self.received.lock();
+
+    while self.line_status() & LINE_DATA_READY != 0 {
+      let byte = unsafe { self.register(DATA).read() };
+
+      if received.len() == RECEIVE_CAPACITY {
+        received.pop_front();
+      }
+      received.push_back(byte);
+    }
+  }
+
+  fn shutdown(&self) {
+    unsafe { self.register(INTERRUPT_ENABLE).write(0); }
+  }
+
+  fn as_char_device(&self) -> Option<&dyn kernel::CharDevice<X8664Platform>> { Some(self) }
+}
+
+impl kernel::CharDevice<X8664Platform> for SerialPort {
+  fn read(&self, buffer: &mut [u8]) -> Result<usize, X8664Error> {
+    let mut received = self.received.lock();
+    let mut count = 0;
+
+    while count < buffer.len() {
+      match received.pop_front() {
+        Some(byte) => buffer[count] = byte,
+        None => break
+      }
+      count += 1;
+    }
+
+    Ok(count)
+  }
+
+  fn write(&self, buffer: &[u8]) -> Result<usize, X8664Error> {
+    for (count, byte) in buffer.iter().enumerate() {
+      let mut spins = 0;
+      while self.line_status() & LINE_TRANSMIT_EMPTY == 0 {
+        spins += 1;
+        if spins == TRANSMIT_SPINS {
+          return if count > 0 { Ok(count) } else { Err(X8664Error::DeviceTimeout("serial port")) };
+        }
+      }
+
+      unsafe { self.register(DATA).write(*byte); }
+    }
+
+    Ok(buffer.len())
+  }
+}
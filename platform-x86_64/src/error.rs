@@ -1,17 +1,98 @@
+use core::fmt;
+use kernel::{ErrorKind, PlatformError};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum X8664Error {
-    //UEFIError(uefi::Status)
+    /// A firmware call failed.
+    UEFIError(uefi::Status),
+
+    /// Talking to a device failed, for the given reason.
+    Io(&'static str),
+
+    /// The named device didn't respond in time.
+    DeviceTimeout(&'static str),
+
+    /// Something didn't respond in time.
+    Timeout,
+    NotFound,
+    Unsupported(&'static str),
+    OutOfMemory,
+
+    /// A binary couldn't be loaded, for the given reason.
+    InvalidExecutable(&'static str),
+
+    /// A device reported a fault. It's still connected, and may recover.
+    DeviceFault(&'static str),
+
+    /// Anything else, for the given reason.
+    Other(&'static str)
+}
+
+impl PlatformError for X8664Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            X8664Error::UEFIError(status) => match *status {
+                uefi::Status::NOT_FOUND => ErrorKind::NotFound,
+                uefi::Status::UNSUPPORTED => ErrorKind::Unsupported,
+                uefi::Status::OUT_OF_RESOURCES => ErrorKind::OutOfMemory,
+                uefi::Status::TIMEOUT => ErrorKind::Timeout,
+                uefi::Status::DEVICE_ERROR => ErrorKind::Io,
+                uefi::Status::LOAD_ERROR => ErrorKind::InvalidExecutable,
+                _ => ErrorKind::Other
+            },
+            X8664Error::Io(_) => ErrorKind::Io,
+            X8664Error::DeviceTimeout(_) | X8664Error::Timeout => ErrorKind::Timeout,
+            X8664Error::NotFound => ErrorKind::NotFound,
+            X8664Error::Unsupported(_) => ErrorKind::Unsupported,
+            X8664Error::OutOfMemory => ErrorKind::OutOfMemory,
+            X8664Error::InvalidExecutable(_) => ErrorKind::InvalidExecutable,
+            X8664Error::DeviceFault(_) => ErrorKind::DeviceFault,
+            X8664Error::Other(_) => ErrorKind::Other
+        }
+    }
 }
 
-// impl <T: core::fmt::Debug> From<uefi::Error<T>> for X8664Error {
-//     fn from(error: uefi::Error<T>) -> X8664Error {
-//         //X8664Error::UEFIError(error.status())
-//     }
-// }
-
-// impl From<uefi::Status> for X8664Error {
-//     fn from(status: uefi::Status) -> X8664Error {
-//         X8664Error::UEFIError(status)
-//     }
-// }
+impl fmt::Display for X8664Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            X8664Error::UEFIError(status) => write!(f, "UEFI error {:?}", status),
+            X8664Error::Io(reason) => write!(f, "I/O error: {}", reason),
+            X8664Error::DeviceTimeout(device) => write!(f, "{} timed out", device),
+            X8664Error::Timeout => write!(f, "timed out"),
+            X8664Error::NotFound => write!(f, "not found"),
+            X8664Error::Unsupported(what) => write!(f, "unsupported: {}", what),
+            X8664Error::OutOfMemory => write!(f, "out of memory"),
+            X8664Error::InvalidExecutable(reason) => write!(f, "invalid executable: {}", reason),
+            X8664Error::DeviceFault(reason) => write!(f, "device fault: {}", reason),
+            X8664Error::Other(reason) => write!(f, "{}", reason)
+        }
+    }
+}
+
+/// For errors the kernel raises itself, which only have a kind.
+impl From<ErrorKind> for X8664Error {
+    fn from(kind: ErrorKind) -> X8664Error {
+        match kind {
+            ErrorKind::Io => X8664Error::Io(kind.as_str()),
+            ErrorKind::Timeout => X8664Error::Timeout,
+            ErrorKind::NotFound => X8664Error::NotFound,
+            ErrorKind::Unsupported => X8664Error::Unsupported(kind.as_str()),
+            ErrorKind::OutOfMemory => X8664Error::OutOfMemory,
+            ErrorKind::InvalidExecutable => X8664Error::InvalidExecutable(kind.as_str()),
+            ErrorKind::DeviceFault => X8664Error::DeviceFault(kind.as_str()),
+            ErrorKind::Other => X8664Error::Other(kind.as_str())
+        }
+    }
+}
+
+impl <T: core::fmt::Debug> From<uefi::Error<T>> for X8664Error {
+    fn from(error: uefi::Error<T>) -> X8664Error {
+        X8664Error::UEFIError(error.status())
+    }
+}
+
+impl From<uefi::Status> for X8664Error {
+    fn from(status: uefi::Status) -> X8664Error {
+        X8664Error::UEFIError(status)
+    }
+}
@@ -1,19 +1,96 @@
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicU64, Ordering};
 use ringbuffer::RingBuffer;
 
-use kernel::{PlatformEvent};
+use kernel::{
+  EventEnvelope,
+  PlatformEvent,
+  sync::{IrqSpinLock, LockClass}
+};
 use crate::X8664Platform;
 
+const CAPACITY: usize = 1000;
+
+struct EventQueues {
+  buffer: RingBuffer<EventEnvelope<X8664Platform>>,
+
+  /// Critical events are kept out of the ring so that a burst of other events
+  /// can't push them out. This queue grows as needed rather than drop anything.
+  critical: VecDeque<EventEnvelope<X8664Platform>>,
+
+  sequence: u64
+}
+
+static EVENT_QUEUES_CLASS: LockClass = LockClass::new("event queues", 60);
+
 lazy_static! {
-  static ref EVENT_BUFFER: RingBuffer<PlatformEvent<X8664Platform>> = {
-    RingBuffer::new_with_capacity(1000)
+  /// Interrupt handlers push events, so both queues sit behind one IRQ-safe
+  /// lock. Sequence numbers are handed out under it too, so they're in the
+  /// same order as the events in each queue.
+  static ref EVENT_QUEUES: IrqSpinLock<EventQueues> = {
+    IrqSpinLock::ranked(EventQueues {
+      buffer: RingBuffer::new_with_capacity(CAPACITY),
+      critical: VecDeque::new(),
+      sequence: 0
+    }, &EVENT_QUEUES_CLASS)
   };
 }
 
+/// Written on every push so that an idle CPU can MONITOR it. It gets a cache
+/// line to itself so unrelated writes don't wake the CPU spuriously.
+#[repr(align(64))]
+struct WakeLine(AtomicU64);
+
+static WAKE_LINE: WakeLine = WakeLine(AtomicU64::new(0));
+
 pub(crate) fn push_event(event: PlatformEvent<X8664Platform>) {
-  EVENT_BUFFER.push(event);
+  {
+    let mut queues = EVENT_QUEUES.lock();
+
+    let envelope = EventEnvelope {
+      sequence: queues.sequence,
+      timestamp: unsafe { core::arch::x86_64::_rdtsc() },
+      event
+    };
+    queues.sequence += 1;
+
+    if envelope.event.is_critical() {
+      queues.critical.push_back(envelope);
+    } else {
+      queues.buffer.push(envelope);
+    }
+  }
+
+  WAKE_LINE.0.fetch_add(1, Ordering::Release);
 }
 
+/// Returns the pending event with the lowest sequence number, from whichever
+/// queue holds it.
+pub(crate) fn poll_event() -> Option<EventEnvelope<X8664Platform>> {
+  let mut queues = EVENT_QUEUES.lock();
+
+  match queues.critical.front() {
+    Some(critical) => {
+      let sequence = critical.sequence;
+      queues.buffer.poll_if(|envelope| envelope.sequence < sequence)
+        .or_else(|| queues.critical.pop_front())
+    },
 
-pub(crate) fn poll_event() -> Option<PlatformEvent<X8664Platform>> {
-  EVENT_BUFFER.poll()
-}
\ No newline at end of file
+    None => queues.buffer.poll()
+  }
+}
+
+pub(crate) fn has_pending() -> bool {
+  let queues = EVENT_QUEUES.lock();
+  queues.buffer.size() > 0 || !queues.critical.is_empty()
+}
+
+/// How many events have been dropped because the buffer was full. Critical
+/// events are never dropped, so they're never counted here.
+pub(crate) fn overflows() -> u64 {
+  EVENT_QUEUES.lock().buffer.overflows() as u64
+}
+
+pub(crate) fn wake_address() -> *const u8 {
+  &WAKE_LINE as *const WakeLine as *const u8
+}
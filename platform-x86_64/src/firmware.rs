@@ -0,0 +1,241 @@
+//! UEFI runtime services, which stay usable after `ExitBootServices`.
+//!
+//! `uefi` 0.4 only wraps a few of them, so they're called through the
+//! runtime services table directly. We keep the identity mapping the
+//! firmware set up and leave its runtime regions out of the heap, so the
+//! table and the code behind it stay where they were and there's no need for
+//! `SetVirtualAddressMap`. That has to change if we ever remap memory.
+//!
+//! The firmware isn't reentrant, so every call is made holding `LOCK`, with
+//! interrupts off.
+
+use alloc::{string::String, vec::Vec};
+use kernel::{
+  firmware::{DateTime, Firmware, Guid, ResetKind, Storage},
+  sync::{IrqSpinLock, LockClass}
+};
+use spin::Once;
+use uefi::{
+  Status,
+  table::{Runtime, SystemTable}
+};
+
+use crate::{X8664Platform, error::X8664Error};
+
+/// Longest variable name we set, in UTF-16 units, not counting the
+/// terminator.
+const MAX_NAME: usize = 512;
+
+const VARIABLE_NON_VOLATILE: u32 = 0x1;
+const VARIABLE_BOOTSERVICE_ACCESS: u32 = 0x2;
+const VARIABLE_RUNTIME_ACCESS: u32 = 0x4;
+
+const UNSPECIFIED_TIME_ZONE: i16 = 0x07ff;
+
+static LOCK_CLASS: LockClass = LockClass::new("firmware", 85);
+static LOCK: IrqSpinLock<()> = IrqSpinLock::ranked((), &LOCK_CLASS);
+
+static FIRMWARE: Once<X8664Firmware> = Once::new();
+
+/// `EFI_RUNTIME_SERVICES`, as the UEFI specification lays it out.
+#[repr(C)]
+struct RuntimeServices {
+  header: [u8; 24],
+  get_time: extern "win64" fn(time: *mut Time, capabilities: *mut u8) -> Status,
+  set_time: extern "win64" fn(time: *const Time) -> Status,
+  get_wakeup_time: usize,
+  set_wakeup_time: usize,
+  set_virtual_address_map: usize,
+  convert_pointer: usize,
+  get_variable: extern "win64" fn(name: *const u16, vendor: *const Guid, attributes: *mut u32,
+    size: *mut usize, data: *mut u8) -> Status,
+  get_next_variable_name: extern "win64" fn(size: *mut usize, name: *mut u16, vendor: *mut Guid) -> Status,
+  set_variable: extern "win64" fn(name: *const u16, vendor: *const Guid, attributes: u32, size: usize,
+    data: *const u8) -> Status,
+  get_next_high_monotonic_count: usize,
+  reset_system: extern "win64" fn(kind: u32, status: Status, size: usize, data: *const u8)
+}
+
+/// `EFI_TIME`.
+#[repr(C)]
+#[derive(Default)]
+struct Time {
+  year: u16,
+  month: u8,
+  day: u8,
+  hour: u8,
+  minute: u8,
+  second: u8,
+  pad1: u8,
+  nanosecond: u32,
+  time_zone: i16,
+  daylight: u8,
+  pad2: u8
+}
+
+/// The runtime services the firmware handed over when boot services exited.
+#[derive(Copy, Clone)]
+pub struct X8664Firmware {
+  services: &'static RuntimeServices
+}
+
+// The firmware is only called with `LOCK` held.
+unsafe impl Send for X8664Firmware {}
+unsafe impl Sync for X8664Firmware {}
+
+impl X8664Firmware {
+  pub fn new(system_table: SystemTable<Runtime>) -> Self {
+    let services = unsafe { system_table.runtime_services() } as *const _ as *const RuntimeServices;
+    X8664Firmware { services: unsafe { &*services } }
+  }
+}
+
+/// Makes the runtime services available through `Platform::firmware`.
+pub fn init(firmware: X8664Firmware) {
+  FIRMWARE.call_once(|| firmware);
+}
+
+pub fn get() -> Option<&'static X8664Firmware> {
+  FIRMWARE.r#try()
+}
+
+fn check(status: Status) -> Result<(), X8664Error> {
+  if status.is_error() {
+    Err(X8664Error::from(status))
+  } else {
+    Ok(())
+  }
+}
+
+/// `name` as a null-terminated UCS-2 string.
+fn encode_name(name: &str) -> Result<[u16; MAX_NAME + 1], X8664Error> {
+  let mut encoded = [0u16; MAX_NAME + 1];
+  let mut length = 0;
+
+  for unit in name.encode_utf16() {
+    // UCS-2 has no surrogates.
+    if length == MAX_NAME || (unit >= 0xd800 && unit < 0xe000) {
+      return Err(X8664Error::Unsupported("variable name"));
+    }
+
+    encoded[length] = unit;
+    length += 1;
+  }
+
+  Ok(encoded)
+}
+
+impl Firmware<X8664Platform> for X8664Firmware {
+  fn reset(&self, kind: ResetKind) -> X8664Error {
+    let kind = match kind {
+      ResetKind::Cold => 0,
+      ResetKind::Warm => 1,
+      ResetKind::Shutdown => 2
+    };
+
+    let _lock = LOCK.lock();
+    (self.services.reset_system)(kind, Status::SUCCESS, 0, core::ptr::null());
+
+    X8664Error::DeviceFault("the firmware didn't reset the machine")
+  }
+
+  fn time(&self) -> Result<DateTime, X8664Error> {
+    let mut time = Time::default();
+    check({
+      let _lock = LOCK.lock();
+      (self.services.get_time)(&mut time, core::ptr::null_mut())
+    })?;
+
+    Ok(DateTime {
+      year: time.year,
+      month: time.month,
+      day: time.day,
+      hour: time.hour,
+      minute: time.minute,
+      second: time.second,
+      nanosecond: time.nanosecond,
+      time_zone: if time.time_zone == UNSPECIFIED_TIME_ZONE { None } else { Some(time.time_zone) }
+    })
+  }
+
+  fn set_time(&self, time: &DateTime) -> Result<(), X8664Error> {
+    let time = Time {
+      year: time.year,
+      month: time.month,
+      day: time.day,
+      hour: time.hour,
+      minute: time.minute,
+      second: time.second,
+      nanosecond: time.nanosecond,
+      time_zone: time.time_zone.unwrap_or(UNSPECIFIED_TIME_ZONE),
+      ..Time::default()
+    };
+
+    let _lock = LOCK.lock();
+    check((self.services.set_time)(&time))
+  }
+
+  fn variable(&self, vendor: &Guid, name: &str, buffer: &mut [u8]) -> Result<usize, X8664Error> {
+    let name = encode_name(name)?;
+    let mut size = buffer.len();
+
+    check({
+      let _lock = LOCK.lock();
+      (self.services.get_variable)(name.as_ptr(), vendor, core::ptr::null_mut(), &mut size, buffer.as_mut_ptr())
+    })?;
+
+    Ok(size)
+  }
+
+  fn set_variable(&self, vendor: &Guid, name: &str, value: &[u8], storage: Storage) -> Result<(), X8664Error> {
+    let name = encode_name(name)?;
+
+    // Variables set at runtime have to stay visible at runtime, and with it
+    // to boot services.
+    let attributes = VARIABLE_BOOTSERVICE_ACCESS | VARIABLE_RUNTIME_ACCESS | match storage {
+      Storage::Volatile => 0,
+      Storage::NonVolatile => VARIABLE_NON_VOLATILE
+    };
+
+    let _lock = LOCK.lock();
+    check((self.services.set_variable)(name.as_ptr(), vendor, attributes, value.len(), value.as_ptr()))
+  }
+
+  fn variable_names(&self, vendor: &Guid) -> Result<Vec<String>, X8664Error> {
+    let mut names = Vec::new();
+
+    // Each call takes the name and vendor the last one returned, starting
+    // from an empty name, and walks every namespace. Names can be longer
+    // than the ones we set, so the buffer grows when the firmware asks.
+    let mut name = alloc::vec![0u16; MAX_NAME + 1];
+    let mut name_vendor = Guid::new(0, 0, 0, [0; 8]);
+
+    loop {
+      let mut size = name.len() * core::mem::size_of::<u16>();
+      let status = {
+        let _lock = LOCK.lock();
+        (self.services.get_next_variable_name)(&mut size, name.as_mut_ptr(), &mut name_vendor)
+      };
+
+      if status == Status::NOT_FOUND {
+        return Ok(names);
+      }
+
+      // The firmware has left the last name in place and set `size` to
+      // what the next one needs, so we can ask again.
+      if status == Status::BUFFER_TOO_SMALL {
+        let needed = (size + 1) / core::mem::size_of::<u16>();
+        name.resize(needed.max(name.len() + 1), 0);
+        continue;
+      }
+      check(status)?;
+
+      if name_vendor == *vendor {
+        let length = name.iter().position(|&unit| unit == 0).unwrap_or(name.len());
+        names.push(core::char::decode_utf16(name[..length].iter().cloned())
+          .map(|c| c.unwrap_or(core::char::REPLACEMENT_CHARACTER))
+          .collect());
+      }
+    }
+  }
+}
@@ -0,0 +1,574 @@
+//! A GDB remote serial protocol stub on COM2.
+//!
+//! With the `gdb` boot option, breakpoint and debug exceptions stop in the
+//! stub instead of the usual handlers, and GDB on the other end of COM2 can
+//! read and write registers and memory, single step and continue. Bytes that
+//! arrive while the kernel runs raise the UART's IRQ, which breaks in, so
+//! `target remote` works at any time and Ctrl-C interrupts. `gdb.wait` also
+//! breaks in at boot, before any devices are discovered.
+//!
+//! The exceptions come in through `debug_entry` and `breakpoint_entry`,
+//! which save every general purpose register, so GDB sees and can change the
+//! state the CPU trapped in rather than the handler's. The stub polls the
+//! UART with interrupts off and allocates nothing, since whatever trapped
+//! may hold any lock. GDB probes memory freely, so memory is only read or
+//! written inside RAM in the firmware's memory map, and anything else gets
+//! an error instead of a fault inside the stub.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use kernel::sync::IrqSpinLock;
+use spin::Once;
+
+use crate::{boot_info, device::serial::{self, SerialPort}};
+
+pub const DEBUG_VECTOR: u64 = 1;
+pub const BREAKPOINT_VECTOR: u64 = 3;
+
+/// The trap flag, which has the CPU raise a debug exception after every
+/// instruction.
+const RFLAGS_TRAP: u64 = 1 << 8;
+
+/// The longest packet we take, which GDB is told in `qSupported`.
+const PACKET_SIZE: usize = 4096;
+
+/// `rax` to `gs`. GDB treats the floating point and SSE registers that
+/// follow in its numbering as unavailable when a `g` reply stops short.
+const REGISTERS: usize = 24;
+
+/// Where `eflags` is in GDB's numbering. It and everything after it are 32
+/// bits wide.
+const EFLAGS: usize = 17;
+
+static PORT: Once<SerialPort> = Once::new();
+static PACKET: IrqSpinLock<[u8; PACKET_SIZE]> = IrqSpinLock::new([0; PACKET_SIZE]);
+
+/// Set while GDB is waiting for the kernel to stop, after `c` or `s`.
+static RESUMED: AtomicBool = AtomicBool::new(false);
+
+/// Set when `break_in` took the `$` that starts a packet off the line.
+static PACKET_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Registers as `gdb_trap_common` leaves them on the stack: the general purpose
+/// registers, the vector, and the interrupt stack frame the CPU pushed.
+#[repr(C)]
+pub struct TrapFrame {
+  r15: u64,
+  r14: u64,
+  r13: u64,
+  r12: u64,
+  r11: u64,
+  r10: u64,
+  r9: u64,
+  r8: u64,
+  rbp: u64,
+  rdi: u64,
+  rsi: u64,
+  rdx: u64,
+  rcx: u64,
+  rbx: u64,
+  rax: u64,
+  vector: u64,
+  rip: u64,
+  cs: u64,
+  rflags: u64,
+  rsp: u64,
+  ss: u64
+}
+
+impl TrapFrame {
+  /// A register by its number in GDB's amd64 layout. The data segment
+  /// registers aren't saved, so they read as zero.
+  fn register(&mut self, number: usize) -> Option<&mut u64> {
+    Some(match number {
+      0 => &mut self.rax,
+      1 => &mut self.rbx,
+      2 => &mut self.rcx,
+      3 => &mut self.rdx,
+      4 => &mut self.rsi,
+      5 => &mut self.rdi,
+      6 => &mut self.rbp,
+      7 => &mut self.rsp,
+      8 => &mut self.r8,
+      9 => &mut self.r9,
+      10 => &mut self.r10,
+      11 => &mut self.r11,
+      12 => &mut self.r12,
+      13 => &mut self.r13,
+      14 => &mut self.r14,
+      15 => &mut self.r15,
+      16 => &mut self.rip,
+      17 => &mut self.rflags,
+      18 => &mut self.cs,
+      19 => &mut self.ss,
+      _ => return None
+    })
+  }
+
+  fn read_register(&mut self, number: usize) -> u64 {
+    self.register(number).map(|value| *value).unwrap_or(0)
+  }
+
+  /// Changing CS or SS would only make `iretq` fault, so writes to them
+  /// are dropped along with the unsaved registers.
+  fn write_register(&mut self, number: usize, value: u64) {
+    if number > EFLAGS {
+      return;
+    }
+
+    if let Some(register) = self.register(number) {
+      *register = match number {
+        EFLAGS => (*register & !0xffff_ffff) | (value & 0xffff_ffff),
+        _ => value
+      };
+    }
+  }
+}
+
+fn register_size(number: usize) -> usize {
+  if number < EFLAGS { 8 } else { 4 }
+}
+
+/// Sets up COM2 if the `gdb` boot option is on. Has to run before the
+/// interrupt controllers are set up, which unmask its IRQ if it is.
+pub fn init() {
+  let command_line = crate::boot_info::command_line();
+  if !command_line.flag("gdb") {
+    return;
+  }
+
+  let port = PORT.call_once(|| SerialPort::new(serial::COM2));
+  port.init_line(1);
+  port.enable_receive_interrupt();
+
+  log::info!("GDB stub listening on COM2");
+}
+
+pub fn is_enabled() -> bool {
+  PORT.r#try().is_some()
+}
+
+/// Stops in the stub if `gdb.wait` was given, so GDB can attach before the
+/// kernel goes any further.
+pub fn wait_if_asked() {
+  if is_enabled() && crate::boot_info::command_line().flag("gdb.wait") {
+    log::info!("Waiting for GDB to attach");
+    breakpoint();
+  }
+}
+
+pub fn breakpoint() {
+  unsafe { asm!("int3" :::: "volatile"); }
+}
+
+/// Called from the UART's IRQ. Breaks in if GDB sent Ctrl-C or started a
+/// packet, and drops anything else, like stray acknowledgements.
+pub fn break_in() {
+  let port = match PORT.r#try() {
+    Some(port) => port,
+    None => return
+  };
+
+  while let Some(byte) = port.read_byte() {
+    match byte {
+      0x03 => return breakpoint(),
+      b'$' => {
+        PACKET_STARTED.store(true, Ordering::Relaxed);
+        return breakpoint();
+      },
+      _ => {}
+    }
+  }
+}
+
+#[naked]
+#[inline(never)]
+pub unsafe extern "sysv64" fn debug_entry() {
+  asm!("
+    push 1
+    jmp gdb_trap_common
+  " :::: "volatile", "intel");
+}
+
+#[naked]
+#[inline(never)]
+pub unsafe extern "sysv64" fn breakpoint_entry() {
+  asm!("
+    push 3
+    jmp gdb_trap_common
+  " :::: "volatile", "intel");
+}
+
+/// Saves the general purpose registers under the vector the entry point
+/// pushed, and the x87 and SSE state below them, since `gdb_trap` is ordinary
+/// code that may use either. RBX keeps the frame's address across the call.
+#[naked]
+#[no_mangle]
+#[inline(never)]
+unsafe extern "sysv64" fn gdb_trap_common() {
+  asm!("
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+    mov rdi, rsp
+    mov rbx, rsp
+    sub rsp, 512
+    and rsp, -16
+    fxsave [rsp]
+    call gdb_trap
+    fxrstor [rsp]
+    mov rsp, rbx
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    add rsp, 8
+    iretq
+  " :::: "volatile", "intel");
+}
+
+#[no_mangle]
+extern "sysv64" fn gdb_trap(frame: &mut TrapFrame) {
+  let port = match PORT.r#try() {
+    Some(port) if !kernel::panic::is_panicking() => port,
+    _ => return detached_trap(frame)
+  };
+
+  // Only one CPU talks to GDB at a time. A breakpoint hit inside the stub
+  // itself lands here too, and has to be passed over.
+  match PACKET.try_lock() {
+    Some(mut packet) => Stub { port, frame, packet: &mut packet[..] }.run(),
+    None => detached_trap(frame)
+  }
+}
+
+/// What the exceptions did before the stub.
+fn detached_trap(frame: &mut TrapFrame) {
+  match frame.vector {
+    BREAKPOINT_VECTOR => log::info!("Breakpoint at {:#x}", frame.rip),
+    _ => panic!("Debug exception at {:#x} (RFLAGS {:#x})", frame.rip, frame.rflags)
+  }
+}
+
+struct Stub<'a> {
+  port: &'a SerialPort,
+  frame: &'a mut TrapFrame,
+  packet: &'a mut [u8]
+}
+
+impl<'a> Stub<'a> {
+  fn run(&mut self) {
+    // If GDB is waiting on `c` or `s`, tell it we've stopped. Otherwise
+    // it's just connected, and will ask.
+    if RESUMED.swap(false, Ordering::Relaxed) {
+      self.reply(b"S05");
+    }
+
+    loop {
+      let length = self.receive();
+      if self.command(length) {
+        return;
+      }
+    }
+  }
+
+  /// Reads a packet into `packet`, acknowledging it if the checksum
+  /// matches, and returns its length.
+  fn receive(&mut self) -> usize {
+    loop {
+      if !PACKET_STARTED.swap(false, Ordering::Relaxed) {
+        while self.read() != b'$' {}
+      }
+
+      let mut length = 0;
+      let mut sum = 0u8;
+      loop {
+        match self.read() {
+          b'#' => break,
+          byte => {
+            if length < self.packet.len() {
+              self.packet[length] = byte;
+              length += 1;
+            }
+            sum = sum.wrapping_add(byte);
+          }
+        }
+      }
+
+      let checksum = (hex_digit(self.read()), hex_digit(self.read()));
+      if checksum == (Some(sum >> 4), Some(sum & 0xf)) {
+        self.port.write_byte(b'+');
+        return length;
+      }
+
+      self.port.write_byte(b'-');
+    }
+  }
+
+  fn read(&self) -> u8 {
+    loop {
+      if let Some(byte) = self.port.read_byte() {
+        return byte;
+      }
+      core::sync::atomic::spin_loop_hint();
+    }
+  }
+
+  /// Handles a packet, returning whether to resume.
+  fn command(&mut self, length: usize) -> bool {
+    let packet = &self.packet[..length];
+    let (command, arguments) = match packet.split_first() {
+      Some((command, arguments)) => (*command, arguments),
+      None => return false
+    };
+
+    match command {
+      b'?' => self.reply(b"S05"),
+
+      b'g' => {
+        let mut reply = Reply::start(self.port);
+        for number in 0..REGISTERS {
+          let value = self.frame.read_register(number);
+          reply.hex(&value.to_le_bytes()[..register_size(number)]);
+        }
+        reply.finish();
+      },
+
+      b'G' => {
+        let mut offset = 0;
+        for number in 0..REGISTERS {
+          let size = register_size(number);
+          match decode_register(arguments.get(offset..offset + size * 2)) {
+            Some(value) => self.frame.write_register(number, value),
+            None => break
+          }
+          offset += size * 2;
+        }
+        self.reply(b"OK");
+      },
+
+      b'p' => match parse_hex(arguments) {
+        Some(number) if (number as usize) < REGISTERS => {
+          let number = number as usize;
+          let value = self.frame.read_register(number);
+          let mut reply = Reply::start(self.port);
+          reply.hex(&value.to_le_bytes()[..register_size(number)]);
+          reply.finish();
+        },
+        _ => self.reply(b"E01")
+      },
+
+      b'P' => {
+        let mut parts = arguments.splitn(2, |byte| *byte == b'=');
+        let number = parts.next().and_then(parse_hex).map(|number| number as usize);
+        let value = decode_register(parts.next());
+
+        match (number, value) {
+          (Some(number), Some(value)) if number < REGISTERS => {
+            self.frame.write_register(number, value);
+            self.reply(b"OK");
+          },
+          _ => self.reply(b"E01")
+        }
+      },
+
+      // GDB takes a short read as far as memory goes.
+      b'm' => match parse_range(arguments) {
+        Some((address, length)) if length > 0 && accessible(address, length) == 0 => self.reply(b"E14"),
+        Some((address, length)) => {
+          // Each byte takes two characters to send.
+          let length = accessible(address, length).min(PACKET_SIZE as u64 / 2) as usize;
+
+          let mut reply = Reply::start(self.port);
+          for index in 0..length {
+            let byte = unsafe { core::ptr::read_volatile((address + index as u64) as *const u8) };
+            reply.hex(&[byte]);
+          }
+          reply.finish();
+        },
+        None => self.reply(b"E01")
+      },
+
+      b'M' => {
+        let mut parts = arguments.splitn(2, |byte| *byte == b':');
+        let range = parts.next().and_then(parse_range);
+        let data = parts.next().unwrap_or(&[]);
+
+        match range {
+          Some((address, length)) if accessible(address, length) < length => self.reply(b"E14"),
+          Some((address, length)) if data.len() as u64 >= length * 2 && data.iter().all(|byte| hex_digit(*byte).is_some()) => {
+            for (index, pair) in data.chunks(2).take(length as usize).enumerate() {
+              let byte = hex_digit(pair[0]).unwrap_or(0) << 4 | hex_digit(pair[1]).unwrap_or(0);
+              unsafe { core::ptr::write_volatile((address + index as u64) as *mut u8, byte); }
+            }
+            self.reply(b"OK");
+          },
+          _ => self.reply(b"E01")
+        }
+      },
+
+      b'c' | b's' => {
+        if let Some(address) = parse_hex(arguments) {
+          self.frame.rip = address;
+        }
+
+        if command == b's' {
+          self.frame.rflags |= RFLAGS_TRAP;
+        } else {
+          self.frame.rflags &= !RFLAGS_TRAP;
+        }
+
+        RESUMED.store(true, Ordering::Relaxed);
+        return true;
+      },
+
+      // Detach or kill: either way GDB is going away, so carry on.
+      b'D' | b'k' => {
+        if command == b'D' {
+          self.reply(b"OK");
+        }
+        self.frame.rflags &= !RFLAGS_TRAP;
+        return true;
+      },
+
+      // There's only the one thread to pick.
+      b'H' => self.reply(b"OK"),
+
+      b'q' if arguments.starts_with(b"Supported") => {
+        let mut reply = Reply::start(self.port);
+        reply.bytes(b"PacketSize=");
+        reply.hex_number(PACKET_SIZE as u64);
+        reply.finish();
+      },
+      b'q' if arguments.starts_with(b"Attached") => self.reply(b"1"),
+
+      // An empty reply tells GDB we don't know the command.
+      _ => self.reply(b"")
+    }
+
+    false
+  }
+
+  fn reply(&self, data: &[u8]) {
+    let mut reply = Reply::start(self.port);
+    reply.bytes(data);
+    reply.finish();
+  }
+}
+
+/// Sends a packet as it's written, working the checksum out along the way.
+struct Reply<'a> {
+  port: &'a SerialPort,
+  sum: u8
+}
+
+impl<'a> Reply<'a> {
+  fn start(port: &'a SerialPort) -> Self {
+    port.write_byte(b'$');
+    Reply { port, sum: 0 }
+  }
+
+  fn bytes(&mut self, data: &[u8]) {
+    for byte in data {
+      self.port.write_byte(*byte);
+      self.sum = self.sum.wrapping_add(*byte);
+    }
+  }
+
+  fn hex(&mut self, data: &[u8]) {
+    for byte in data {
+      self.bytes(&[HEX[(byte >> 4) as usize], HEX[(byte & 0xf) as usize]]);
+    }
+  }
+
+  /// A number in hex without leading zeros, as GDB writes them.
+  fn hex_number(&mut self, value: u64) {
+    let digits = (64 - (value | 1).leading_zeros() as usize + 3) / 4;
+    for digit in (0..digits).rev() {
+      self.bytes(&[HEX[(value >> (digit * 4)) as usize & 0xf]]);
+    }
+  }
+
+  fn finish(self) {
+    let sum = self.sum;
+    self.port.write_byte(b'#');
+    self.port.write_byte(HEX[(sum >> 4) as usize]);
+    self.port.write_byte(HEX[(sum & 0xf) as usize]);
+  }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_digit(byte: u8) -> Option<u8> {
+  match byte {
+    b'0'..=b'9' => Some(byte - b'0'),
+    b'a'..=b'f' => Some(byte - b'a' + 10),
+    b'A'..=b'F' => Some(byte - b'A' + 10),
+    _ => None
+  }
+}
+
+/// A big-endian hex number, as in addresses and lengths.
+fn parse_hex(text: &[u8]) -> Option<u64> {
+  if text.is_empty() || text.len() > 16 {
+    return None;
+  }
+
+  text.iter().try_fold(0u64, |value, byte| Some(value << 4 | hex_digit(*byte)? as u64))
+}
+
+/// `address,length`, as in `m` and `M`.
+/// How many of the `length` bytes from `address` can be touched: only RAM,
+/// and only up to the end of the region the range starts in.
+fn accessible(address: u64, length: u64) -> u64 {
+  if address == 0 {
+    return 0;
+  }
+
+  boot_info::memory_regions()
+    .find(|region| region.contains(address) && region.kind.is_ram())
+    .map(|region| length.min(region.start + region.length - address))
+    .unwrap_or(0)
+}
+
+fn parse_range(text: &[u8]) -> Option<(u64, u64)> {
+  let mut parts = text.splitn(2, |byte| *byte == b',');
+  Some((parse_hex(parts.next()?)?, parse_hex(parts.next()?)?))
+}
+
+/// A register value, which GDB sends as its bytes in target order, so
+/// little-endian.
+fn decode_register(text: Option<&[u8]>) -> Option<u64> {
+  let text = text?;
+  if text.len() % 2 != 0 || text.len() > 16 {
+    return None;
+  }
+
+  let mut bytes = [0u8; 8];
+  for (index, pair) in text.chunks(2).enumerate() {
+    bytes[index] = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+  }
+  Some(u64::from_le_bytes(bytes))
+}
@@ -0,0 +1,98 @@
+//! The hardware access portable drivers get on x86_64.
+//!
+//! UEFI leaves memory identity mapped, so device memory and DMA buffers are
+//! used at their physical addresses.
+
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use kernel::hal::{Dma, DmaBuffer, Hal, Irq, Mmio, MmioRegion, PciConfig, PciLocation, PortIo};
+use x86_64::instructions::port::Port;
+
+use crate::{X8664Platform, device::{DeviceID, pci::PCIAddress}, error::X8664Error, interrupts};
+
+pub struct X8664Hal;
+
+pub static HAL: X8664Hal = X8664Hal;
+
+pub fn get() -> Hal<X8664Platform> {
+  Hal { ports: &HAL, mmio: &HAL, pci: &HAL, dma: &HAL, irq: &HAL }
+}
+
+impl PortIo for X8664Hal {
+  unsafe fn read_u8(&self, port: u16) -> u8 { Port::new(port).read() }
+  unsafe fn read_u16(&self, port: u16) -> u16 { Port::new(port).read() }
+  unsafe fn read_u32(&self, port: u16) -> u32 { Port::new(port).read() }
+
+  unsafe fn write_u8(&self, port: u16, value: u8) { Port::new(port).write(value) }
+  unsafe fn write_u16(&self, port: u16, value: u16) { Port::new(port).write(value) }
+  unsafe fn write_u32(&self, port: u16, value: u32) { Port::new(port).write(value) }
+}
+
+impl Mmio<X8664Platform> for X8664Hal {
+  fn map(&self, physical: u64, size: usize) -> Result<MmioRegion, X8664Error> {
+    if physical == 0 || size == 0 {
+      return Err(X8664Error::NotFound);
+    }
+
+    // TODO: the firmware maps device memory uncached, but we should say so
+    // ourselves once we have our own page tables.
+    Ok(unsafe { MmioRegion::new(physical as *mut u8, physical, size) })
+  }
+
+  fn unmap(&self, _region: MmioRegion) {}
+}
+
+impl PciConfig for X8664Hal {
+  /// Only segment 0 is reachable through the legacy configuration ports.
+  /// Anything else, like a device or function number out of range, reads as
+  /// all ones, like an empty slot.
+  fn read_u32(&self, location: PciLocation, offset: u16) -> u32 {
+    if !is_reachable(location, offset) {
+      return 0xffff_ffff;
+    }
+
+    PCIAddress::from(location).read_dword(location.function, offset as u8)
+  }
+
+  fn write_u32(&self, location: PciLocation, offset: u16, value: u32) {
+    if !is_reachable(location, offset) {
+      return;
+    }
+
+    PCIAddress::from(location).write_dword(location.function, offset as u8, value)
+  }
+}
+
+/// Whether the configuration ports can address a register. Out-of-range
+/// device and function numbers would spill into the neighbouring fields.
+fn is_reachable(location: PciLocation, offset: u16) -> bool {
+  location.segment == 0 && location.device < 32 && location.function < 8 && offset <= 0xff
+}
+
+impl Dma<X8664Platform> for X8664Hal {
+  fn allocate(&self, size: usize, align: usize) -> Result<DmaBuffer, X8664Error> {
+    let layout = Layout::from_size_align(size, align)
+      .map_err(|_| X8664Error::Unsupported("DMA buffer alignment"))?;
+
+    let pointer = unsafe { alloc_zeroed(layout) };
+    if pointer.is_null() {
+      return Err(X8664Error::OutOfMemory);
+    }
+
+    Ok(unsafe { DmaBuffer::new(pointer, pointer as u64, size, align) })
+  }
+
+  fn free(&self, buffer: DmaBuffer) {
+    let layout = Layout::from_size_align(buffer.size(), buffer.align()).unwrap();
+    unsafe { dealloc(buffer.as_ptr(), layout) }
+  }
+}
+
+impl Irq<X8664Platform> for X8664Hal {
+  fn enable(&self, irq: u32, device: DeviceID) -> Result<(), X8664Error> {
+    interrupts::enable_device_irq(irq, device)
+  }
+
+  fn disable(&self, irq: u32, device: DeviceID) {
+    interrupts::disable_device_irq(irq, device)
+  }
+}
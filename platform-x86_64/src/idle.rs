@@ -0,0 +1,194 @@
+use core::{
+  arch::x86_64::{__cpuid, _rdtsc},
+  fmt,
+  sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}
+};
+use x86_64::instructions::interrupts;
+
+use crate::event_buffer;
+
+/// The highest C-state number an MWAIT hint can express.
+pub const MAX_CSTATE: usize = 7;
+
+const CPUID_FEATURES: u32 = 0x1;
+const CPUID_FEATURE_ECX_MONITOR: u32 = 1 << 3;
+
+const CPUID_MONITOR_MWAIT: u32 = 0x5;
+const CPUID_MWAIT_ECX_EXTENSIONS: u32 = 1 << 0;
+const CPUID_MWAIT_ECX_INTERRUPT_BREAK: u32 = 1 << 1;
+
+/// MWAIT extension bit: wake on an interrupt even while IF is clear.
+const MWAIT_ECX_INTERRUPT_BREAK: u32 = 1 << 0;
+
+/// How the CPU waits for the next interrupt when there's nothing to do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IdleMethod {
+  /// `sti; hlt`, which only ever reaches C1.
+  Halt,
+
+  /// MONITOR on the event buffer's wake line, then MWAIT with a C-state hint.
+  MonitorWait
+}
+
+static MWAIT_SUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// The deepest C-state the CPU reports MWAIT sub-states for.
+static DEEPEST_CSTATE: AtomicUsize = AtomicUsize::new(1);
+
+/// The C-state we ask MWAIT for. Defaults to C1, since deeper states can stop
+/// the local APIC timer on CPUs without an always-running APIC timer.
+static TARGET_CSTATE: AtomicUsize = AtomicUsize::new(1);
+
+static ENTRIES: [AtomicU64; MAX_CSTATE + 1] = [AtomicU64::new(0); MAX_CSTATE + 1];
+static RESIDENCY: [AtomicU64; MAX_CSTATE + 1] = [AtomicU64::new(0); MAX_CSTATE + 1];
+
+pub fn init() {
+  let features = unsafe { __cpuid(CPUID_FEATURES) };
+  if features.ecx & CPUID_FEATURE_ECX_MONITOR == 0 {
+    log::info!("Idle: MONITOR/MWAIT not supported, using HLT");
+    return;
+  }
+
+  let leaf = unsafe { __cpuid(CPUID_MONITOR_MWAIT) };
+  let extensions = CPUID_MWAIT_ECX_EXTENSIONS | CPUID_MWAIT_ECX_INTERRUPT_BREAK;
+  if leaf.ecx & extensions != extensions {
+    log::info!("Idle: MWAIT can't break on masked interrupts, using HLT");
+    return;
+  }
+
+  // EDX holds a 4-bit count of MWAIT sub-states for each of C0 through C7.
+  let mut deepest = 1;
+  for cstate in 1..=MAX_CSTATE {
+    if (leaf.edx >> (cstate * 4)) & 0xf != 0 {
+      deepest = cstate;
+    }
+  }
+
+  DEEPEST_CSTATE.store(deepest, Ordering::Relaxed);
+  MWAIT_SUPPORTED.store(true, Ordering::Relaxed);
+
+  log::info!("Idle: using MONITOR/MWAIT, C-states up to C{} available", deepest);
+}
+
+pub fn method() -> IdleMethod {
+  if MWAIT_SUPPORTED.load(Ordering::Relaxed) {
+    IdleMethod::MonitorWait
+  } else {
+    IdleMethod::Halt
+  }
+}
+
+/// Sets the C-state MWAIT should aim for, clamped to what the CPU supports.
+/// Has no effect when idling with HLT.
+pub fn set_target_cstate(cstate: usize) {
+  let deepest = DEEPEST_CSTATE.load(Ordering::Relaxed);
+  let cstate = if cstate < 1 { 1 } else if cstate > deepest { deepest } else { cstate };
+
+  TARGET_CSTATE.store(cstate, Ordering::Relaxed);
+}
+
+/// Waits for the next interrupt unless there's already an event to process.
+///
+/// The event buffer is checked with interrupts disabled, and interrupts are
+/// only re-enabled by the instruction that puts the CPU to sleep. An event
+/// pushed by an interrupt handler can therefore never land between the check
+/// and the wait.
+pub fn enter() {
+  interrupts::disable();
+
+  if event_buffer::has_pending() {
+    interrupts::enable();
+    return;
+  }
+
+  let start = unsafe { _rdtsc() };
+
+  let cstate = match method() {
+    IdleMethod::Halt => {
+      unsafe { enable_and_hlt(); }
+      1
+    },
+
+    IdleMethod::MonitorWait => {
+      let cstate = TARGET_CSTATE.load(Ordering::Relaxed);
+
+      unsafe { monitor(event_buffer::wake_address()); }
+
+      // An event may have been pushed by another CPU before the monitor was
+      // armed, and that write won't wake us.
+      if event_buffer::has_pending() {
+        interrupts::enable();
+        return;
+      }
+
+      unsafe { mwait(cstate); }
+      interrupts::enable();
+      cstate
+    }
+  };
+
+  let end = unsafe { _rdtsc() };
+
+  ENTRIES[cstate].fetch_add(1, Ordering::Relaxed);
+  RESIDENCY[cstate].fetch_add(end.wrapping_sub(start), Ordering::Relaxed);
+}
+
+/// Executes `sti; hlt`. STI's interrupt shadow covers HLT, so a pending
+/// interrupt is delivered after the CPU has halted rather than before.
+#[inline(always)]
+unsafe fn enable_and_hlt() {
+  asm!("sti; hlt" :::: "volatile");
+}
+
+#[inline(always)]
+unsafe fn monitor(address: *const u8) {
+  asm!("monitor" :: "{rax}"(address), "{ecx}"(0), "{edx}"(0) :: "volatile");
+}
+
+/// Waits in `cstate` until the monitored line is written or an interrupt
+/// arrives, even though interrupts are masked.
+#[inline(always)]
+unsafe fn mwait(cstate: usize) {
+  let hint = ((cstate - 1) as u32 & 0xf) << 4;
+  asm!("mwait" :: "{eax}"(hint), "{ecx}"(MWAIT_ECX_INTERRUPT_BREAK) :: "volatile");
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CStateResidency {
+  /// Number of times the CPU entered this state.
+  pub entries: u64,
+
+  /// Time-stamp counter cycles spent in this state.
+  pub cycles: u64
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct IdleStatistics {
+  pub method: IdleMethod,
+  pub cstates: [CStateResidency; MAX_CSTATE + 1]
+}
+
+pub fn statistics() -> IdleStatistics {
+  let mut cstates = [CStateResidency::default(); MAX_CSTATE + 1];
+
+  for (index, residency) in cstates.iter_mut().enumerate() {
+    residency.entries = ENTRIES[index].load(Ordering::Relaxed);
+    residency.cycles = RESIDENCY[index].load(Ordering::Relaxed);
+  }
+
+  IdleStatistics { method: method(), cstates }
+}
+
+impl fmt::Display for IdleStatistics {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "Idle method: {:?}", self.method)?;
+
+    for (cstate, residency) in self.cstates.iter().enumerate() {
+      if residency.entries > 0 {
+        writeln!(f, "  C{}: {} entries, {} cycles", cstate, residency.entries, residency.cycles)?;
+      }
+    }
+
+    Ok(())
+  }
+}
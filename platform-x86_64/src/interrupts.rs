@@ -1,14 +1,79 @@
+use alloc::vec::Vec;
+use core::{
+  arch::x86_64::_rdtsc,
+  sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering}
+};
 use x86_64::structures::idt::*;
-use x86_64::instructions::port::Port;
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+use x86_64::registers::model_specific::Msr;
 use x2apic::{
   ioapic::{IoApic, IrqFlags, IrqMode},
   lapic::{LocalApic, LocalApicBuilder}
 };
 use spin::Mutex;
 
-use crate::{PlatformEvent, device::DeviceID};
+use kernel::{InterruptStatistics, LatencyCounter, VectorStatistics};
+use crate::{PlatformEvent, acpi, cpu::{self, MAX_CPUS}, device::{DeviceID, pc_keyboard, pci, pit, serial}, error::X8664Error, gdb};
 use crate::event_buffer::push_event;
 
+/// Vector of the first I/O APIC input. GSI `n` arrives on `IRQ_BASE + n`.
+const IRQ_BASE: u8 = 0x20;
+
+/// The I/O APIC address to use if the firmware has no MADT.
+const DEFAULT_IOAPIC_ADDRESS: u64 = 0xfec00000;
+
+/// Where the local APIC sends spurious interrupts.
+const SPURIOUS_VECTOR: usize = 0xff;
+
+/// The local APIC's interrupt command register. The LAPIC is in x2APIC mode,
+/// so it's an MSR.
+const X2APIC_ICR: u32 = 0x830;
+
+/// An NMI to every CPU but this one, which is taken even with interrupts
+/// disabled.
+const ICR_NMI_ALL_EXCLUDING_SELF: u64 = 0b100 << 8 | 1 << 14 | 0b11 << 18;
+
+const VECTORS: usize = 256;
+
+static VECTOR_COUNTS: [[AtomicU64; VECTORS]; MAX_CPUS] = [[AtomicU64::new(0); VECTORS]; MAX_CPUS];
+static HANDLER_CYCLES: [AtomicU64; VECTORS] = [AtomicU64::new(0); VECTORS];
+static HANDLER_MAX_CYCLES: [AtomicU64; VECTORS] = [AtomicU64::new(0); VECTORS];
+static SPURIOUS_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// The most I/O APIC inputs we take interrupts from. Their vectors end at
+/// 0x4f, below the test and GPE vectors.
+const MAX_GSIS: usize = 48;
+
+/// How many devices can share a GSI, as PCI devices often do.
+const SHARED_DEVICES: usize = 4;
+
+/// How many inputs the I/O APIC has, up to `MAX_GSIS`.
+static GSI_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// The devices polled when each GSI fires, stored as their ID plus one so
+/// that zero can mean none.
+static GSI_DEVICES: [[AtomicU32; SHARED_DEVICES]; MAX_GSIS] = [[AtomicU32::new(0); SHARED_DEVICES]; MAX_GSIS];
+
+/// Bitmaps of device GSIs: how each was enabled, and the level-triggered
+/// ones masked until their devices have been polled. Until then the device
+/// keeps the line asserted, and the GSI would fire again straight after the
+/// EOI.
+static LEVEL_TRIGGERED: AtomicU64 = AtomicU64::new(0);
+static ACTIVE_LOW: AtomicU64 = AtomicU64::new(0);
+static AWAITING_POLL: AtomicU64 = AtomicU64::new(0);
+
+static UNKNOWN_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Defines a handler for each GSI and installs it at the GSI's vector.
+macro_rules! gsi_handlers {
+  ($idt:ident; $($gsi:literal => $name:ident),*) => {
+    $(
+      extern "x86-interrupt" fn $name(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, $gsi); }
+      $idt[IRQ_BASE as usize + $gsi].set_handler_fn($name);
+    )*
+  };
+}
+
 lazy_static! {
   static ref IDT: InterruptDescriptorTable = {
     let mut idt = InterruptDescriptorTable::new();
@@ -34,19 +99,21 @@ lazy_static! {
     idt.double_fault.set_handler_fn(double_fault_handler);
 
     extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
+      // Panicking again would only get in the way of the first panic.
+      if kernel::panic::is_panicking() {
+        crate::panic::halt();
+      }
+
       panic!("Non-maskable interrupt: {:?}", stack_frame);
     }
     idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler);
 
-    extern "x86-interrupt" fn debug_handler(stack_frame: &mut InterruptStackFrame) {
-      panic!("Debug interrupt: {:?}", stack_frame);
-    }
-    idt.debug.set_handler_fn(debug_handler);
-
-    extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut InterruptStackFrame) {
-      log::info!("Breakpoint interrupt: {:?}", stack_frame);
+    // These save every register for the GDB stub, so they're entered
+    // directly rather than through the x86-interrupt ABI.
+    unsafe {
+      idt.debug.set_handler_fn(core::mem::transmute(gdb::debug_entry as unsafe extern "sysv64" fn()));
+      idt.breakpoint.set_handler_fn(core::mem::transmute(gdb::breakpoint_entry as unsafe extern "sysv64" fn()));
     }
-    idt.breakpoint.set_handler_fn(breakpoint_handler);
 
     extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: &mut InterruptStackFrame) {
       log::info!("Invalid opcode interrupt: {:?}", stack_frame);
@@ -54,53 +121,16 @@ lazy_static! {
     idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
 
 
-    extern "x86-interrupt" fn irq_0(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0x0); }
-    idt[0x20].set_handler_fn(irq_0);
-
-    extern "x86-interrupt" fn irq_1(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0x1); }
-    idt[0x21].set_handler_fn(irq_1);
-
-    extern "x86-interrupt" fn irq_2(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0x2); }
-    idt[0x22].set_handler_fn(irq_2);
-
-    extern "x86-interrupt" fn irq_3(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0x3); }
-    idt[0x23].set_handler_fn(irq_3);
-
-    extern "x86-interrupt" fn irq_4(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0x4); }
-    idt[0x24].set_handler_fn(irq_4);
-
-    extern "x86-interrupt" fn irq_5(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0x5); }
-    idt[0x25].set_handler_fn(irq_5);
-
-    extern "x86-interrupt" fn irq_6(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0x6); }
-    idt[0x26].set_handler_fn(irq_6);
-
-    extern "x86-interrupt" fn irq_7(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0x7); }
-    idt[0x27].set_handler_fn(irq_7);
-
-    extern "x86-interrupt" fn irq_8(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0x8); }
-    idt[0x28].set_handler_fn(irq_8);
-
-    extern "x86-interrupt" fn irq_9(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0x9); }
-    idt[0x29].set_handler_fn(irq_9);
-
-    extern "x86-interrupt" fn irq_10(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0xA); }
-    idt[0x2A].set_handler_fn(irq_10);
-
-    extern "x86-interrupt" fn irq_11(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0xB); }
-    idt[0x2B].set_handler_fn(irq_11);
-
-    extern "x86-interrupt" fn irq_12(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0xC); }
-    idt[0x2C].set_handler_fn(irq_12);
-
-    extern "x86-interrupt" fn irq_13(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0xD); }
-    idt[0x2D].set_handler_fn(irq_13);
-
-    extern "x86-interrupt" fn irq_14(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0xE); }
-    idt[0x2E].set_handler_fn(irq_14);
-
-    extern "x86-interrupt" fn irq_15(stack_frame: &mut InterruptStackFrame) { self::irq_handler(stack_frame, 0xF); }
-    idt[0x2F].set_handler_fn(irq_15);
+    gsi_handlers!(idt;
+      0 => gsi_0, 1 => gsi_1, 2 => gsi_2, 3 => gsi_3, 4 => gsi_4, 5 => gsi_5,
+      6 => gsi_6, 7 => gsi_7, 8 => gsi_8, 9 => gsi_9, 10 => gsi_10, 11 => gsi_11,
+      12 => gsi_12, 13 => gsi_13, 14 => gsi_14, 15 => gsi_15, 16 => gsi_16, 17 => gsi_17,
+      18 => gsi_18, 19 => gsi_19, 20 => gsi_20, 21 => gsi_21, 22 => gsi_22, 23 => gsi_23,
+      24 => gsi_24, 25 => gsi_25, 26 => gsi_26, 27 => gsi_27, 28 => gsi_28, 29 => gsi_29,
+      30 => gsi_30, 31 => gsi_31, 32 => gsi_32, 33 => gsi_33, 34 => gsi_34, 35 => gsi_35,
+      36 => gsi_36, 37 => gsi_37, 38 => gsi_38, 39 => gsi_39, 40 => gsi_40, 41 => gsi_41,
+      42 => gsi_42, 43 => gsi_43, 44 => gsi_44, 45 => gsi_45, 46 => gsi_46, 47 => gsi_47
+    );
 
     extern "x86-interrupt" fn acpi_gpe(stack_frame: &mut InterruptStackFrame) {
       log::info!("ACPI General Purpose Event: {:?}", stack_frame);
@@ -112,12 +142,21 @@ lazy_static! {
     }
     idt[0x55].set_handler_fn(test_interrupt);
 
+    // Spurious interrupts must not be acknowledged with an EOI.
+    extern "x86-interrupt" fn spurious_interrupt(_stack_frame: &mut InterruptStackFrame) {
+      SPURIOUS_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+    idt[SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt);
+
     idt
   };
 
   static ref IOAPIC: Mutex<IoApic> = {
     unsafe {
-      let addr = 0xfec00000; // TODO detect this
+      let addr = acpi::madt()
+        .and_then(|madt| madt.io_apics.first())
+        .map(|ioapic| ioapic.address as u64)
+        .unwrap_or(DEFAULT_IOAPIC_ADDRESS);
       let ioapic = IoApic::new(addr);
 
       Mutex::new(ioapic)
@@ -128,7 +167,7 @@ lazy_static! {
     let lapic = LocalApicBuilder::new()
       .timer_vector(0)
       .error_vector(2)
-      .spurious_vector(3)
+      .spurious_vector(SPURIOUS_VECTOR)
       .build()
       .unwrap_or_else(|err| panic!("{}", err));
 
@@ -138,17 +177,246 @@ lazy_static! {
 }
 
 
-fn irq_handler(_stack_frame: &mut InterruptStackFrame, irq: u8) {
-  log::debug!("IRQ {}", irq);
-  match irq {
-    0 => push_event(PlatformEvent::ClockTicked),
-    1 => push_event(PlatformEvent::DevicePollable(DeviceID::PCKeyboard)),
-    _ => {
-      log::warn!("Unknown IRQ {}", irq);
+fn irq_handler(_stack_frame: &mut InterruptStackFrame, gsi: u8) {
+  let start = unsafe { _rdtsc() };
+  let vector = IRQ_BASE as usize + gsi as usize;
+
+  VECTOR_COUNTS[cpu::current_id()][vector].fetch_add(1, Ordering::Relaxed);
+
+  // Anything logged here is staged, in case this interrupted the logger.
+  let clock_ticked = {
+    let _deferred = kernel::dmesg::defer();
+    dispatch_irq(gsi as u32)
+  };
+
+  unsafe { LAPIC.lock().end_of_interrupt(); }
+
+  let cycles = unsafe { _rdtsc() }.wrapping_sub(start);
+  HANDLER_CYCLES[vector].fetch_add(cycles, Ordering::Relaxed);
+
+  let mut max = HANDLER_MAX_CYCLES[vector].load(Ordering::Relaxed);
+  while cycles > max {
+    match HANDLER_MAX_CYCLES[vector].compare_exchange_weak(max, cycles, Ordering::Relaxed, Ordering::Relaxed) {
+      Ok(_) => break,
+      Err(current) => max = current
     }
   }
 
-  unsafe { LAPIC.lock().end_of_interrupt(); }
+  // This may switch to another thread, and only comes back when something
+  // switches back to the interrupted one. The EOI has to have been sent first.
+  if clock_ticked {
+    kernel::thread::clock_tick();
+  }
+}
+
+/// Handles an I/O APIC interrupt, returning whether it was a clock tick,
+/// which is finished once the EOI has gone.
+fn dispatch_irq(gsi: u32) -> bool {
+  log::debug!("GSI {}", gsi);
+
+  if gsi == acpi::isa_irq_route(pit::IRQ).gsi {
+    if !pit::tick() {
+      return false;
+    }
+    push_event(PlatformEvent::ClockTicked);
+    return true;
+  }
+
+  if acpi::sci_irq().map(|sci| acpi::isa_irq_route(sci).gsi) == Some(gsi) {
+    // The GPE status has to be cleared before the EOI, or the level-triggered
+    // SCI fires again straight away.
+    let gpes = acpi::take_gpe_status();
+    if pci::hotplug::is_hotplug_event(gpes) {
+      if let Some(bus) = pci::bus_id() {
+        push_event(PlatformEvent::DevicePollable(bus));
+      }
+    }
+    return false;
+  }
+
+  if gdb::is_enabled() && gsi == acpi::isa_irq_route(serial::COM2_IRQ).gsi {
+    gdb::break_in();
+    return false;
+  }
+
+  if routed_devices(gsi).next().is_none() {
+    UNKNOWN_COUNT.fetch_add(1, Ordering::Relaxed);
+    log::warn!("Interrupt on GSI {} with no device routed to it", gsi);
+    return false;
+  }
+
+  // Masked before the devices are told, so that `device_polled` can't
+  // unmask it first.
+  if LEVEL_TRIGGERED.load(Ordering::Relaxed) & (1 << gsi) != 0 {
+    let mut ioapic = IOAPIC.lock();
+    AWAITING_POLL.fetch_or(1 << gsi, Ordering::Relaxed);
+    unsafe { ioapic.disable_irq(gsi as u8); }
+  }
+
+  for id in routed_devices(gsi) {
+    push_event(PlatformEvent::DevicePollable(id));
+  }
+
+  false
+}
+
+/// Has the device polled whenever ISA IRQ `irq` fires. The IRQ is left
+/// masked or not as it is.
+pub(crate) fn route_isa_irq(irq: u8, id: DeviceID) -> Result<(), X8664Error> {
+  route_gsi(acpi::isa_irq_route(irq).gsi, id)
+}
+
+/// Unmasks ISA IRQ `irq` and has the device polled whenever it fires.
+pub(crate) fn enable_isa_device_irq(irq: u8, id: DeviceID) -> Result<(), X8664Error> {
+  route_isa_irq(irq, id)?;
+  without_interrupts(|| enable_isa_irq(&mut IOAPIC.lock(), irq));
+  Ok(())
+}
+
+/// Unmasks PCI interrupt line `irq` and has the device polled whenever it
+/// fires. The line may be shared with other devices.
+pub(crate) fn enable_device_irq(irq: u32, id: DeviceID) -> Result<(), X8664Error> {
+  let route = acpi::pci_irq_route(irq);
+  route_gsi(route.gsi, id)?;
+
+  let bit = 1 << route.gsi;
+  if route.trigger == acpi::TriggerMode::Level {
+    LEVEL_TRIGGERED.fetch_or(bit, Ordering::Relaxed);
+  } else {
+    LEVEL_TRIGGERED.fetch_and(!bit, Ordering::Relaxed);
+  }
+  if route.polarity == acpi::Polarity::ActiveLow {
+    ACTIVE_LOW.fetch_or(bit, Ordering::Relaxed);
+  } else {
+    ACTIVE_LOW.fetch_and(!bit, Ordering::Relaxed);
+  }
+
+  log::debug!("PCI interrupt line {} routed to GSI {} ({:?}, {:?})", irq, route.gsi, route.trigger, route.polarity);
+
+  without_interrupts(|| enable_route(&mut IOAPIC.lock(), route));
+  Ok(())
+}
+
+/// Stops polling the device when PCI interrupt line `irq` fires, masking it
+/// once no other device shares it.
+pub(crate) fn disable_device_irq(irq: u32, id: DeviceID) {
+  let gsi = acpi::pci_irq_route(irq).gsi;
+  let devices = match GSI_DEVICES.get(gsi as usize) {
+    Some(devices) => devices,
+    None => return
+  };
+
+  for device in devices.iter() {
+    let _ = device.compare_exchange(id.0 + 1, 0, Ordering::AcqRel, Ordering::Relaxed);
+  }
+
+  if routed_devices(gsi).next().is_none() {
+    without_interrupts(|| {
+      let mut ioapic = IOAPIC.lock();
+      AWAITING_POLL.fetch_and(!(1 << gsi), Ordering::Relaxed);
+      unsafe { ioapic.disable_irq(gsi as u8); }
+    });
+  }
+}
+
+/// Unmasks the level-triggered GSIs the device shares that were masked until
+/// it had been polled. If another device on them still wants attention,
+/// they fire again.
+pub(crate) fn device_polled(id: DeviceID) {
+  let awaiting = AWAITING_POLL.load(Ordering::Relaxed);
+  if awaiting == 0 {
+    return;
+  }
+
+  for gsi in 0..GSI_COUNT.load(Ordering::Relaxed) as u32 {
+    let bit = 1 << gsi;
+    if awaiting & bit == 0 || !routed_devices(gsi).any(|device| device == id) {
+      continue;
+    }
+
+    let route = acpi::IrqRoute {
+      gsi,
+      trigger: acpi::TriggerMode::Level,
+      polarity: if ACTIVE_LOW.load(Ordering::Relaxed) & bit != 0 { acpi::Polarity::ActiveLow } else { acpi::Polarity::ActiveHigh }
+    };
+
+    // Under the lock, so that masking and unmasking can't cross.
+    without_interrupts(|| {
+      let mut ioapic = IOAPIC.lock();
+      if AWAITING_POLL.fetch_and(!bit, Ordering::Relaxed) & bit != 0 {
+        enable_route(&mut ioapic, route);
+      }
+    });
+  }
+}
+
+/// Adds the device to those polled when `gsi` fires.
+fn route_gsi(gsi: u32, id: DeviceID) -> Result<(), X8664Error> {
+  if gsi as usize >= GSI_COUNT.load(Ordering::Relaxed) {
+    return Err(X8664Error::Unsupported("GSIs beyond the I/O APIC's inputs"));
+  }
+
+  let devices = &GSI_DEVICES[gsi as usize];
+  if devices.iter().any(|device| device.load(Ordering::Acquire) == id.0 + 1) {
+    return Ok(());
+  }
+
+  devices.iter()
+    .find(|device| device.compare_exchange(0, id.0 + 1, Ordering::AcqRel, Ordering::Relaxed).is_ok())
+    .map(|_| ())
+    .ok_or(X8664Error::Unsupported("more devices sharing an IRQ than can be told apart"))
+}
+
+fn routed_devices(gsi: u32) -> impl Iterator<Item = DeviceID> {
+  let devices: &'static [AtomicU32] = match GSI_DEVICES.get(gsi as usize) {
+    Some(devices) => devices,
+    None => &[]
+  };
+
+  devices.iter().filter_map(|device| match device.load(Ordering::Acquire) {
+    0 => None,
+    id => Some(DeviceID(id - 1))
+  })
+}
+
+/// A snapshot of the interrupt counters, listing only vectors that have fired.
+pub fn statistics() -> InterruptStatistics {
+  let mut vectors = Vec::new();
+
+  for vector in 0..VECTORS {
+    let per_cpu: Vec<u64> = VECTOR_COUNTS.iter()
+      .map(|counts| counts[vector].load(Ordering::Relaxed))
+      .collect();
+
+    let count: u64 = per_cpu.iter().sum();
+    if count == 0 {
+      continue;
+    }
+
+    vectors.push(VectorStatistics {
+      vector: vector as u8,
+      per_cpu,
+      handler: LatencyCounter {
+        count,
+        total: HANDLER_CYCLES[vector].load(Ordering::Relaxed),
+        max: HANDLER_MAX_CYCLES[vector].load(Ordering::Relaxed)
+      }
+    });
+  }
+
+  InterruptStatistics {
+    vectors,
+    spurious: SPURIOUS_COUNT.load(Ordering::Relaxed),
+    unknown: UNKNOWN_COUNT.load(Ordering::Relaxed)
+  }
+}
+
+/// Halts every CPU but this one. Only called while panicking. The others get
+/// an NMI, since the ones spinning on a lock we hold have interrupts off, and
+/// the NMI handler halts once a panic is under way. The ICR is written
+/// directly, so we don't wait for whoever holds the local APIC lock.
+pub(crate) fn stop_other_cpus() {
+  unsafe { Msr::new(X2APIC_ICR).write(ICR_NMI_ALL_EXCLUDING_SELF); }
 }
 
 fn init_local_apic() {
@@ -157,23 +425,53 @@ fn init_local_apic() {
   }
 }
 
-fn init_ioapic() {
-  unsafe {
-    let mut ioapic = IOAPIC.lock();
+/// Unmasks an ISA IRQ on whichever I/O APIC input the firmware wired it to,
+/// with the polarity and trigger mode the MADT gives for it.
+fn enable_isa_irq(ioapic: &mut IoApic, irq: u8) {
+  let route = acpi::isa_irq_route(irq);
+  log::debug!("ISA IRQ {} routed to GSI {} ({:?}, {:?})", irq, route.gsi, route.trigger, route.polarity);
+  enable_route(ioapic, route);
+}
 
-    ioapic.init(0x20);
+fn enable_route(ioapic: &mut IoApic, route: acpi::IrqRoute) {
+  let mut flags = IrqFlags::empty();
+  if route.trigger == acpi::TriggerMode::Level {
+    flags |= IrqFlags::LEVEL_TRIGGERED;
+  }
+  if route.polarity == acpi::Polarity::ActiveLow {
+    flags |= IrqFlags::LOW_ACTIVE;
+  }
 
-    ioapic.enable_irq(0,
+  unsafe {
+    ioapic.enable_irq(route.gsi as u8,
       0, // CPU(s)
       IrqMode::Fixed,
-      IrqFlags::LEVEL_TRIGGERED | IrqFlags::LOW_ACTIVE,
+      flags,
     );
+  }
+}
 
-    ioapic.enable_irq(1,
-      0, // CPU(s)
-      IrqMode::Fixed,
-      IrqFlags::empty(),
-    );
+fn init_ioapic() {
+  let mut ioapic = IOAPIC.lock();
+
+  unsafe { ioapic.init(IRQ_BASE); }
+
+  let inputs = unsafe { ioapic.max_table_entry() } as usize + 1;
+  if inputs > MAX_GSIS {
+    log::warn!("The I/O APIC has {} inputs, only the first {} are used", inputs, MAX_GSIS);
+  }
+  GSI_COUNT.store(inputs.min(MAX_GSIS), Ordering::Relaxed);
+
+  enable_isa_irq(&mut ioapic, pit::IRQ);
+  enable_isa_irq(&mut ioapic, pc_keyboard::IRQ);
+
+  if gdb::is_enabled() {
+    enable_isa_irq(&mut ioapic, serial::COM2_IRQ);
+  }
+
+  if let Some(sci) = acpi::sci_irq() {
+    enable_isa_irq(&mut ioapic, sci);
+    pci::hotplug::init();
   }
 }
 
@@ -191,7 +489,9 @@ pub fn init() {
     let mut pic2: Port<u8> = Port::new(0x21);
     pic2.write(0xff);
   }
-  
+
+  pit::init();
+
   IDT.load();
   x86_64::instructions::interrupts::enable();
 }
@@ -1,9 +1,11 @@
 #![feature(abi_x86_interrupt)]
+#![feature(asm)]
 #![feature(core_intrinsics)]
 #![feature(alloc_layout_extra)]
 #![feature(custom_inner_attributes)]
 #![feature(const_in_array_repeat_expressions)]
 #![feature(allocator_api)]
+#![feature(naked_functions)]
 #![no_std]
 
 extern crate alloc;
@@ -11,16 +13,28 @@ extern crate alloc;
 #[macro_use]
 extern crate lazy_static;
 
+mod acpi;
 mod boot_info;
+mod context;
+mod cpu;
 mod device;
 mod error;
 mod event_buffer;
 mod file;
+mod firmware;
+mod gdb;
+mod hal;
+mod idle;
 mod interrupts;
 #[macro_use] pub mod logging;
 mod memory;
+mod panic;
 
-use kernel::{Platform};
+use alloc::{sync::Arc, vec::Vec};
+use kernel::{
+  CommandLine, InterruptStatistics, MemoryRegion, MemoryStatistics, Platform, ThreadContext, ThreadEntry,
+  firmware::Firmware
+};
 use self::{
   device::{DeviceID, Device},
   error::X8664Error,
@@ -28,8 +42,14 @@ use self::{
 };
 
 type PlatformEvent = kernel::PlatformEvent::<X8664Platform>;
+type EventEnvelope = kernel::EventEnvelope::<X8664Platform>;
 
-pub use boot_info::{X8664BootInfo, X8664MemorySegment};
+pub use boot_info::{
+  BOOT_TEXT_SIZE, MAX_MEMORY_DESCRIPTORS, BootText, X8664BootInfo, X8664DisplayMode, X8664MemoryDescriptor,
+  X8664MemorySegment, X8664PixelFormat
+};
+pub use firmware::X8664Firmware;
+pub use idle::{CStateResidency, IdleMethod, IdleStatistics};
 
 #[derive(Clone)]
 pub struct X8664Platform {
@@ -38,6 +58,9 @@ pub struct X8664Platform {
 
 impl X8664Platform {
   pub fn early_init() {
+    cpu::init();
+    kernel::interrupts::init::<X8664Platform>();
+    kernel::panic::init::<X8664Platform>();
     logging::init();
   }
 
@@ -45,6 +68,15 @@ impl X8664Platform {
     Self { boot_info }
   }
 
+  /// How long the CPU has spent in each C-state while idle.
+  pub fn idle_statistics(&self) -> IdleStatistics {
+    idle::statistics()
+  }
+
+  fn init_firmware(&self) {
+    firmware::init(self.boot_info.firmware);
+  }
+
   fn init_allocator(&self) {
     let mut available_memory = 0;
 
@@ -57,11 +89,32 @@ impl X8664Platform {
 
   }
 
+  fn init_command_line(&self) {
+    boot_info::init_command_line(&self.boot_info);
+
+    let command_line = boot_info::command_line();
+    log::info!("Boot options: {}", command_line);
+
+    kernel::dmesg::configure(command_line);
+  }
+
+  fn init_acpi(&self) {
+    acpi::init(self.boot_info.rsdp_address);
+  }
+
   fn init_interrupts(&self) {
     interrupts::init();
     log::info!("Interrupts configured");
   }
 
+  fn init_debugger(&self) {
+    gdb::init();
+  }
+
+  fn init_idle(&self) {
+    idle::init();
+  }
+
   fn init_devices(&self) {
     device::discover();
   }
@@ -74,18 +127,131 @@ impl Platform for X8664Platform {
   type File = X8664File;
 
   fn init(&mut self) {   
+    boot_info::init_memory_map(&self.boot_info);
+    boot_info::init_display_mode(&self.boot_info);
+    self.init_firmware();
     self.init_allocator(); 
+    self.init_command_line();
+    self.init_acpi();
+    self.init_debugger();
     self.init_interrupts();
+    gdb::wait_if_asked();
+    self.init_idle();
     self.init_devices();
 
     log::info!("Done!");
   }
 
-  fn poll_event(&self) -> Option<PlatformEvent> {
+  fn poll_event(&self) -> Option<EventEnvelope> {
     event_buffer::poll_event()
   }
 
+  fn device(&self, id: DeviceID) -> Option<Arc<Device>> {
+    device::get(id)
+  }
+
   fn sleep(&self) {
-    x86_64::instructions::hlt()
+    idle::enter()
+  }
+
+  fn command_line(&self) -> &CommandLine {
+    boot_info::command_line()
+  }
+
+  fn register_pci_driver(&self, driver: kernel::PciDriver<Self>) {
+    device::pci::driver::register(driver);
+  }
+
+  fn device_polled(&self, id: DeviceID) {
+    interrupts::device_polled(id);
+  }
+
+  fn hal() -> kernel::hal::Hal<Self> {
+    hal::get()
+  }
+
+  fn firmware() -> Option<&'static dyn Firmware<Self>> {
+    firmware::get().map(|firmware| firmware as &'static dyn Firmware<Self>)
+  }
+
+  fn timestamp(&self) -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+  }
+
+  fn timestamp_frequency(&self) -> u64 {
+    device::pit::tsc_frequency()
+  }
+
+  fn event_overflows(&self) -> u64 {
+    event_buffer::overflows()
+  }
+
+  fn interrupt_statistics(&self) -> InterruptStatistics {
+    interrupts::statistics()
+  }
+
+  fn memory_statistics(&self) -> MemoryStatistics {
+    let (allocated, allocations, failures) = memory::statistics();
+    let total = self.boot_info.memory_map.iter().map(|segment| segment.length).sum();
+
+    MemoryStatistics { total, allocated, allocations, failures }
+  }
+
+  fn memory_map(&self) -> Vec<MemoryRegion> {
+    let mut regions: Vec<MemoryRegion> = boot_info::memory_regions().collect();
+    regions.sort_by_key(|region| region.start);
+    regions
+  }
+
+  fn init_thread_context(stack: &mut [u8], entry: ThreadEntry, argument: usize) -> ThreadContext {
+    context::init_thread_context(stack, entry, argument)
+  }
+
+  unsafe fn switch_thread(from: *mut ThreadContext, to: *const ThreadContext) {
+    context::switch_thread(from, to)
+  }
+
+  fn disable_interrupts() -> bool {
+    let enabled = x86_64::instructions::interrupts::are_enabled();
+    x86_64::instructions::interrupts::disable();
+    enabled
+  }
+
+  fn restore_interrupts(enabled: bool) {
+    if enabled {
+      x86_64::instructions::interrupts::enable();
+    }
+  }
+
+  fn current_cpu() -> usize {
+    cpu::current_id()
+  }
+
+  fn cpu_count() -> usize {
+    cpu::count()
+  }
+
+  fn stop_other_cpus() {
+    interrupts::stop_other_cpus()
+  }
+
+  fn panic_write(text: &str) {
+    logging::panic_print(text)
+  }
+
+  fn backtrace(frame: &mut dyn FnMut(usize)) {
+    panic::backtrace(frame)
+  }
+
+  fn delay(milliseconds: u64) {
+    panic::delay(milliseconds)
+  }
+
+  fn halt() -> ! {
+    panic::halt()
+  }
+
+  fn reboot() -> ! {
+    panic::reboot()
   }
 }
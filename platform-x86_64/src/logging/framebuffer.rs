@@ -0,0 +1,211 @@
+//! A text console drawn on a linear framebuffer, for when the display is in
+//! a graphics mode and the VGA text buffer isn't shown. Like the VGA console,
+//! text goes on the bottom line and everything scrolls up.
+//!
+//! It draws nothing until a display driver attaches a framebuffer, and stops
+//! when the driver detaches it. The framebuffer's geometry is kept in
+//! atomics rather than behind `CONSOLE`, so the panic handler can still draw
+//! when whatever panicked holds the lock.
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering}
+};
+use kernel::sync::{IrqSpinLock, LockClass};
+
+use crate::boot_info::{X8664DisplayMode, X8664PixelFormat};
+use super::font;
+
+/// Colours, as `0x00rrggbb`. That's how a BGR pixel reads as a `u32`; RGB
+/// pixels have red and blue swapped.
+const FOREGROUND: u32 = 0x00ff_ff00;
+const BACKGROUND: u32 = 0x0000_0000;
+const PANIC_FOREGROUND: u32 = 0x00ff_ffff;
+const PANIC_BACKGROUND: u32 = 0x00aa_0000;
+
+/// Drawn for characters the font doesn't have.
+const UNKNOWN: char = '?';
+
+static CONSOLE_CLASS: LockClass = LockClass::new("framebuffer console", 90);
+static CONSOLE: IrqSpinLock<Console> = IrqSpinLock::ranked(Console { column: 0 }, &CONSOLE_CLASS);
+
+/// The attached framebuffer, or 0 if there isn't one. The rest of the
+/// geometry is only meaningful while it's set.
+static ADDRESS: AtomicUsize = AtomicUsize::new(0);
+static WIDTH: AtomicUsize = AtomicUsize::new(0);
+static HEIGHT: AtomicUsize = AtomicUsize::new(0);
+static STRIDE: AtomicUsize = AtomicUsize::new(0);
+static RGB: AtomicBool = AtomicBool::new(false);
+
+static PANIC_SCREEN_CLEARED: AtomicBool = AtomicBool::new(false);
+static PANIC_POSITION: AtomicUsize = AtomicUsize::new(0);
+
+struct Console {
+    column: usize
+}
+
+/// Where to draw: 32-bit pixels, `stride` pixels from the start of one line
+/// to the start of the next.
+#[derive(Copy, Clone)]
+struct Surface {
+    pixels: *mut u32,
+    width: usize,
+    height: usize,
+    stride: usize,
+    rgb: bool
+}
+
+/// Starts drawing on the framebuffer at `address`, which is in `mode`,
+/// clearing the screen first. The framebuffer must stay mapped until
+/// `detach`.
+pub fn attach(address: usize, mode: X8664DisplayMode) {
+    let X8664DisplayMode { width, height, stride, pixel_format } = mode;
+    if address == 0 || width < font::WIDTH || height < font::HEIGHT || stride < width {
+        return;
+    }
+
+    let rgb = pixel_format == X8664PixelFormat::Rgb;
+    let mut console = CONSOLE.lock();
+    Surface { pixels: address as *mut u32, width, height, stride, rgb }.fill(0, height, BACKGROUND);
+    console.column = 0;
+
+    WIDTH.store(width, Ordering::Relaxed);
+    HEIGHT.store(height, Ordering::Relaxed);
+    STRIDE.store(stride, Ordering::Relaxed);
+    RGB.store(rgb, Ordering::Relaxed);
+    ADDRESS.store(address, Ordering::Release);
+}
+
+pub fn detach() {
+    let _console = CONSOLE.lock();
+    ADDRESS.store(0, Ordering::Release);
+}
+
+pub fn write(text: &str) {
+    let mut console = CONSOLE.lock();
+
+    if let Some(surface) = Surface::attached() {
+        for c in text.chars() {
+            console.write_char(surface, c);
+        }
+    }
+}
+
+/// Draws without taking `CONSOLE`, whose holder may have panicked. The first
+/// call clears the screen, and text runs down from the top so that the
+/// message isn't scrolled away.
+pub fn panic_print(text: &str) {
+    let surface = match Surface::attached() {
+        Some(surface) => surface,
+        None => return
+    };
+
+    if !PANIC_SCREEN_CLEARED.swap(true, Ordering::Relaxed) {
+        surface.fill(0, surface.height, PANIC_BACKGROUND);
+    }
+
+    let columns = surface.columns();
+    let size = columns * surface.rows();
+    let mut position = PANIC_POSITION.load(Ordering::Relaxed);
+
+    for c in text.chars() {
+        if position >= size {
+            break;
+        }
+
+        match c {
+            '\n' => position += columns - position % columns,
+            c => {
+                surface.draw(position / columns, position % columns, c, PANIC_FOREGROUND, PANIC_BACKGROUND);
+                position += 1;
+            }
+        }
+    }
+
+    PANIC_POSITION.store(position, Ordering::Relaxed);
+}
+
+impl Console {
+    fn write_char(&mut self, surface: Surface, c: char) {
+        match c {
+            '\n' => self.new_line(surface),
+            '\r' => self.column = 0,
+
+            c => {
+                if self.column >= surface.columns() {
+                    self.new_line(surface);
+                }
+
+                surface.draw(surface.rows() - 1, self.column, c, FOREGROUND, BACKGROUND);
+                self.column += 1;
+            }
+        }
+    }
+
+    /// Scrolls up a line of text and clears the bottom one.
+    fn new_line(&mut self, surface: Surface) {
+        let line_pixels = font::HEIGHT * surface.stride;
+        let rows = surface.rows();
+
+        unsafe { ptr::copy(surface.pixels.add(line_pixels), surface.pixels, (rows - 1) * line_pixels); }
+        surface.fill((rows - 1) * font::HEIGHT, rows * font::HEIGHT, BACKGROUND);
+        self.column = 0;
+    }
+}
+
+impl Surface {
+    fn attached() -> Option<Surface> {
+        match ADDRESS.load(Ordering::Acquire) {
+            0 => None,
+            address => Some(Surface {
+                pixels: address as *mut u32,
+                width: WIDTH.load(Ordering::Relaxed),
+                height: HEIGHT.load(Ordering::Relaxed),
+                stride: STRIDE.load(Ordering::Relaxed),
+                rgb: RGB.load(Ordering::Relaxed)
+            })
+        }
+    }
+
+    fn columns(&self) -> usize {
+        self.width / font::WIDTH
+    }
+
+    fn rows(&self) -> usize {
+        self.height / font::HEIGHT
+    }
+
+    /// `colour` as this surface's pixels have it.
+    fn pixel(&self, colour: u32) -> u32 {
+        if self.rgb {
+            (colour & 0x0000_ff00) | ((colour & 0xff) << 16) | ((colour >> 16) & 0xff)
+        } else {
+            colour
+        }
+    }
+
+    fn draw(&self, row: usize, column: usize, c: char, foreground: u32, background: u32) {
+        let (foreground, background) = (self.pixel(foreground), self.pixel(background));
+        let c = if c.is_ascii_graphic() || c == ' ' { c } else { UNKNOWN };
+        let glyph = &font::GLYPHS[(c as u8 - font::FIRST) as usize];
+
+        for (y, bits) in glyph.iter().enumerate() {
+            let line = (row * font::HEIGHT + y) * self.stride + column * font::WIDTH;
+
+            for x in 0..font::WIDTH {
+                let colour = if bits & (0x80 >> x) != 0 { foreground } else { background };
+                unsafe { ptr::write_volatile(self.pixels.add(line + x), colour); }
+            }
+        }
+    }
+
+    /// Paints pixel lines `start..end` with `colour`.
+    fn fill(&self, start: usize, end: usize, colour: u32) {
+        let colour = self.pixel(colour);
+        for y in start..end {
+            for x in 0..self.width {
+                unsafe { ptr::write_volatile(self.pixels.add(y * self.stride + x), colour); }
+            }
+        }
+    }
+}
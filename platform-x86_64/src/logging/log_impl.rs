@@ -1,22 +1,24 @@
-use crate::println;
-
 static LOGGER: X8664Logger = X8664Logger;
 pub fn init() {
+    super::sinks::init();
+
     log::set_logger(&LOGGER)
         .map(|()| log::set_max_level(log::LevelFilter::Trace))
         .unwrap();
 }
 
+/// Hands records to the kernel's log sinks. Which records get through is up
+/// to the log buffer's per-module filters.
 struct X8664Logger;
 
 impl log::Log for X8664Logger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= log::Level::Trace
+        kernel::dmesg::enabled(metadata)
     }
 
     fn log(&self, record: &log::Record) {
         if self.enabled(record.metadata()) {
-            println!("{} - {}", record.level(), record.args());
+            kernel::dmesg::record(record, unsafe { core::arch::x86_64::_rdtsc() });
         }
     }
 
@@ -1,7 +1,10 @@
+mod font;
+pub(crate) mod framebuffer;
 mod log_impl;
 #[macro_use] mod macros;
+mod sinks;
 mod vga;
 mod writer;
 
 pub use log_impl::init;
-pub use writer::print;
+pub use writer::{panic_print, print};
@@ -0,0 +1,42 @@
+//! The platform's log sinks: COM1, the VGA text buffer and the framebuffer
+//! console. All three are there from the first log line, though the
+//! framebuffer console draws nothing until a display driver gives it a
+//! framebuffer. They're called `serial`, `vga` and `framebuffer` for the
+//! `log.sink.<name>` boot options.
+
+use kernel::{dmesg::Record, log_sink::{self, LogFormat, LogSink}};
+use log::LevelFilter;
+
+use super::{framebuffer, writer};
+
+struct SerialSink;
+struct VgaSink;
+struct FramebufferSink;
+
+static SERIAL: SerialSink = SerialSink;
+static VGA: VgaSink = VgaSink;
+static FRAMEBUFFER: FramebufferSink = FramebufferSink;
+
+impl LogSink for SerialSink {
+    fn write(&self, _record: &Record, text: &str) {
+        writer::write_to_serial_out(text);
+    }
+}
+
+impl LogSink for VgaSink {
+    fn write(&self, _record: &Record, text: &str) {
+        writer::write_to_screen(text);
+    }
+}
+
+impl LogSink for FramebufferSink {
+    fn write(&self, _record: &Record, text: &str) {
+        framebuffer::write(text);
+    }
+}
+
+pub fn init() {
+    log_sink::register("serial", &SERIAL, LevelFilter::Trace, LogFormat::Plain);
+    log_sink::register("vga", &VGA, LevelFilter::Trace, LogFormat::Plain);
+    log_sink::register("framebuffer", &FRAMEBUFFER, LevelFilter::Trace, LogFormat::Plain);
+}
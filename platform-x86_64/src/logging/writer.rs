@@ -1,13 +1,17 @@
-use spin::Mutex;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use kernel::sync::{IrqSpinLock, LockClass};
 
-use super::vga;
+use super::{framebuffer, vga};
+
+/// Almost everything logs, so nothing else may be taken while this is held.
+static WRITER_CLASS: LockClass = LockClass::new("log writer", 90);
 
 lazy_static! {
-    static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+    static ref WRITER: IrqSpinLock<Writer> = IrqSpinLock::ranked(Writer {
         column_position: 0,
         colour_code: vga::ColourCode::new(vga::Colour::Yellow, vga::Colour::Black),
         buffer: unsafe { &mut *(0xb8000 as *mut vga::Buffer) },
-    });
+    }, &WRITER_CLASS);
 }
 
 pub struct Writer {
@@ -48,8 +52,6 @@ impl Writer {
 
 impl core::fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> core::result::Result<(), core::fmt::Error> { 
-        write_to_serial_out(s);
-
         for b in s.bytes() {
             match b {
                 0x20..=0x7e => self.write_byte(b),
@@ -61,12 +63,77 @@ impl core::fmt::Write for Writer {
     }
 }
 
+/// Prints to the serial port and the screen, bypassing the log sinks.
 pub fn print(args: core::fmt::Arguments) {
     use core::fmt::Write;
+
+    SerialOut.write_fmt(args).unwrap();
     WRITER.lock().write_fmt(args).unwrap();
 }
 
-fn write_to_serial_out(s: &str) {
+pub fn write_to_screen(s: &str) {
+    use core::fmt::Write;
+
+    WRITER.lock().write_str(s).unwrap();
+}
+
+struct SerialOut;
+
+impl core::fmt::Write for SerialOut {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        write_to_serial_out(s);
+        Ok(())
+    }
+}
+
+static PANIC_SCREEN_CLEARED: AtomicBool = AtomicBool::new(false);
+static PANIC_POSITION: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes straight to the serial port, the VGA buffer and any framebuffer
+/// console, without `WRITER`, whose holder may have panicked. The first call
+/// clears the screen, and text runs down from the top so that the message
+/// isn't scrolled away.
+pub fn panic_print(s: &str) {
+    write_to_serial_out(s);
+    framebuffer::panic_print(s);
+
+    let buffer = unsafe { &mut *(0xb8000 as *mut vga::Buffer) };
+    let colour_code = vga::ColourCode::new(vga::Colour::White, vga::Colour::Red);
+    let blank = vga::Char { ascii_character: b' ', colour_code };
+
+    if !PANIC_SCREEN_CLEARED.swap(true, Ordering::Relaxed) {
+        for row in buffer.chars.iter_mut() {
+            for c in row.iter_mut() {
+                *c = blank;
+            }
+        }
+    }
+
+    let size = vga::BUFFER_WIDTH * vga::BUFFER_HEIGHT;
+    let mut position = PANIC_POSITION.load(Ordering::Relaxed);
+
+    for b in s.bytes() {
+        if position >= size {
+            break;
+        }
+
+        match b {
+            b'\n' => position += vga::BUFFER_WIDTH - position % vga::BUFFER_WIDTH,
+            b => {
+                let ascii_character = match b {
+                    0x20..=0x7e => b,
+                    _ => 0x7e
+                };
+                buffer.chars[position / vga::BUFFER_WIDTH][position % vga::BUFFER_WIDTH] = vga::Char { ascii_character, colour_code };
+                position += 1;
+            }
+        }
+    }
+
+    PANIC_POSITION.store(position, Ordering::Relaxed);
+}
+
+pub fn write_to_serial_out(s: &str) {
     let mut stdout = x86_64::instructions::port::Port::new(0x3f8);
 
     for c in s.bytes() {
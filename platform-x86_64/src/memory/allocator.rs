@@ -1,11 +1,16 @@
 use linked_list_allocator::Heap;
-use spin::Mutex;
+use kernel::sync::{IrqSpinLock, LockClass};
 
-static FRAME_ALLOCATOR: Mutex<FrameAllocator> = Mutex::new(FrameAllocator {
+/// The allocator logs while locked, so this comes before the log writer.
+static FRAME_ALLOCATOR_CLASS: LockClass = LockClass::new("frame allocator", 80);
+
+static FRAME_ALLOCATOR: IrqSpinLock<FrameAllocator> = IrqSpinLock::ranked(FrameAllocator {
     heaps: [Heap::empty(); 50]
-});
+}, &FRAME_ALLOCATOR_CLASS);
 
-pub fn get() -> &'static Mutex<FrameAllocator> {
+/// The lock disables interrupts, so a thread can't be preempted in the middle
+/// of an allocation and leave the others spinning.
+pub fn get() -> &'static IrqSpinLock<FrameAllocator> {
     &FRAME_ALLOCATOR
 }
 
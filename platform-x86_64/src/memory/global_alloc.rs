@@ -1,20 +1,58 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use super::allocator;
 
 #[global_allocator]
 static GLOBAL_ALLOC: GlobalAllocator = GlobalAllocator;
 struct GlobalAllocator;
 
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static FAILURES: AtomicU64 = AtomicU64::new(0);
+
 unsafe impl alloc::alloc::GlobalAlloc for GlobalAllocator {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
         use core::alloc::Alloc;
-        allocator::get().lock().alloc(layout)
-            .expect("Failed to allocate memory")
-            .as_ptr()
+
+        // The allocator logs, and logging mustn't come back here, so its
+        // records are staged. The guard goes before the lock is released,
+        // while interrupts are still off.
+        let ptr = {
+            let mut allocator = allocator::get().lock();
+            let _deferred = kernel::dmesg::defer();
+            allocator.alloc(layout)
+        };
+
+        // Failures go back to the caller, which can cope or go to the
+        // allocation error handler.
+        match ptr {
+            Ok(ptr) => {
+                ALLOCATED.fetch_add(layout.size() as u64, Ordering::Relaxed);
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                ptr.as_ptr()
+            },
+            Err(_) => {
+                FAILURES.fetch_add(1, Ordering::Relaxed);
+                core::ptr::null_mut()
+            }
+        }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) { 
         use core::alloc::Alloc;
         let ptr = core::ptr::NonNull::new(ptr).expect("Tried to dealloc null pointer");
-        allocator::get().lock().dealloc(ptr, layout)
+        {
+            let mut allocator = allocator::get().lock();
+            let _deferred = kernel::dmesg::defer();
+            allocator.dealloc(ptr, layout);
+        }
+
+        ALLOCATED.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+        ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
     }
 }
+
+/// Bytes allocated, live allocations, and failed allocations.
+pub fn statistics() -> (u64, u64, u64) {
+    (ALLOCATED.load(Ordering::Relaxed), ALLOCATIONS.load(Ordering::Relaxed), FAILURES.load(Ordering::Relaxed))
+}
@@ -1,2 +1,4 @@
 pub mod allocator;
 mod global_alloc;
+
+pub use global_alloc::statistics;
@@ -0,0 +1,73 @@
+//! What the kernel's panic handler needs from us.
+
+use core::arch::x86_64::_rdtsc;
+use x86_64::instructions::port::Port;
+
+use crate::device::pit;
+
+/// Used to wait if the TSC was never calibrated.
+const FALLBACK_TSC_FREQUENCY: u64 = 1_000_000_000;
+
+/// Walks the chain of saved frame pointers. Each frame starts with the
+/// caller's frame pointer, followed by the return address.
+pub fn backtrace(frame: &mut dyn FnMut(usize)) {
+  let mut frame_pointer: usize;
+  unsafe { asm!("mov %rbp, $0" : "=r"(frame_pointer) ::: "volatile"); }
+
+  while frame_pointer != 0 && frame_pointer % 8 == 0 {
+    let (next, return_address) = unsafe {
+      let slots = frame_pointer as *const usize;
+      (*slots, *slots.add(1))
+    };
+
+    if return_address == 0 {
+      break;
+    }
+    frame(return_address);
+
+    // The stack grows down, so callers' frames are always higher up.
+    if next <= frame_pointer {
+      break;
+    }
+    frame_pointer = next;
+  }
+}
+
+pub fn delay(milliseconds: u64) {
+  let frequency = match pit::tsc_frequency() {
+    0 => FALLBACK_TSC_FREQUENCY,
+    frequency => frequency
+  };
+
+  let start = unsafe { _rdtsc() };
+  let cycles = frequency / 1000 * milliseconds;
+
+  while unsafe { _rdtsc() }.wrapping_sub(start) < cycles {
+    core::sync::atomic::spin_loop_hint();
+  }
+}
+
+pub fn halt() -> ! {
+  loop {
+    x86_64::instructions::interrupts::disable();
+    x86_64::instructions::hlt();
+  }
+}
+
+/// Pulses the reset line through the keyboard controller, then tries the
+/// PCI reset control register. If neither works there's nothing else to try.
+pub fn reboot() -> ! {
+  unsafe {
+    let mut keyboard_command: Port<u8> = Port::new(0x64);
+    keyboard_command.write(0xfe);
+  }
+  delay(100);
+
+  unsafe {
+    let mut reset_control: Port<u8> = Port::new(0xcf9);
+    reset_control.write(0x06);
+  }
+  delay(100);
+
+  halt()
+}
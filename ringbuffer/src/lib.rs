@@ -65,8 +65,9 @@ use spin::Mutex;
 /// // Put another item in.
 /// ringbuffer.push(6);
 ///
-/// // There's still five items.
+/// // There's still five items, and one was dropped to make room.
 /// assert_eq!(5, ringbuffer.size());
+/// assert_eq!(1, ringbuffer.overflows());
 ///
 /// // The first item is '2' -- the '1' was dropped -- and it continues from
 /// // there.
@@ -77,6 +78,18 @@ use spin::Mutex;
 /// assert_eq!(6, ringbuffer.pop());
 /// ```
 ///
+/// To merge a ring buffer with some other ordered source, `poll_if` only takes
+/// the next item if it passes a test:
+///
+/// ```
+/// # use ringbuffer::RingBuffer;
+/// let ringbuffer = RingBuffer::new_with_capacity(10);
+/// ringbuffer.push(3);
+///
+/// assert_eq!(None, ringbuffer.poll_if(|item| *item < 2));
+/// assert_eq!(Some(3), ringbuffer.poll_if(|item| *item < 4));
+/// ```
+///
 /// When you read too fast, it spins until data is available:
 ///
 /// ```
@@ -108,7 +121,8 @@ pub struct RingBuffer<Item: Clone> {
 struct RingBufferData<Item: Clone> {
   array: Vec<Item>,
   start: usize,
-  count: usize
+  count: usize,
+  overflows: usize
 }
 
 impl <Item: Clone> RingBuffer<Item> {
@@ -118,7 +132,8 @@ impl <Item: Clone> RingBuffer<Item> {
         Mutex::new(RingBufferData {
           array: Vec::with_capacity(capacity),
           start: 0,
-          count: 0
+          count: 0,
+          overflows: 0
         })
       )
     }
@@ -135,6 +150,12 @@ impl <Item: Clone> RingBuffer<Item> {
     self.mutex.lock().size()
   }
 
+  /// The number of items that have been dropped to make room for new ones.
+  #[inline]
+  pub fn overflows(&self) -> usize {
+    self.mutex.lock().overflows
+  }
+
   pub fn push(&self, item: Item) {
     let mut inner = self.mutex.lock();
     inner.push(item)
@@ -145,6 +166,12 @@ impl <Item: Clone> RingBuffer<Item> {
     inner.poll()
   }
 
+  /// Removes and returns the next item only if `predicate` accepts it.
+  pub fn poll_if<F: FnOnce(&Item) -> bool>(&self, predicate: F) -> Option<Item> {
+    let mut inner = self.mutex.lock();
+    inner.poll_if(predicate)
+  }
+
   pub fn pop(&self) -> Item {
     loop {
       match self.poll() {
@@ -206,11 +233,20 @@ impl <Item: Clone> RingBufferData<Item> {
 
     if self.is_full() {
       self.start = self.wrap_index(self.start + 1);
+      self.overflows += 1;
     } else {
       self.count += 1;
     }
   }
 
+  pub fn poll_if<F: FnOnce(&Item) -> bool>(&mut self, predicate: F) -> Option<Item> {
+    if self.is_empty() || !predicate(&self.array[self.read_position()]) {
+      None
+    } else {
+      self.poll()
+    }
+  }
+
   pub fn poll(&mut self) -> Option<Item> {
     if self.is_empty() {
       None
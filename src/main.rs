@@ -5,15 +5,36 @@ extern crate alloc;
 
 use uefi::{
   prelude::*,
-  table::boot::MemoryType
+  proto::{
+    console::gop::{GraphicsOutput, PixelFormat},
+    loaded_image::LoadedImage,
+    media::{
+      file::{File, FileAttribute, FileMode, FileType},
+      fs::SimpleFileSystem
+    }
+  },
+  table::{
+    boot::{BootServices, MemoryType},
+    cfg::{ACPI_GUID, ACPI2_GUID}
+  }
 };
 use kernel::Kernel;
 use platform_x86_64::{
+  BOOT_TEXT_SIZE,
+  MAX_MEMORY_DESCRIPTORS,
+  BootText,
+  X8664Firmware,
   X8664Platform,
   X8664BootInfo,
-  X8664MemorySegment
+  X8664DisplayMode,
+  X8664MemoryDescriptor,
+  X8664MemorySegment,
+  X8664PixelFormat
 };
 
+/// Boot options, one per line, read from the ESP the kernel was loaded from.
+const CONFIG_FILE: &str = "EFI\\BOOT\\kernel.cfg";
+
 #[no_mangle]
 pub extern "win64" fn uefi_start(image: uefi::Handle, system_table: SystemTable<Boot>) -> ! {
   X8664Platform::early_init();
@@ -26,6 +47,24 @@ pub extern "win64" fn uefi_start(image: uefi::Handle, system_table: SystemTable<
     log::info!("Booted by UEFI {}.{}", major, minor);
   }
 
+  // Find the ACPI tables while the configuration table is still around,
+  // preferring the ACPI 2.0 RSDP.
+  let rsdp_address = {
+    let config_table = system_table.config_table();
+    let find = |guid| config_table.iter()
+      .find(|entry| entry.guid == guid)
+      .map(|entry| entry.address as u64);
+
+    find(ACPI2_GUID).or_else(|| find(ACPI_GUID))
+  };
+
+  // The files and load options are gone once we exit boot services.
+  let load_options = read_load_options(image, system_table.boot_services());
+  let config_file = read_config_file(image, system_table.boot_services());
+
+  // So is the graphics output, which knows what mode the display is in.
+  let display_mode = read_display_mode(system_table.boot_services());
+
   const MAX_MMAP_SIZE: usize = 103680;
   let estimated_mmap_size = system_table.boot_services().memory_map_size();
 
@@ -34,18 +73,32 @@ pub extern "win64" fn uefi_start(image: uefi::Handle, system_table: SystemTable<
   }
 
   let mut uefi_mmap_storage = [0; MAX_MMAP_SIZE];
-  let (_system_table, uefi_memory_map_iter) = system_table
+  let (system_table, uefi_memory_map_iter) = system_table
     .exit_boot_services(image, &mut uefi_mmap_storage[..])
     .expect_success("Failed to exit boot services");
 
   let mut memory_map = [X8664MemorySegment { start_address: 0, length: 0}; 100];
   let mut memory_map_segments = 0;
 
+  let empty_descriptor = X8664MemoryDescriptor { start_address: 0, length: 0, memory_type: 0 };
+  let mut firmware_memory_map = [empty_descriptor; MAX_MEMORY_DESCRIPTORS];
+  let mut firmware_memory_map_length = 0;
+  let mut firmware_memory_map_truncated = false;
+
   for descriptor in uefi_memory_map_iter {
     let size = descriptor.page_count * 0x1000;
     let start_address = descriptor.phys_start;
     let descriptor_type = descriptor.ty;
 
+    if firmware_memory_map_length < MAX_MEMORY_DESCRIPTORS {
+      firmware_memory_map[firmware_memory_map_length] = X8664MemoryDescriptor {
+        start_address, length: size, memory_type: descriptor_type.0
+      };
+      firmware_memory_map_length += 1;
+    } else {
+      firmware_memory_map_truncated = true;
+    }
+
     match descriptor_type {
       MemoryType::CONVENTIONAL | MemoryType::BOOT_SERVICES_CODE | MemoryType::BOOT_SERVICES_DATA => {
         memory_map[memory_map_segments] = X8664MemorySegment {
@@ -59,6 +112,107 @@ pub extern "win64" fn uefi_start(image: uefi::Handle, system_table: SystemTable<
 
   }
 
-  let boot_info = X8664BootInfo { memory_map };
+  if firmware_memory_map_truncated {
+    log::warn!("The memory map has more than {} entries, the rest aren't kept", MAX_MEMORY_DESCRIPTORS);
+  }
+
+  // The runtime services' own regions are left out of the heap, and memory
+  // stays identity mapped, so the firmware can still be called.
+  let firmware = X8664Firmware::new(system_table);
+
+  let boot_info = X8664BootInfo {
+    memory_map,
+    firmware_memory_map,
+    firmware_memory_map_length,
+    rsdp_address,
+    display_mode,
+    load_options,
+    config_file,
+    firmware
+  };
   Kernel::new(X8664Platform::new(boot_info)).start()
+}
+
+fn read_load_options(image: Handle, boot_services: &BootServices) -> BootText {
+  let loaded_image = match boot_services.handle_protocol::<LoadedImage>(image).log_warning() {
+    Ok(loaded_image) => unsafe { &*loaded_image.get() },
+    Err(error) => {
+      log::warn!("Couldn't open the loaded image to read its options: {:?}", error.status());
+      return BootText::empty();
+    }
+  };
+
+  let mut buffer = [0u8; BOOT_TEXT_SIZE];
+  match loaded_image.load_options(&mut buffer) {
+    Ok(options) => BootText::from_bytes(options.as_bytes()),
+    Err(_) => {
+      log::warn!("Load options are longer than {} bytes, ignoring them", BOOT_TEXT_SIZE);
+      BootText::empty()
+    }
+  }
+}
+
+fn read_display_mode(boot_services: &BootServices) -> Option<X8664DisplayMode> {
+  let graphics_output = match boot_services.locate_protocol::<GraphicsOutput>().log_warning() {
+    Ok(graphics_output) => unsafe { &*graphics_output.get() },
+    Err(error) => {
+      log::info!("No graphics output to read the display mode from ({:?})", error.status());
+      return None;
+    }
+  };
+
+  let mode = graphics_output.current_mode_info();
+  let (width, height) = mode.resolution();
+  let pixel_format = match mode.pixel_format() {
+    PixelFormat::RGB => X8664PixelFormat::Rgb,
+    PixelFormat::BGR => X8664PixelFormat::Bgr,
+    other => {
+      log::warn!("The display's pixel format ({:?}) isn't supported, so it won't be drawn on", other);
+      return None;
+    }
+  };
+
+  Some(X8664DisplayMode { width, height, stride: mode.stride(), pixel_format })
+}
+
+fn read_config_file(image: Handle, boot_services: &BootServices) -> BootText {
+  let mut buffer = [0u8; BOOT_TEXT_SIZE];
+
+  match read_file(image, boot_services, CONFIG_FILE, &mut buffer) {
+    Ok(length) => {
+      if length == buffer.len() {
+        log::warn!("{} is longer than {} bytes, the rest is ignored", CONFIG_FILE, BOOT_TEXT_SIZE);
+      }
+      BootText::from_bytes(&buffer[..length])
+    },
+    Err(status) => {
+      log::info!("No boot configuration read from {} ({:?})", CONFIG_FILE, status);
+      BootText::empty()
+    }
+  }
+}
+
+/// Reads the start of a file on the volume the image was loaded from.
+fn read_file(image: Handle, boot_services: &BootServices, path: &str, buffer: &mut [u8]) -> Result<usize, Status> {
+  let loaded_image = boot_services.handle_protocol::<LoadedImage>(image)
+    .log_warning()
+    .map_err(|error| error.status())?;
+  let device = unsafe { &*loaded_image.get() }.device();
+
+  let filesystem = boot_services.handle_protocol::<SimpleFileSystem>(device)
+    .log_warning()
+    .map_err(|error| error.status())?;
+  let filesystem = unsafe { &mut *filesystem.get() };
+
+  let mut root = filesystem.open_volume()
+    .log_warning()
+    .map_err(|error| error.status())?;
+  let file = root.open(path, FileMode::Read, FileAttribute::empty())
+    .log_warning()
+    .map_err(|error| error.status())?;
+
+  match file.into_type().log_warning().map_err(|error| error.status())? {
+    FileType::Regular(mut file) => file.read(buffer).log_warning().map_err(|error| error.status()),
+    FileType::Dir(_) => Err(Status::INVALID_PARAMETER)
+  }
 }
\ No newline at end of file